@@ -0,0 +1,813 @@
+// Document Model
+// Rope-backed text storage shared by the editor and the renderers
+// Responsibilities:
+// - O(log n) inserts and deletes at char positions
+// - Conversions between byte, char, UTF-16 and line indices
+// - Find extended grapheme cluster boundaries so edits never split a user-perceived character
+// - Find the word and line around a position for double- and triple-click selection
+// - Find word and subword (camelCase, snake_case) boundaries for word-wise motion
+// - Cheap immutable snapshots for rendering and diffing
+// - Remember recent edits so caches built for an earlier revision can catch up
+
+use std::fmt;
+use std::ops::{Deref, Range};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use ropey::{Rope, RopeSlice};
+use unicode_segmentation::{GraphemeCursor, GraphemeIncomplete, UnicodeSegmentation};
+
+// Revisions come from one counter shared by every document, so an edited copy of a
+// document (e.g. one showing uncommitted IME text) never reports the revision of another
+static NEXT_REVISION: AtomicU64 = AtomicU64::new(1);
+
+fn next_revision() -> u64 {
+    NEXT_REVISION.fetch_add(1, Ordering::Relaxed)
+}
+
+// Edits a document remembers; caches further behind than this diff the text instead
+const MAX_CHANGES: usize = 64;
+
+// `start..old_end` in the text before an edit became `start..new_end` after it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Change {
+    pub start: usize,
+    pub old_end: usize,
+    pub new_end: usize,
+}
+
+impl Change {
+    // Where a position in the previous text ended up; positions inside the replaced
+    // span move to its end
+    pub fn map(self, pos: usize) -> usize {
+        if pos >= self.old_end {
+            pos - self.old_end + self.new_end
+        } else if pos > self.start {
+            self.new_end
+        } else {
+            pos
+        }
+    }
+
+    // Combine with a change made after this one (in post-change coordinates)
+    pub fn merge(self, next: Change) -> Change {
+        // Map a position in the intermediate text back to the original
+        let back = |pos: usize| {
+            if pos >= self.new_end {
+                pos - self.new_end + self.old_end
+            } else if pos > self.start {
+                self.old_end
+            } else {
+                pos
+            }
+        };
+        Change {
+            start: self.start.min(next.start),
+            old_end: self.old_end.max(back(next.old_end)),
+            new_end: next.new_end.max(next.map(self.new_end)),
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct Document {
+    rope: Rope,
+    revision: u64,
+    // Latest edits with the revision each was made to, oldest first. Shared with
+    // snapshots until the next edit.
+    changes: Arc<Vec<(u64, Change)>>,
+}
+
+// Immutable view of a document at a given revision.
+// Cloning the underlying rope only bumps a reference count, so snapshots
+// can be handed to the render path on every keystroke.
+#[derive(Clone, Default)]
+pub struct DocumentSnapshot(Document);
+
+impl Deref for DocumentSnapshot {
+    type Target = Document;
+
+    fn deref(&self) -> &Document {
+        &self.0
+    }
+}
+
+impl Document {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn snapshot(&self) -> DocumentSnapshot {
+        DocumentSnapshot(self.clone())
+    }
+
+    // Bumped on every mutation so caches can cheaply detect stale state
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    pub fn len_chars(&self) -> usize {
+        self.rope.len_chars()
+    }
+
+    pub fn len_bytes(&self) -> usize {
+        self.rope.len_bytes()
+    }
+
+    pub fn len_lines(&self) -> usize {
+        self.rope.len_lines()
+    }
+
+    // Editing operations - positions are char indices and clamped to the document
+    pub fn insert(&mut self, char_idx: usize, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        let char_idx = char_idx.min(self.len_chars());
+        self.rope.insert(char_idx, text);
+        self.record(Change {
+            start: char_idx,
+            old_end: char_idx,
+            new_end: char_idx + text.chars().count(),
+        });
+    }
+
+    pub fn remove(&mut self, range: Range<usize>) {
+        let range = self.clamp_range(range);
+        if range.is_empty() {
+            return;
+        }
+        self.rope.remove(range.clone());
+        self.record(Change {
+            start: range.start,
+            old_end: range.end,
+            new_end: range.start,
+        });
+    }
+
+    pub fn replace(&mut self, range: Range<usize>, text: &str) {
+        let range = self.clamp_range(range);
+        let start = range.start;
+        self.remove(range);
+        self.insert(start, text);
+    }
+
+    // Move to a new revision, logging the edit that led there
+    fn record(&mut self, change: Change) {
+        let changes = Arc::make_mut(&mut self.changes);
+        if changes.len() == MAX_CHANGES {
+            changes.remove(0);
+        }
+        changes.push((self.revision, change));
+        self.revision = next_revision();
+    }
+
+    // Edits that turn `old` into this document, oldest first. An earlier revision of this
+    // document replays the edit log; anything else is diffed by common prefix and suffix,
+    // so an insertion in the middle only reports the inserted span.
+    pub fn changes_since(&self, old: &Document) -> Vec<Change> {
+        if old.revision == self.revision {
+            return Vec::new();
+        }
+        if let Some(first) = self.changes.iter().position(|&(revision, _)| revision == old.revision) {
+            return self.changes[first..].iter().map(|&(_, change)| change).collect();
+        }
+
+        let (old_len, new_len) = (old.len_chars(), self.len_chars());
+        let prefix = old.chars()
+            .zip(self.chars())
+            .take_while(|(old, new)| old == new)
+            .count();
+        if prefix == old_len && prefix == new_len {
+            return Vec::new();
+        }
+        let max_suffix = (old_len - prefix).min(new_len - prefix);
+        let suffix = old.chars_at(old_len).reversed()
+            .zip(self.chars_at(new_len).reversed())
+            .take(max_suffix)
+            .take_while(|(old, new)| old == new)
+            .count();
+        vec![Change {
+            start: prefix,
+            old_end: old_len - suffix,
+            new_end: new_len - suffix,
+        }]
+    }
+
+    // Index conversions
+    pub fn char_to_byte(&self, char_idx: usize) -> usize {
+        self.rope.char_to_byte(char_idx.min(self.len_chars()))
+    }
+
+    pub fn byte_to_char(&self, byte_idx: usize) -> usize {
+        self.rope.byte_to_char(byte_idx.min(self.len_bytes()))
+    }
+
+    // UTF-16 code units are what the DOM counts in (textarea selection offsets, input
+    // events). An offset inside a surrogate pair maps to the char it belongs to.
+    pub fn len_utf16(&self) -> usize {
+        self.rope.len_utf16_cu()
+    }
+
+    pub fn char_to_utf16(&self, char_idx: usize) -> usize {
+        self.rope.char_to_utf16_cu(char_idx.min(self.len_chars()))
+    }
+
+    pub fn utf16_to_char(&self, utf16_idx: usize) -> usize {
+        self.rope.utf16_cu_to_char(utf16_idx.min(self.len_utf16()))
+    }
+
+    // Caret position for a DOM offset: one inside a surrogate pair or a grapheme cluster
+    // (an emoji modifier, the second half of a flag) moves to the cluster's start
+    pub fn utf16_to_caret(&self, utf16_idx: usize) -> usize {
+        self.snap_to_grapheme(self.utf16_to_char(utf16_idx))
+    }
+
+    pub fn byte_to_utf16(&self, byte_idx: usize) -> usize {
+        self.char_to_utf16(self.byte_to_char(byte_idx))
+    }
+
+    pub fn utf16_to_byte(&self, utf16_idx: usize) -> usize {
+        self.char_to_byte(self.utf16_to_char(utf16_idx))
+    }
+
+    pub fn char_to_line(&self, char_idx: usize) -> usize {
+        self.rope.char_to_line(char_idx.min(self.len_chars()))
+    }
+
+    pub fn line_to_char(&self, line_idx: usize) -> usize {
+        self.rope.line_to_char(line_idx.min(self.len_lines()))
+    }
+
+    // Grapheme boundaries. The cursor walks the rope chunk by chunk, asking for more
+    // context when a cluster (e.g. a flag or an emoji ZWJ sequence) spans chunks.
+    pub fn prev_grapheme_boundary(&self, char_idx: usize) -> usize {
+        let byte_idx = self.char_to_byte(char_idx);
+        let (mut chunk, mut chunk_start, _, _) = self.rope.chunk_at_byte(byte_idx);
+        let mut cursor = GraphemeCursor::new(byte_idx, self.len_bytes(), true);
+        loop {
+            match cursor.prev_boundary(chunk, chunk_start) {
+                Ok(None) => return 0,
+                Ok(Some(boundary)) => return self.byte_to_char(boundary),
+                Err(GraphemeIncomplete::PrevChunk) => {
+                    (chunk, chunk_start, _, _) = self.rope.chunk_at_byte(chunk_start - 1);
+                }
+                Err(GraphemeIncomplete::PreContext(end)) => {
+                    let context = self.rope.chunk_at_byte(end - 1).0;
+                    cursor.provide_context(context, end - context.len());
+                }
+                Err(_) => return char_idx.saturating_sub(1),
+            }
+        }
+    }
+
+    pub fn next_grapheme_boundary(&self, char_idx: usize) -> usize {
+        let byte_idx = self.char_to_byte(char_idx);
+        let (mut chunk, mut chunk_start, _, _) = self.rope.chunk_at_byte(byte_idx);
+        let mut cursor = GraphemeCursor::new(byte_idx, self.len_bytes(), true);
+        loop {
+            match cursor.next_boundary(chunk, chunk_start) {
+                Ok(None) => return self.len_chars(),
+                Ok(Some(boundary)) => return self.byte_to_char(boundary),
+                Err(GraphemeIncomplete::NextChunk) => {
+                    chunk_start += chunk.len();
+                    chunk = self.rope.chunk_at_byte(chunk_start).0;
+                }
+                Err(GraphemeIncomplete::PreContext(end)) => {
+                    let context = self.rope.chunk_at_byte(end - 1).0;
+                    cursor.provide_context(context, end - context.len());
+                }
+                Err(_) => return (char_idx + 1).min(self.len_chars()),
+            }
+        }
+    }
+
+    pub fn is_grapheme_boundary(&self, char_idx: usize) -> bool {
+        let byte_idx = self.char_to_byte(char_idx);
+        let (chunk, chunk_start, _, _) = self.rope.chunk_at_byte(byte_idx);
+        let mut cursor = GraphemeCursor::new(byte_idx, self.len_bytes(), true);
+        loop {
+            match cursor.is_boundary(chunk, chunk_start) {
+                Ok(is_boundary) => return is_boundary,
+                Err(GraphemeIncomplete::PreContext(end)) => {
+                    let context = self.rope.chunk_at_byte(end - 1).0;
+                    cursor.provide_context(context, end - context.len());
+                }
+                Err(_) => return true,
+            }
+        }
+    }
+
+    // The cluster Backspace removes from a caret at `char_idx`
+    pub fn cluster_before(&self, char_idx: usize) -> Range<usize> {
+        self.prev_grapheme_boundary(char_idx)..char_idx.min(self.len_chars())
+    }
+
+    // The cluster forward Delete removes
+    pub fn cluster_after(&self, char_idx: usize) -> Range<usize> {
+        char_idx.min(self.len_chars())..self.next_grapheme_boundary(char_idx)
+    }
+
+    // Move an index that falls inside a grapheme cluster back to the cluster's start
+    pub fn snap_to_grapheme(&self, char_idx: usize) -> usize {
+        let char_idx = char_idx.min(self.len_chars());
+        if self.is_grapheme_boundary(char_idx) {
+            char_idx
+        } else {
+            self.prev_grapheme_boundary(char_idx)
+        }
+    }
+
+    // Word around a char, split the way unicode-segmentation splits words, so a run of
+    // spaces or punctuation counts as one "word". At the end of a line the last word is used.
+    pub fn word_range_at(&self, char_idx: usize) -> Range<usize> {
+        let line_idx = self.char_to_line(char_idx);
+        let line_start = self.line_to_char(line_idx);
+        let text = self.line_text(line_idx);
+        let offset = char_idx.min(self.len_chars()) - line_start;
+
+        let mut word = offset..offset;
+        let mut start = 0;
+        for segment in text.split_word_bounds() {
+            let end = start + segment.chars().count();
+            word = start..end;
+            if offset < end {
+                break;
+            }
+            start = end;
+        }
+        line_start + word.start..line_start + word.end
+    }
+
+    // A whole line, its line break included
+    pub fn line_range(&self, line_idx: usize) -> Range<usize> {
+        self.line_to_char(line_idx)..self.line_to_char(line_idx + 1)
+    }
+
+    // Word-wise motion (Ctrl+Right): the end of the next word, skipping the spaces and
+    // punctuation before it. With `subword`, parts of camelCase and snake_case identifiers
+    // count as words. Past the last word of a line the line end is the stop, and from the
+    // line end the motion continues onto the next line.
+    pub fn next_word_end(&self, char_idx: usize, subword: bool) -> usize {
+        let char_idx = char_idx.min(self.len_chars());
+        let line_idx = self.char_to_line(char_idx);
+        let line_start = self.line_to_char(line_idx);
+        let line_len = self.line_len_chars(line_idx);
+        let offset = char_idx - line_start;
+
+        if offset >= line_len {
+            return if line_idx + 1 < self.len_lines() { self.line_to_char(line_idx + 1) } else { char_idx };
+        }
+        let end = word_ranges(&self.line_text(line_idx), subword)
+            .into_iter()
+            .find(|word| word.end > offset)
+            .map(|word| word.end)
+            .unwrap_or(line_len);
+        line_start + end
+    }
+
+    // Ctrl+Left: the start of the previous word, mirroring `next_word_end`
+    pub fn prev_word_start(&self, char_idx: usize, subword: bool) -> usize {
+        let char_idx = char_idx.min(self.len_chars());
+        let line_idx = self.char_to_line(char_idx);
+        let line_start = self.line_to_char(line_idx);
+        let offset = char_idx - line_start;
+
+        if offset == 0 {
+            return match line_idx.checked_sub(1) {
+                Some(prev) => self.line_to_char(prev) + self.line_len_chars(prev),
+                None => 0,
+            };
+        }
+        let start = word_ranges(&self.line_text(line_idx), subword)
+            .into_iter()
+            .rev()
+            .find(|word| word.start < offset)
+            .map(|word| word.start)
+            .unwrap_or(0);
+        line_start + start
+    }
+
+    // Text of a line without its line break
+    fn line_text(&self, line_idx: usize) -> String {
+        self.line(line_idx).chars().take(self.line_len_chars(line_idx)).collect()
+    }
+
+    // Read access
+    pub fn chars(&self) -> ropey::iter::Chars<'_> {
+        self.rope.chars()
+    }
+
+    // Iterator positioned at `char_idx`; call `.reversed()` to walk backwards
+    pub fn chars_at(&self, char_idx: usize) -> ropey::iter::Chars<'_> {
+        self.rope.chars_at(char_idx.min(self.len_chars()))
+    }
+
+    pub fn line(&self, line_idx: usize) -> RopeSlice<'_> {
+        self.rope.line(line_idx)
+    }
+
+    // Line length in chars, not counting the trailing line break
+    pub fn line_len_chars(&self, line_idx: usize) -> usize {
+        let line = self.rope.line(line_idx);
+        let mut len = line.len_chars();
+        if len > 0 && is_line_break(line.char(len - 1)) {
+            len -= 1;
+            if len > 0 && line.char(len) == '\n' && line.char(len - 1) == '\r' {
+                len -= 1;
+            }
+        }
+        len
+    }
+
+    pub fn slice(&self, range: Range<usize>) -> RopeSlice<'_> {
+        let range = self.clamp_range(range);
+        self.rope.slice(range)
+    }
+
+    fn clamp_range(&self, range: Range<usize>) -> Range<usize> {
+        let len = self.len_chars();
+        let end = range.end.min(len);
+        range.start.min(end)..end
+    }
+}
+
+// Char ranges of the words in a line: word segments with at least one letter or digit,
+// optionally cut further into subwords. Unicode keeps "foo.bar", "a:b" and "3.14" in one
+// segment; in code that punctuation separates words, so segments are cut at it.
+fn word_ranges(text: &str, subword: bool) -> Vec<Range<usize>> {
+    let mut words = Vec::new();
+    let mut start = 0;
+    for segment in text.split_word_bounds() {
+        if segment.chars().any(char::is_alphanumeric) {
+            let mut piece_start = start;
+            for piece in segment.split(|ch: char| ch.is_ascii_punctuation() && ch != '_') {
+                let len = piece.chars().count();
+                if piece.chars().any(char::is_alphanumeric) {
+                    if subword {
+                        push_subwords(piece, piece_start, &mut words);
+                    } else {
+                        words.push(piece_start..piece_start + len);
+                    }
+                }
+                // Past the piece and the punctuation char that ended it
+                piece_start += len + 1;
+            }
+        }
+        start += segment.chars().count();
+    }
+    words
+}
+
+// Split an identifier at underscores and at case changes: "parseHTTPResponse_code"
+// becomes "parse", "HTTP", "Response" and "code", and "utf8Decoder" "utf8" and "Decoder"
+fn push_subwords(word: &str, start: usize, words: &mut Vec<Range<usize>>) {
+    let chars: Vec<char> = word.chars().collect();
+    let mut piece: Option<usize> = None;
+    for (idx, &ch) in chars.iter().enumerate() {
+        if ch == '_' {
+            if let Some(piece_start) = piece.take() {
+                words.push(start + piece_start..start + idx);
+            }
+            continue;
+        }
+        if let Some(piece_start) = piece
+            && idx > piece_start
+        {
+            let prev = chars[idx - 1];
+            let camel = (prev.is_lowercase() || prev.is_numeric()) && ch.is_uppercase();
+            let acronym_end = prev.is_uppercase() && ch.is_uppercase()
+                && chars.get(idx + 1).is_some_and(|next| next.is_lowercase());
+            if camel || acronym_end {
+                words.push(start + piece_start..start + idx);
+                piece = Some(idx);
+            }
+        }
+        piece.get_or_insert(idx);
+    }
+    if let Some(piece_start) = piece {
+        words.push(start + piece_start..start + chars.len());
+    }
+}
+
+// Chars ropey treats as line breaks
+pub fn is_line_break(ch: char) -> bool {
+    matches!(ch, '\n' | '\r' | '\u{000B}' | '\u{000C}' | '\u{0085}' | '\u{2028}' | '\u{2029}')
+}
+
+impl From<&str> for Document {
+    fn from(text: &str) -> Self {
+        Self {
+            rope: Rope::from_str(text),
+            revision: next_revision(),
+            changes: Arc::default(),
+        }
+    }
+}
+
+impl fmt::Display for Document {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for chunk in self.rope.chunks() {
+            f.write_str(chunk)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    // Thumbs up with a skin tone: two astral chars, four UTF-16 units, one cluster
+    const THUMBS_UP: &str = "\u{1F44D}\u{1F3FD}";
+    // Japan and France flags: two regional indicators each
+    const FLAGS: &str = "\u{1F1EF}\u{1F1F5}\u{1F1EB}\u{1F1F7}";
+
+    // Pieces the property test builds text from: combining marks (with and without a
+    // base), ZWJ sequences, modifiers, flags and every line break pairing
+    const PIECES: &[&str] = &[
+        "a",
+        "e\u{301}",
+        "x\u{308}\u{323}",
+        "\u{301}",
+        THUMBS_UP,
+        "\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}",
+        "\u{200D}",
+        "\u{1F1EF}",
+        "\u{1F1EF}\u{1F1F5}",
+        "\r\n",
+        "\r",
+        "\n",
+        " ",
+    ];
+
+    #[derive(Debug, Clone)]
+    enum Op {
+        Insert(usize, usize),
+        Backspace(usize),
+        Delete(usize),
+    }
+
+    fn op() -> impl Strategy<Value = Op> {
+        prop_oneof![
+            (0..PIECES.len(), any::<usize>()).prop_map(|(piece, at)| Op::Insert(piece, at)),
+            any::<usize>().prop_map(Op::Backspace),
+            any::<usize>().prop_map(Op::Delete),
+        ]
+    }
+
+    // Char offsets of every cluster boundary, by segmenting the whole string at once
+    fn reference_boundaries(text: &str) -> Vec<usize> {
+        let mut boundaries = vec![0];
+        let mut offset = 0;
+        for cluster in text.graphemes(true) {
+            offset += cluster.chars().count();
+            boundaries.push(offset);
+        }
+        boundaries
+    }
+
+    fn forward_boundaries(document: &Document) -> Vec<usize> {
+        let mut boundaries = vec![0];
+        let mut idx = 0;
+        while idx < document.len_chars() {
+            idx = document.next_grapheme_boundary(idx);
+            boundaries.push(idx);
+        }
+        boundaries
+    }
+
+    fn backward_boundaries(document: &Document) -> Vec<usize> {
+        let mut boundaries = vec![document.len_chars()];
+        let mut idx = document.len_chars();
+        while idx > 0 {
+            idx = document.prev_grapheme_boundary(idx);
+            boundaries.push(idx);
+        }
+        boundaries.reverse();
+        boundaries
+    }
+
+    fn without(text: &str, range: Range<usize>) -> String {
+        text.chars().take(range.start).chain(text.chars().skip(range.end)).collect()
+    }
+
+    proptest! {
+        // Each case walks a text long enough to span several rope chunks after every edit
+        #![proptest_config(ProptestConfig::with_cases(48))]
+
+        #[test]
+        fn edits_never_split_clusters(
+            seed in proptest::collection::vec(0..PIECES.len(), 0..300),
+            ops in proptest::collection::vec(op(), 1..24),
+        ) {
+            let mut text: String = seed.iter().map(|&piece| PIECES[piece]).collect();
+            let mut document = Document::from(text.as_str());
+
+            for op in ops {
+                let boundaries = reference_boundaries(&text);
+                let clusters = boundaries.len() - 1;
+                match op {
+                    // The caret only ever rests on cluster boundaries
+                    Op::Insert(piece, at) => {
+                        let at = boundaries[at % boundaries.len()];
+                        document.insert(at, PIECES[piece]);
+                        text = text.chars().take(at).chain(PIECES[piece].chars()).chain(text.chars().skip(at)).collect();
+                    }
+                    Op::Backspace(at) if clusters > 0 => {
+                        let cluster = at % clusters;
+                        let range = document.cluster_before(boundaries[cluster + 1]);
+                        // Exactly one whole cluster goes: no mark is left without its base
+                        // and no half of a pair or sequence stays behind
+                        prop_assert_eq!(range.clone(), boundaries[cluster]..boundaries[cluster + 1]);
+                        document.remove(range.clone());
+                        text = without(&text, range);
+                    }
+                    Op::Delete(at) if clusters > 0 => {
+                        let cluster = at % clusters;
+                        let range = document.cluster_after(boundaries[cluster]);
+                        prop_assert_eq!(range.clone(), boundaries[cluster]..boundaries[cluster + 1]);
+                        document.remove(range.clone());
+                        text = without(&text, range);
+                    }
+                    _ => {}
+                }
+
+                prop_assert_eq!(document.to_string(), text.clone());
+                let expected = reference_boundaries(&text);
+                prop_assert_eq!(forward_boundaries(&document), expected.clone());
+                prop_assert_eq!(backward_boundaries(&document), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn surrogate_pairs_count_two_units() {
+        let document = Document::from(format!("a{}b", THUMBS_UP).as_str());
+        assert_eq!(document.len_chars(), 4);
+        assert_eq!(document.len_utf16(), 6);
+        assert_eq!((0..=4).map(|idx| document.char_to_utf16(idx)).collect::<Vec<_>>(), [0, 1, 3, 5, 6]);
+        assert_eq!(document.utf16_to_char(5), 3);
+        // "a" is one byte, each astral char four
+        assert_eq!(document.byte_to_utf16(1), 1);
+        assert_eq!(document.byte_to_utf16(5), 3);
+        assert_eq!(document.byte_to_utf16(9), 5);
+        assert_eq!(document.utf16_to_byte(5), 9);
+    }
+
+    #[test]
+    fn regional_indicator_flags() {
+        let document = Document::from(FLAGS);
+        assert_eq!(document.len_utf16(), 8);
+        assert_eq!(document.utf16_to_char(4), 2);
+        assert_eq!(document.utf16_to_caret(4), 2);
+        // Half a flag is not a place for the caret
+        assert_eq!(document.utf16_to_caret(2), 0);
+        assert_eq!(document.utf16_to_caret(6), 2);
+        assert_eq!(document.utf16_to_caret(8), 4);
+    }
+
+    #[test]
+    fn offsets_inside_a_surrogate_pair_snap_to_the_cluster_start() {
+        let document = Document::from(format!("a{}b", THUMBS_UP).as_str());
+        // Inside the thumb, on the modifier and inside the modifier
+        for offset in [2, 3, 4] {
+            assert_eq!(document.utf16_to_caret(offset), 1, "offset {}", offset);
+        }
+        assert_eq!(document.utf16_to_caret(5), 3);
+        // The char conversion alone maps into the pair's own char
+        assert_eq!(document.utf16_to_char(2), 1);
+        assert_eq!(document.utf16_to_char(4), 2);
+    }
+
+    #[test]
+    fn offsets_past_the_end_are_clamped() {
+        let document = Document::from(THUMBS_UP);
+        assert_eq!(document.utf16_to_char(100), 2);
+        assert_eq!(document.char_to_utf16(100), 4);
+        assert_eq!(document.byte_to_utf16(100), 4);
+    }
+
+    #[test]
+    fn round_trips_across_chunk_boundaries() {
+        // Long enough for the rope to split it into many chunks, with astral chars
+        // landing on and around the seams
+        let text: String = (0..2000)
+            .map(|idx| match idx % 5 {
+                0 => THUMBS_UP.to_string(),
+                1 => "x".to_string(),
+                2 => FLAGS.to_string(),
+                3 => "\u{e9}".to_string(),
+                _ => "\r\n".to_string(),
+            })
+            .collect();
+        let document = Document::from(text.as_str());
+        assert!(document.rope.chunks().count() > 1);
+
+        let mut utf16 = 0;
+        let mut byte = 0;
+        for (char_idx, ch) in text.chars().enumerate() {
+            assert_eq!(document.char_to_utf16(char_idx), utf16);
+            assert_eq!(document.utf16_to_char(utf16), char_idx);
+            assert_eq!(document.byte_to_utf16(byte), utf16);
+            assert_eq!(document.utf16_to_byte(utf16), byte);
+            utf16 += ch.len_utf16();
+            byte += ch.len_utf8();
+        }
+        assert_eq!(document.len_utf16(), utf16);
+        assert_eq!(document.char_to_utf16(document.len_chars()), utf16);
+    }
+
+    #[test]
+    fn changes_replay_edits_or_diff_unrelated_text() {
+        let old = Document::from("hello world");
+        assert!(old.changes_since(&old).is_empty());
+
+        let mut new = old.clone();
+        new.insert(5, ",");
+        new.replace(0..1, "J");
+        assert_eq!(new.changes_since(&old), [
+            Change { start: 5, old_end: 5, new_end: 6 },
+            Change { start: 0, old_end: 1, new_end: 0 },
+            Change { start: 0, old_end: 0, new_end: 1 },
+        ]);
+
+        // Same text, separate history: only the span between the common ends differs
+        let unrelated = Document::from("hello, world");
+        assert_eq!(unrelated.changes_since(&old), [Change { start: 5, old_end: 5, new_end: 6 }]);
+        assert!(Document::from("hello world").changes_since(&old).is_empty());
+    }
+
+    // The words `word_ranges` finds in `text`, as strings
+    fn words(text: &str, subword: bool) -> Vec<String> {
+        word_ranges(text, subword)
+            .into_iter()
+            .map(|range| text.chars().skip(range.start).take(range.len()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn word_ranges_split_identifiers_and_skip_punctuation() {
+        let cases: &[(&str, bool, &[&str])] = &[
+            ("camelCaseWord", false, &["camelCaseWord"]),
+            ("camelCaseWord", true, &["camel", "Case", "Word"]),
+            ("HTTPServer", true, &["HTTP", "Server"]),
+            ("parseHTTPResponse_code", true, &["parse", "HTTP", "Response", "code"]),
+            ("ABC", true, &["ABC"]),
+            ("snake_case_name", false, &["snake_case_name"]),
+            ("snake_case_name", true, &["snake", "case", "name"]),
+            ("_private__x", true, &["private", "x"]),
+            ("utf8Decoder2", true, &["utf8", "Decoder2"]),
+            ("x2 = y10", false, &["x2", "y10"]),
+            // Punctuation runs are skipped, including ones Unicode keeps inside a word
+            ("a += b;", false, &["a", "b"]),
+            ("--> ::", false, &[]),
+            ("foo.bar(baz)", false, &["foo", "bar", "baz"]),
+            ("std::mem::take", false, &["std", "mem", "take"]),
+            ("3.14", false, &["3", "14"]),
+            ("don't", false, &["don", "t"]),
+            // Accented words stay whole; ideographs are words of one char; kana runs join
+            ("na\u{ef}ve caf\u{e9}", true, &["na\u{ef}ve", "caf\u{e9}"]),
+            ("\u{65e5}\u{672c}\u{8a9e}\u{30c6}\u{30ad}\u{30b9}\u{30c8}", false, &["\u{65e5}", "\u{672c}", "\u{8a9e}", "\u{30c6}\u{30ad}\u{30b9}\u{30c8}"]),
+            // Emoji are not words
+            (&format!("hi {} there", THUMBS_UP), false, &["hi", "there"]),
+        ];
+        for &(text, subword, expected) in cases {
+            assert_eq!(words(text, subword), expected, "{:?} (subword: {})", text, subword);
+        }
+    }
+
+    #[test]
+    fn word_motion_stops_at_word_edges_and_line_ends() {
+        // Line 0 is chars 0..15, line 1 chars 16..27, line 2 chars 28..34
+        let document = Document::from(format!("let fooBar = 1;\n  baz_qux()\n\u{65e5}\u{672c} {}x", THUMBS_UP).as_str());
+        // (from, subword, next_word_end, prev_word_start)
+        let cases = [
+            (0, false, 3, 0),
+            (3, false, 10, 0),
+            (3, true, 7, 0),
+            (7, true, 10, 4),
+            (9, true, 10, 7),
+            (9, false, 10, 4),
+            // Past the last word the line end is a stop, then the next line's start
+            (14, false, 15, 13),
+            (15, false, 16, 13),
+            (16, false, 25, 15),
+            // Leading indentation: back to the line start, which is not a word start
+            (18, false, 25, 16),
+            (21, true, 25, 18),
+            (23, true, 25, 22),
+            (25, false, 27, 18),
+            // Each ideograph is a stop and the emoji cluster is skipped whole
+            (27, false, 28, 18),
+            (28, false, 29, 27),
+            (29, false, 30, 28),
+            (30, false, 34, 29),
+            (34, false, 34, 33),
+        ];
+        for (from, subword, next, prev) in cases {
+            assert_eq!(document.next_word_end(from, subword), next, "next_word_end({}, {})", from, subword);
+            assert_eq!(document.prev_word_start(from, subword), prev, "prev_word_start({}, {})", from, subword);
+        }
+    }
+}
@@ -3,6 +3,8 @@ use wasm_bindgen::prelude::*;
 use web_sys::{HtmlCanvasElement, HtmlTextAreaElement};
 use std::cell::RefCell;
 use std::rc::Rc;
+use crate::document::{Document, DocumentSnapshot};
+use crate::selection::Selection;
 
 #[wasm_bindgen]
 extern "C" {
@@ -14,6 +16,106 @@ macro_rules! console_log {
     ($($t:tt)*) => (log(&format_args!($($t)*).to_string()))
 }
 
+// Font size multiplier per zoom keystroke
+const ZOOM_STEP: f32 = 1.1;
+
+// Auto-scroll while drag-selecting outside the canvas: pixels scrolled per tick for each
+// pixel the pointer is past the edge, capped so far-away pointers stay controllable
+const AUTO_SCROLL_RATE: f32 = 0.2;
+const AUTO_SCROLL_MAX: f32 = 40.0;
+const AUTO_SCROLL_INTERVAL_MS: i32 = 16;
+
+// Inset of the text from the canvas's top-left corner, in CSS pixels
+const TEXT_PADDING: f32 = 100.0;
+
+// Wheel deltas reported in lines or pages are converted to pixels
+const WHEEL_LINE_PX: f64 = 20.0;
+
+// Chars of document copied into the hidden textarea on each side of the selection, so
+// input methods and virtual keyboards see the text around the caret
+const MIRROR_CONTEXT: usize = 256;
+// Longer selections are not copied; the textarea gets a caret at the selection's head
+const MIRROR_MAX_SELECTION: usize = 4096;
+
+// Interval handle and the callback it runs, dropped together when the timer stops
+type IntervalTimer = (i32, Closure<dyn FnMut()>);
+
+// Last pointer position of a drag, with the canvas size it is measured against
+#[derive(Clone, Copy)]
+struct Drag {
+    x: f32,
+    y: f32,
+    width: f32,
+    height: f32,
+}
+
+// The slice of the document held by the hidden textarea. Offsets are UTF-16 code units,
+// which is what the textarea counts in.
+#[derive(Debug, Clone, PartialEq)]
+struct Mirror {
+    // Document offset of the textarea's first code unit
+    offset: u32,
+    text: String,
+    // Selection within `text`
+    anchor: u32,
+    head: u32,
+}
+
+impl Mirror {
+    fn new(document: &Document, selection: Selection) -> Self {
+        let mirrored = if selection.range().len() > MIRROR_MAX_SELECTION {
+            Selection::caret(selection.head)
+        } else {
+            selection
+        };
+        let range = mirrored.range();
+        let start = document.snap_to_grapheme(range.start.saturating_sub(MIRROR_CONTEXT));
+        let mut end = (range.end + MIRROR_CONTEXT).min(document.len_chars());
+        if !document.is_grapheme_boundary(end) {
+            end = document.next_grapheme_boundary(end);
+        }
+        let offset = document.char_to_utf16(start);
+        let local = |char_idx: usize| (document.char_to_utf16(char_idx) - offset) as u32;
+        Self {
+            offset: offset as u32,
+            text: document.slice(start..end).to_string(),
+            anchor: local(mirrored.anchor),
+            head: local(mirrored.head),
+        }
+    }
+
+    // The document span, in UTF-16 code units, and the text replacing it that turn the
+    // mirrored text into `value`. None when nothing changed.
+    fn edit_to(&self, value: &str) -> Option<(std::ops::Range<u32>, String)> {
+        let old: Vec<char> = self.text.chars().collect();
+        let new: Vec<char> = value.chars().collect();
+        let prefix = old.iter().zip(&new).take_while(|(old, new)| old == new).count();
+        let suffix = old[prefix..].iter().rev()
+            .zip(new[prefix..].iter().rev())
+            .take_while(|(old, new)| old == new)
+            .count();
+        if prefix + suffix == old.len() && old.len() == new.len() {
+            return None;
+        }
+        let utf16_len = |chars: &[char]| chars.iter().map(|ch| ch.len_utf16() as u32).sum::<u32>();
+        let start = self.offset + utf16_len(&old[..prefix]);
+        let end = self.offset + utf16_len(&old[..old.len() - suffix]);
+        Some((start..end, new[prefix..new.len() - suffix].iter().collect()))
+    }
+}
+
+thread_local! {
+    // What the hidden textarea was last filled with
+    static MIRROR: RefCell<Option<Mirror>> = const { RefCell::new(None) };
+    // Set between compositionstart and compositionend, while the textarea holds IME text
+    // that must not be copied into the document
+    static COMPOSING: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+    static DRAG: std::cell::Cell<Option<Drag>> = const { std::cell::Cell::new(None) };
+    static AUTO_SCROLL_TIMER: RefCell<Option<IntervalTimer>> = const { RefCell::new(None) };
+    // Steps the wheel glide until the text comes to rest
+    static MOMENTUM_TIMER: RefCell<Option<IntervalTimer>> = const { RefCell::new(None) };
+}
+
 #[component]
 pub fn FastTextInput() -> impl IntoView {
     let (text_content, set_text_content) = signal("Hello World".to_string());
@@ -26,6 +128,7 @@ pub fn FastTextInput() -> impl IntoView {
     // Setup input capture and rendering
     Effect::new(move |_| {
         setup_input_capture();
+        observe_canvas_size();
         let _ = set_render_frame; // Use the signal to avoid warnings
     });
     
@@ -57,6 +160,9 @@ pub fn FastTextInput() -> impl IntoView {
                 width="800" 
                 height="600" 
                 style="
+                    width: 100%;
+                    height: 100%;
+                    box-sizing: border-box;
                     border: 1px solid black; 
                     outline: none; 
                     background-color: #1a1a1a;
@@ -64,10 +170,31 @@ pub fn FastTextInput() -> impl IntoView {
                     cursor: text;
                 " 
                 tabindex="0"
-                on:click=move |_| {
-                    // Focus the hidden textarea when canvas is clicked
+                on:mousedown=move |event: web_sys::MouseEvent| {
+                    // Keep focus in the hidden textarea rather than the canvas
+                    event.prevent_default();
+                    if let Some((x, y)) = canvas_point(&event) {
+                        crate::input_buffer::click_at(x, y, event.detail().max(1) as u32, event.shift_key());
+                    }
                     focus_hidden_input();
                 }
+                on:pointerdown=move |event: web_sys::PointerEvent| {
+                    if event.button() == 0 {
+                        start_drag(&event);
+                    }
+                }
+                on:pointermove=move |event: web_sys::PointerEvent| {
+                    move_drag(&event);
+                }
+                on:pointerup=move |_| end_drag()
+                on:pointercancel=move |_| end_drag()
+                on:wheel=move |event: web_sys::WheelEvent| {
+                    // Ctrl+wheel (and trackpad pinch) stays with the browser
+                    if !event.ctrl_key() {
+                        event.prevent_default();
+                        scroll_wheel(&event);
+                    }
+                }
             ></canvas>
             
             // Status display
@@ -90,60 +217,281 @@ pub fn setup_input_capture() {
         let buffer_ptr = crate::input_buffer::get_input_buffer_ptr();
         let memory = wasm_bindgen::memory();
         
-        // Setup input event listener that syncs with GPU renderer
+        // The textarea only holds a mirrored slice of the document around the selection:
+        // edits arrive as beforeinput events and are applied to the renderer as deltas, then
+        // cancelled; the next render mirrors the result back
+        let before_input = Closure::wrap(Box::new(move |event: web_sys::InputEvent| {
+            // Composition text reaches the renderer through the composition events
+            if event.is_composing() || COMPOSING.with(|composing| composing.get()) {
+                return;
+            }
+            let handled = match event.input_type().as_str() {
+                "insertText" | "insertFromDrop" => match event.data().or_else(|| {
+                    event.data_transfer().and_then(|data| data.get_data("text/plain").ok())
+                }) {
+                    Some(text) => {
+                        crate::input_buffer::insert_text(&text);
+                        true
+                    }
+                    None => false,
+                },
+                "insertLineBreak" | "insertParagraph" => {
+                    crate::input_buffer::insert_text("\n");
+                    true
+                }
+                // Virtual keyboards that send no usable keydown delete through these. They only
+                // do so when the textarea has text before its caret, which the mirror provides.
+                "deleteContentBackward" => {
+                    crate::input_buffer::delete_char_at_cursor();
+                    true
+                }
+                "deleteContentForward" => {
+                    crate::input_buffer::delete_char_after_cursor();
+                    true
+                }
+                "deleteWordBackward" => {
+                    crate::input_buffer::delete_word_before_cursor(false);
+                    true
+                }
+                "deleteWordForward" => {
+                    crate::input_buffer::delete_word_after_cursor(false);
+                    true
+                }
+                "historyUndo" => {
+                    crate::input_buffer::undo();
+                    true
+                }
+                "historyRedo" => {
+                    crate::input_buffer::redo();
+                    true
+                }
+                _ => false,
+            };
+            if handled {
+                event.prevent_default();
+            }
+        }) as Box<dyn FnMut(_)>);
+        let _ = textarea.add_event_listener_with_callback("beforeinput", before_input.as_ref().unchecked_ref());
+        before_input.forget();
+        
+        // Some input methods don't let their edits be cancelled. Whatever they changed in the
+        // mirrored text is applied to the same span of the document.
         let input_callback = Closure::wrap(Box::new(move || {
+            if COMPOSING.with(|composing| composing.get()) {
+                return;
+            }
             let window = web_sys::window().unwrap();
             let document = window.document().unwrap();
             
             if let Some(textarea) = document.get_element_by_id("hidden-input") {
                 let textarea: HtmlTextAreaElement = textarea.dyn_into().unwrap();
                 let value = textarea.value();
-                let cursor_pos = textarea.selection_start().unwrap_or(Some(0)).unwrap_or(0) as usize;
-                
-                // Sync textarea with GPU renderer
-                wasm_bindgen_futures::spawn_local(async move {
-                    match crate::text_input::get_or_init_webgpu_resources().await {
-                        Ok(resources) => {
-                            let mut borrowed = resources.borrow_mut();
-                            if let Some(renderer) = borrowed.fast_text_renderer.as_mut() {
-                                // Set the text from textarea to GPU renderer
-                                renderer.set_text(&value);
-                                let text = renderer.get_text();
-                                let cursor_pos = renderer.get_cursor_position();
-                                drop(borrowed);
-                                
-                                // Render the synchronized text
-                                if let Err(e) = crate::fast_text_input::render_from_buffer(&text, cursor_pos) {
-                                    console_log!("Render error: {:?}", e);
-                                }
-                            }
-                        }
-                        Err(e) => console_log!("Failed to get renderer: {:?}", e),
-                    }
-                });
+                // Forget the mirror so the next render rewrites the textarea either way
+                let mirror = MIRROR.with(|mirror| mirror.borrow_mut().take());
+                if let Some((range, text)) = mirror.and_then(|mirror| mirror.edit_to(&value)) {
+                    crate::input_buffer::replace_range(range.start, range.end, &text);
+                }
             }
         }) as Box<dyn FnMut()>);
         
         textarea.set_oninput(Some(input_callback.as_ref().unchecked_ref()));
         input_callback.forget();
         
+        // Clipboard: the textarea only mirrors part of the document, so copy and cut fill the
+        // clipboard from the renderer's selection, and paste inserts the clipboard text directly
+        let copy = Closure::wrap(Box::new(move |event: web_sys::ClipboardEvent| {
+            copy_selection(&event);
+        }) as Box<dyn FnMut(_)>);
+        let cut = Closure::wrap(Box::new(move |event: web_sys::ClipboardEvent| {
+            if copy_selection(&event) {
+                crate::input_buffer::delete_selection();
+            }
+        }) as Box<dyn FnMut(_)>);
+        let paste = Closure::wrap(Box::new(move |event: web_sys::ClipboardEvent| {
+            event.prevent_default();
+            let text = event.clipboard_data().and_then(|data| data.get_data("text/plain").ok());
+            if let Some(text) = text.filter(|text| !text.is_empty()) {
+                crate::input_buffer::insert_text(&text);
+            }
+        }) as Box<dyn FnMut(_)>);
+        for (name, callback) in [("copy", copy), ("cut", cut), ("paste", paste)] {
+            let _ = textarea.add_event_listener_with_callback(name, callback.as_ref().unchecked_ref());
+            callback.forget();
+        }
+        
+        // IME composition: preedit text is only drawn until the input method commits it
+        let composition_start = Closure::wrap(Box::new(move |_: web_sys::CompositionEvent| {
+            COMPOSING.with(|composing| composing.set(true));
+            crate::input_buffer::update_composition("");
+        }) as Box<dyn FnMut(_)>);
+        let composition_update = Closure::wrap(Box::new(move |event: web_sys::CompositionEvent| {
+            crate::input_buffer::update_composition(&event.data().unwrap_or_default());
+        }) as Box<dyn FnMut(_)>);
+        let composition_end = Closure::wrap(Box::new(move |event: web_sys::CompositionEvent| {
+            COMPOSING.with(|composing| composing.set(false));
+            crate::input_buffer::end_composition(&event.data().unwrap_or_default());
+            // The input method left its text in the textarea and the renderer has it now, so
+            // an input event that follows must not apply it a second time
+            if let Some(textarea) = event.target().and_then(|target| target.dyn_into::<HtmlTextAreaElement>().ok()) {
+                MIRROR.with(|mirror| {
+                    if let Some(mirror) = mirror.borrow_mut().as_mut() {
+                        mirror.text = textarea.value();
+                    }
+                });
+            }
+        }) as Box<dyn FnMut(_)>);
+        for (name, callback) in [
+            ("compositionstart", composition_start),
+            ("compositionupdate", composition_update),
+            ("compositionend", composition_end),
+        ] {
+            let _ = textarea.add_event_listener_with_callback(name, callback.as_ref().unchecked_ref());
+            callback.forget();
+        }
+        
+        // Selection moved inside the textarea, e.g. by dragging a virtual keyboard's space
+        // bar: move the renderer's selection to match. Offsets are converted from UTF-16
+        // code units by the renderer.
+        let selection_change = Closure::wrap(Box::new(move |event: web_sys::Event| {
+            if COMPOSING.with(|composing| composing.get()) {
+                return;
+            }
+            let Some(textarea) = event.target().and_then(|target| target.dyn_into::<HtmlTextAreaElement>().ok()) else {
+                return;
+            };
+            let (Ok(Some(start)), Ok(Some(end))) = (textarea.selection_start(), textarea.selection_end()) else {
+                return;
+            };
+            let backward = textarea.selection_direction().ok().flatten().as_deref() == Some("backward");
+            let (anchor, head) = if backward { (end, start) } else { (start, end) };
+            let moved = MIRROR.with(|mirror| match mirror.borrow().as_ref() {
+                Some(mirror) if mirror.text == textarea.value() => {
+                    ((mirror.anchor, mirror.head) != (anchor, head)).then_some(mirror.offset)
+                }
+                _ => None,
+            });
+            if let Some(offset) = moved {
+                crate::input_buffer::set_selection(offset + anchor, offset + head);
+            }
+        }) as Box<dyn FnMut(_)>);
+        let _ = textarea.add_event_listener_with_callback("selectionchange", selection_change.as_ref().unchecked_ref());
+        selection_change.forget();
+        
         // Handle special keys
         let keydown_callback = Closure::wrap(Box::new(move |event: web_sys::KeyboardEvent| {
+            // Keys pressed while composing belong to the input method
+            if event.is_composing() || COMPOSING.with(|composing| composing.get()) {
+                return;
+            }
+            let shortcut = event.ctrl_key() || event.meta_key();
+            // Word motion is Ctrl+arrow, or Option+arrow on macOS; adding the other of the
+            // two modifiers moves by subword. Cmd+arrows on macOS go to line and text edges.
+            let mac = is_mac();
+            let word = if mac { event.alt_key() } else { event.ctrl_key() };
+            let subword = word && if mac { event.ctrl_key() } else { event.alt_key() };
+            let edge = mac && event.meta_key();
+            let extend = event.shift_key();
             match event.key().as_str() {
+                "ArrowLeft" | "ArrowRight" | "ArrowUp" | "ArrowDown" if edge => {
+                    event.prevent_default();
+                    match event.key().as_str() {
+                        "ArrowLeft" => crate::input_buffer::move_cursor_home(false, extend),
+                        "ArrowRight" => crate::input_buffer::move_cursor_end(false, extend),
+                        "ArrowUp" => crate::input_buffer::move_cursor_home(true, extend),
+                        _ => crate::input_buffer::move_cursor_end(true, extend),
+                    }
+                }
+                "ArrowLeft" if word => {
+                    event.prevent_default();
+                    crate::input_buffer::move_word_left(subword, extend);
+                }
+                "ArrowRight" if word => {
+                    event.prevent_default();
+                    crate::input_buffer::move_word_right(subword, extend);
+                }
+                "ArrowLeft" if event.shift_key() => {
+                    event.prevent_default();
+                    crate::input_buffer::extend_selection_left();
+                }
+                "ArrowRight" if event.shift_key() => {
+                    event.prevent_default();
+                    crate::input_buffer::extend_selection_right();
+                }
                 "ArrowLeft" => {
                     event.prevent_default();
                     crate::input_buffer::move_cursor_left();
-                    sync_textarea_with_gpu_renderer();
                 }
                 "ArrowRight" => {
                     event.prevent_default();
                     crate::input_buffer::move_cursor_right();
-                    sync_textarea_with_gpu_renderer();
+                }
+                "ArrowUp" => {
+                    event.prevent_default();
+                    crate::input_buffer::move_cursor_up(event.shift_key());
+                }
+                "ArrowDown" => {
+                    event.prevent_default();
+                    crate::input_buffer::move_cursor_down(event.shift_key());
+                }
+                "PageUp" => {
+                    event.prevent_default();
+                    crate::input_buffer::page_up(event.shift_key());
+                }
+                "PageDown" => {
+                    event.prevent_default();
+                    crate::input_buffer::page_down(event.shift_key());
+                }
+                "Home" => {
+                    event.prevent_default();
+                    crate::input_buffer::move_cursor_home(shortcut, event.shift_key());
+                }
+                "End" => {
+                    event.prevent_default();
+                    crate::input_buffer::move_cursor_end(shortcut, event.shift_key());
+                }
+                "z" | "Z" if shortcut && event.shift_key() => {
+                    event.prevent_default();
+                    crate::input_buffer::redo();
+                }
+                "z" | "Z" if shortcut => {
+                    event.prevent_default();
+                    crate::input_buffer::undo();
+                }
+                "y" | "Y" if event.ctrl_key() => {
+                    event.prevent_default();
+                    crate::input_buffer::redo();
+                }
+                "a" | "A" if shortcut => {
+                    event.prevent_default();
+                    crate::input_buffer::select_all();
+                }
+                "=" | "+" if shortcut => {
+                    event.prevent_default();
+                    crate::input_buffer::zoom(ZOOM_STEP);
+                }
+                "-" if shortcut => {
+                    event.prevent_default();
+                    crate::input_buffer::zoom(1.0 / ZOOM_STEP);
+                }
+                "0" if shortcut => {
+                    event.prevent_default();
+                    crate::input_buffer::reset_zoom();
+                }
+                "Backspace" if word => {
+                    event.prevent_default();
+                    crate::input_buffer::delete_word_before_cursor(subword);
                 }
                 "Backspace" => {
                     event.prevent_default();
                     crate::input_buffer::delete_char_at_cursor();
-                    sync_textarea_with_gpu_renderer();
+                }
+                "Delete" if word => {
+                    event.prevent_default();
+                    crate::input_buffer::delete_word_after_cursor(subword);
+                }
+                "Delete" => {
+                    event.prevent_default();
+                    crate::input_buffer::delete_char_after_cursor();
                 }
                 _ => {}
             }
@@ -154,32 +502,276 @@ pub fn setup_input_capture() {
     }
 }
 
-fn sync_textarea_with_gpu_renderer() {
-    wasm_bindgen_futures::spawn_local(async move {
-        match crate::text_input::get_or_init_webgpu_resources().await {
-            Ok(resources) => {
-                let borrowed = resources.borrow();
-                if let Some(renderer) = borrowed.fast_text_renderer.as_ref() {
-                    let text = renderer.get_text();
-                    let cursor_pos = renderer.get_cursor_position();
-                    drop(borrowed);
-                    
-                    // Update textarea to match GPU renderer state
-                    if let Some(window) = web_sys::window() {
-                        if let Some(document) = window.document() {
-                            if let Some(textarea) = document.get_element_by_id("hidden-input") {
-                                let textarea: HtmlTextAreaElement = textarea.dyn_into().unwrap();
-                                textarea.set_value(&text);
-                                let _ = textarea.set_selection_start(Some(cursor_pos as u32));
-                                let _ = textarea.set_selection_end(Some(cursor_pos as u32));
-                            }
-                        }
-                    }
-                }
+// Put the selected text on the clipboard. Returns whether there was any.
+fn copy_selection(event: &web_sys::ClipboardEvent) -> bool {
+    event.prevent_default();
+    let Some(text) = crate::input_buffer::selected_text().filter(|text| !text.is_empty()) else {
+        return false;
+    };
+    event
+        .clipboard_data()
+        .is_some_and(|data| data.set_data("text/plain", &text).is_ok())
+}
+
+fn is_mac() -> bool {
+    web_sys::window()
+        .and_then(|window| window.navigator().platform().ok())
+        .is_some_and(|platform| platform.starts_with("Mac"))
+}
+
+// Pointer position in canvas pixels. The canvas backing store can be larger than its CSS
+// box (by devicePixelRatio on high-DPI screens), so CSS pixels are scaled to match.
+fn canvas_point(event: &web_sys::MouseEvent) -> Option<(f32, f32)> {
+    let canvas: HtmlCanvasElement = event.current_target()?.dyn_into().ok()?;
+    let bounds = canvas.get_bounding_client_rect();
+    let (border_x, border_y) = (canvas.client_left() as f64, canvas.client_top() as f64);
+    let (css_width, css_height) = (canvas.client_width() as f64, canvas.client_height() as f64);
+    if css_width <= 0.0 || css_height <= 0.0 {
+        return None;
+    }
+    let x = (event.client_x() as f64 - bounds.left() - border_x) * canvas.width() as f64 / css_width;
+    let y = (event.client_y() as f64 - bounds.top() - border_y) * canvas.height() as f64 / css_height;
+    Some((x as f32, y as f32))
+}
+
+// Capture the pointer so the drag keeps reporting positions once it leaves the canvas
+fn start_drag(event: &web_sys::PointerEvent) {
+    let Some(canvas) = event.current_target().and_then(|target| target.dyn_into::<HtmlCanvasElement>().ok()) else {
+        return;
+    };
+    let _ = canvas.set_pointer_capture(event.pointer_id());
+    if let Some((x, y)) = canvas_point(event) {
+        DRAG.with(|drag| drag.set(Some(Drag { x, y, width: canvas.width() as f32, height: canvas.height() as f32 })));
+        start_auto_scroll();
+    }
+}
+
+fn move_drag(event: &web_sys::PointerEvent) {
+    let Some(mut current) = DRAG.with(|drag| drag.get()) else {
+        return;
+    };
+    if let Some((x, y)) = canvas_point(event) {
+        current.x = x;
+        current.y = y;
+        DRAG.with(|drag| drag.set(Some(current)));
+        crate::input_buffer::drag_to(x, y);
+    }
+}
+
+fn end_drag() {
+    if DRAG.with(|drag| drag.take()).is_none() {
+        return;
+    }
+    stop_auto_scroll();
+    crate::input_buffer::release_pointer();
+}
+
+// Scroll speed along one axis: zero inside the canvas, growing with the distance past an edge
+fn auto_scroll_speed(position: f32, size: f32) -> f32 {
+    let past_edge = if position < 0.0 {
+        position
+    } else if position > size {
+        position - size
+    } else {
+        0.0
+    };
+    (past_edge * AUTO_SCROLL_RATE).clamp(-AUTO_SCROLL_MAX, AUTO_SCROLL_MAX)
+}
+
+fn start_auto_scroll() {
+    let Some(window) = web_sys::window() else {
+        return;
+    };
+    stop_auto_scroll();
+    let tick = Closure::<dyn FnMut()>::new(move || {
+        let Some(drag) = DRAG.with(|drag| drag.get()) else {
+            return;
+        };
+        let dx = auto_scroll_speed(drag.x, drag.width);
+        let dy = auto_scroll_speed(drag.y, drag.height);
+        if dx != 0.0 || dy != 0.0 {
+            crate::input_buffer::drag_scroll(dx, dy, drag.x, drag.y);
+        }
+    });
+    if let Ok(id) = window.set_interval_with_callback_and_timeout_and_arguments_0(
+        tick.as_ref().unchecked_ref(),
+        AUTO_SCROLL_INTERVAL_MS,
+    ) {
+        AUTO_SCROLL_TIMER.with(|timer| *timer.borrow_mut() = Some((id, tick)));
+    }
+}
+
+fn stop_auto_scroll() {
+    if let Some((id, _)) = AUTO_SCROLL_TIMER.with(|timer| timer.borrow_mut().take())
+        && let Some(window) = web_sys::window()
+    {
+        window.clear_interval_with_handle(id);
+    }
+}
+
+// Glide the text by a wheel or trackpad delta. Pixel and line deltas are CSS pixels and are
+// scaled to the canvas backing store.
+fn scroll_wheel(event: &web_sys::WheelEvent) {
+    let canvas = event.current_target().and_then(|target| target.dyn_into::<HtmlCanvasElement>().ok());
+    let pixel_ratio = canvas.as_ref().map_or(1.0, canvas_pixel_ratio) as f64;
+    let scale = match event.delta_mode() {
+        web_sys::WheelEvent::DOM_DELTA_LINE => WHEEL_LINE_PX * pixel_ratio,
+        web_sys::WheelEvent::DOM_DELTA_PAGE => canvas
+            .map(|canvas| canvas.height() as f64)
+            .unwrap_or(WHEEL_LINE_PX * pixel_ratio),
+        _ => pixel_ratio,
+    };
+    crate::input_buffer::scroll_wheel((event.delta_x() * scale) as f32, (event.delta_y() * scale) as f32);
+}
+
+// Step the wheel glide every frame until it comes to rest
+pub fn start_momentum() {
+    if MOMENTUM_TIMER.with(|timer| timer.borrow().is_some()) {
+        return;
+    }
+    let Some(window) = web_sys::window() else {
+        return;
+    };
+    let tick = Closure::<dyn FnMut()>::new(crate::input_buffer::step_scroll);
+    if let Ok(id) = window.set_interval_with_callback_and_timeout_and_arguments_0(
+        tick.as_ref().unchecked_ref(),
+        AUTO_SCROLL_INTERVAL_MS,
+    ) {
+        MOMENTUM_TIMER.with(|timer| *timer.borrow_mut() = Some((id, tick)));
+    }
+}
+
+pub fn stop_momentum() {
+    if let Some((id, _)) = MOMENTUM_TIMER.with(|timer| timer.borrow_mut().take())
+        && let Some(window) = web_sys::window()
+    {
+        window.clear_interval_with_handle(id);
+    }
+}
+
+// Canvas backing-store size for its CSS box (inside the border), scaled by
+// devicePixelRatio so text stays sharp
+pub fn canvas_pixel_size(canvas: &HtmlCanvasElement) -> (u32, u32) {
+    let dpr = web_sys::window().map_or(1.0, |window| window.device_pixel_ratio());
+    let scale = |css: i32| ((css as f64 * dpr).round() as u32).max(1);
+    (scale(canvas.client_width()), scale(canvas.client_height()))
+}
+
+// Device pixels per CSS pixel of the canvas's backing store
+fn canvas_pixel_ratio(canvas: &HtmlCanvasElement) -> f32 {
+    if canvas.client_width() <= 0 {
+        return 1.0;
+    }
+    canvas.width() as f32 / canvas.client_width() as f32
+}
+
+// Fit the renderer's viewport to the canvas backing store. Font size and padding are given
+// in CSS pixels and scaled to the backing store so text keeps its size on high-DPI screens.
+pub fn fit_viewport(renderer: &mut crate::gpu::fast_text::FastTextRenderer, canvas: &HtmlCanvasElement) -> Result<(), JsValue> {
+    let pixel_ratio = canvas_pixel_ratio(canvas);
+    renderer.set_config(crate::gpu::text_config::TextConfig { pixel_ratio, ..renderer.config() })?;
+    let viewport = renderer.viewport_mut();
+    viewport.resize(canvas.width() as f32, canvas.height() as f32);
+    viewport.set_padding(TEXT_PADDING * pixel_ratio, TEXT_PADDING * pixel_ratio);
+    Ok(())
+}
+
+// Resize the backing store and the viewport whenever the canvas's CSS box changes, then
+// redraw at the new size
+fn observe_canvas_size() {
+    let Some(canvas) = web_sys::window()
+        .and_then(|window| window.document())
+        .and_then(|document| document.get_element_by_id("fast-webgpu-canvas"))
+    else {
+        return;
+    };
+    let callback = Closure::<dyn FnMut()>::new(move || {
+        // Until the renderer exists there is nothing to resize; it sizes itself when created
+        let Some(resources) = crate::text_input::webgpu_resources() else {
+            return;
+        };
+        let Ok(mut borrowed) = resources.try_borrow_mut() else {
+            return;
+        };
+        let borrowed = &mut *borrowed;
+        if let (Some(context), Some(renderer)) = (borrowed.context.as_mut(), borrowed.fast_text_renderer.as_mut()) {
+            let (width, height) = canvas_pixel_size(&context.canvas);
+            if let Err(e) = context.resize(width, height) {
+                console_log!("Canvas resize error: {:?}", e);
+                return;
             }
-            Err(e) => console_log!("Failed to get renderer: {:?}", e),
+            if let Err(e) = fit_viewport(renderer, &context.canvas) {
+                console_log!("Viewport resize error: {:?}", e);
+                return;
+            }
+            request_redraw();
         }
     });
+    match web_sys::ResizeObserver::new(callback.as_ref().unchecked_ref()) {
+        Ok(observer) => observer.observe(&canvas),
+        Err(e) => console_log!("ResizeObserver unavailable: {:?}", e),
+    }
+    callback.forget();
+}
+
+// Canvas pixels to CSS pixels along one axis; the inverse of the scaling in `canvas_point`
+fn canvas_to_css(value: f32, css_size: i32, backing_size: u32) -> f32 {
+    if backing_size == 0 {
+        return value;
+    }
+    value * css_size as f32 / backing_size as f32
+}
+
+// Move the hidden textarea over a canvas position, given in backing-store pixels. The
+// textarea is placed in CSS pixels, offset by the canvas border.
+fn position_hidden_input(x: f32, y: f32, height: f32) {
+    let Some(document) = web_sys::window().and_then(|window| window.document()) else {
+        return;
+    };
+    let Some(canvas) = document
+        .get_element_by_id("fast-webgpu-canvas")
+        .and_then(|element| element.dyn_into::<HtmlCanvasElement>().ok())
+    else {
+        return;
+    };
+    let Some(textarea) = document
+        .get_element_by_id("hidden-input")
+        .and_then(|element| element.dyn_into::<web_sys::HtmlElement>().ok())
+    else {
+        return;
+    };
+    let left = canvas_to_css(x, canvas.client_width(), canvas.width()) + canvas.client_left() as f32;
+    let top = canvas_to_css(y, canvas.client_height(), canvas.height()) + canvas.client_top() as f32;
+    let height = canvas_to_css(height, canvas.client_height(), canvas.height());
+    let style = textarea.style();
+    let _ = style.set_property("left", &format!("{}px", left));
+    let _ = style.set_property("top", &format!("{}px", top));
+    let _ = style.set_property("height", &format!("{}px", height));
+}
+
+// Fill the hidden textarea with the text around the selection and select the same span in
+// it. Nothing is written when the textarea already holds this mirror.
+fn write_mirror(mirror: Mirror) {
+    if MIRROR.with(|current| current.borrow().as_ref() == Some(&mirror)) {
+        return;
+    }
+    let Some(textarea) = web_sys::window()
+        .and_then(|window| window.document())
+        .and_then(|document| document.get_element_by_id("hidden-input"))
+        .and_then(|element| element.dyn_into::<HtmlTextAreaElement>().ok())
+    else {
+        return;
+    };
+    if textarea.value() != mirror.text {
+        textarea.set_value(&mirror.text);
+    }
+    let direction = if mirror.head < mirror.anchor { "backward" } else { "forward" };
+    let _ = textarea.set_selection_range_with_direction(
+        mirror.anchor.min(mirror.head),
+        mirror.anchor.max(mirror.head),
+        direction,
+    );
+    MIRROR.with(|current| *current.borrow_mut() = Some(mirror));
 }
 
 #[wasm_bindgen]
@@ -217,12 +809,11 @@ pub fn setup_render_loop(set_render_frame: WriteSignal<u32>) {
 }
 
 // Called from input_buffer.rs when input is committed
-pub fn render_from_buffer(text: &str, cursor_pos: usize) -> Result<(), JsValue> {
-    console_log!("render_from_buffer called with text: '{}' (length: {})", text, text.len());
+pub fn render_from_buffer(snapshot: DocumentSnapshot, selection: Selection) -> Result<(), JsValue> {
+    console_log!("render_from_buffer called with {} chars (revision: {})", snapshot.len_chars(), snapshot.revision());
     // Initialize WebGPU if not already done
-    let text_owned = text.to_string();
     wasm_bindgen_futures::spawn_local(async move {
-        if let Err(e) = render_fast_text(&text_owned, cursor_pos).await {
+        if let Err(e) = render_fast_text(&snapshot, selection).await {
             console_log!("Fast render error: {:?}", e);
         }
     });
@@ -230,8 +821,8 @@ pub fn render_from_buffer(text: &str, cursor_pos: usize) -> Result<(), JsValue>
     Ok(())
 }
 
-async fn render_fast_text(text: &str, _cursor_pos: usize) -> Result<(), JsValue> {
-    console_log!("render_fast_text called with text: '{}' (length: {})", text, text.len());
+async fn render_fast_text(text: &DocumentSnapshot, selection: Selection) -> Result<(), JsValue> {
+    console_log!("render_fast_text called with {} chars", text.len_chars());
     
     // Get or initialize WebGPU resources
     let resources = crate::text_input::get_or_init_webgpu_resources().await?;
@@ -263,15 +854,138 @@ async fn render_fast_text(text: &str, _cursor_pos: usize) -> Result<(), JsValue>
             if let Some(fast_renderer) = res.fast_text_renderer.as_mut() {
                 fast_renderer.render(
                     text,           // Use the actual text parameter
-                    100.0,          // x
-                    100.0,          // y
-                    800.0,          // screen_width
-                    600.0,          // screen_height
+                    selection,      // highlight + caret
                     &context
                 )?;
+                
+                // Keep the caret blinking while idle
+                if let Some(delay) = fast_renderer.next_caret_toggle_in(js_sys::Date::now()) {
+                    schedule_caret_blink(delay);
+                }
+                
+                // Follow the caret with the textarea so IME candidate windows open next to it
+                if let Some(position) = fast_renderer.caret_position(selection.head) {
+                    position_hidden_input(position.x, position.top, position.height);
+                }
+                
+                // The input method owns the textarea while composing
+                if !COMPOSING.with(|composing| composing.get()) {
+                    write_mirror(Mirror::new(fast_renderer.document(), fast_renderer.selection()));
+                }
             }
         }
     }
     
     Ok(())
-}
\ No newline at end of file
+}
+
+thread_local! {
+    static CARET_BLINK_TIMEOUT: RefCell<Option<i32>> = const { RefCell::new(None) };
+}
+
+// Re-render once the caret's blink phase flips; replaces any pending blink redraw
+fn schedule_caret_blink(delay_ms: f64) {
+    let Some(window) = web_sys::window() else {
+        return;
+    };
+    
+    CARET_BLINK_TIMEOUT.with(|handle| {
+        if let Some(id) = handle.borrow_mut().take() {
+            window.clear_timeout_with_handle(id);
+        }
+        
+        let callback = Closure::once_into_js(move || {
+            CARET_BLINK_TIMEOUT.with(|handle| *handle.borrow_mut() = None);
+            request_redraw();
+        });
+        if let Ok(id) = window.set_timeout_with_callback_and_timeout_and_arguments_0(
+            callback.unchecked_ref(),
+            delay_ms.ceil() as i32,
+        ) {
+            *handle.borrow_mut() = Some(id);
+        }
+    });
+}
+
+// Re-render the current renderer state without changing it
+pub fn request_redraw() {
+    wasm_bindgen_futures::spawn_local(async move {
+        match crate::text_input::get_or_init_webgpu_resources().await {
+            Ok(resources) => {
+                let borrowed = resources.borrow();
+                if let Some(renderer) = borrowed.fast_text_renderer.as_ref() {
+                    let (snapshot, selection) = renderer.display();
+                    drop(borrowed);
+                    if let Err(e) = render_from_buffer(snapshot, selection) {
+                        console_log!("Render error: {:?}", e);
+                    }
+                }
+            }
+            Err(e) => console_log!("Failed to get renderer: {:?}", e),
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn caret_positions_scale_back_to_css_pixels() {
+        // A 400x300 CSS box at devicePixelRatio 2 has an 800x600 backing store
+        assert_eq!(canvas_to_css(250.0, 400, 800), 125.0);
+        assert_eq!(canvas_to_css(36.0, 300, 600), 18.0);
+        // At devicePixelRatio 1 the two coincide
+        assert_eq!(canvas_to_css(250.0, 400, 400), 250.0);
+        // A canvas with no backing store yet leaves the value alone
+        assert_eq!(canvas_to_css(250.0, 0, 0), 250.0);
+    }
+    
+    #[test]
+    fn mirror_counts_in_utf16_around_the_selection() {
+        let document = Document::from("a😀b\ncd");
+        // Caret after the emoji: two code units in, after one char
+        let mirror = Mirror::new(&document, Selection::caret(2));
+        assert_eq!(mirror.text, "a😀b\ncd");
+        assert_eq!((mirror.offset, mirror.anchor, mirror.head), (0, 3, 3));
+        // A backward selection keeps its direction
+        let mirror = Mirror::new(&document, Selection::new(4, 1));
+        assert_eq!((mirror.anchor, mirror.head), (5, 1));
+    }
+    
+    #[test]
+    fn mirror_keeps_text_before_a_caret_at_a_line_start() {
+        let text = format!("{}\n{}", "😀".repeat(MIRROR_CONTEXT * 2), "x".repeat(MIRROR_CONTEXT * 2));
+        let document = Document::from(text.as_str());
+        let line_start = MIRROR_CONTEXT * 2 + 1;
+        let mirror = Mirror::new(&document, Selection::caret(line_start));
+        // The line break and the emoji before it are there for a virtual keyboard to delete
+        assert_eq!(mirror.text.chars().count(), MIRROR_CONTEXT * 2);
+        assert!(mirror.text.starts_with('😀') && mirror.text.contains("😀\nx"));
+        assert_eq!(mirror.offset as usize, document.char_to_utf16(line_start - MIRROR_CONTEXT));
+        assert_eq!(mirror.head as usize, (MIRROR_CONTEXT - 1) * 2 + 1);
+        // A selection too long to copy leaves a caret at its head
+        let document = Document::from("x".repeat(MIRROR_MAX_SELECTION + 1).as_str());
+        let mirror = Mirror::new(&document, Selection::new(0, document.len_chars()));
+        assert_eq!((mirror.anchor, mirror.head), (MIRROR_CONTEXT as u32, MIRROR_CONTEXT as u32));
+        assert_eq!(mirror.text.len(), MIRROR_CONTEXT);
+    }
+    
+    #[test]
+    fn mirror_edits_map_to_document_code_units() {
+        let document = Document::from("x".repeat(MIRROR_CONTEXT + 2).as_str());
+        let mut mirror = Mirror::new(&document, Selection::caret(MIRROR_CONTEXT + 2));
+        assert_eq!(mirror.offset, 2);
+        assert_eq!(mirror.edit_to(&mirror.text.clone()), None);
+        // Typing an emoji at the end
+        let typed = format!("{}😀", mirror.text);
+        assert_eq!(mirror.edit_to(&typed), Some((258..258, "😀".to_string())));
+        // Replacing it with another emoji replaces both of its code units
+        mirror.text = typed;
+        let replaced = mirror.text.replace('😀', "😎");
+        assert_eq!(mirror.edit_to(&replaced), Some((258..260, "😎".to_string())));
+        // Deleting it
+        let deleted = mirror.text.replace('😀', "");
+        assert_eq!(mirror.edit_to(&deleted), Some((258..260, String::new())));
+    }
+}
@@ -0,0 +1,197 @@
+// Font Registry
+// Fonts available to the renderers, registered at runtime
+// Responsibilities:
+// - Validate TTF/OTF bytes once and share them between renderers
+// - Fetch font files by URL
+// - Track which font is active so renderers can be rebuilt against it
+// - Resolve each char through an ordered fallback chain to the first font that has it
+
+use std::cell::RefCell;
+use std::rc::Rc;
+use fontdue::{Font, FontSettings};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::JsFuture;
+
+pub type FontId = usize;
+
+// A glyph of a particular font; glyph ids are only meaningful within their font
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct GlyphKey {
+    pub font: FontId,
+    pub glyph: u16,
+}
+
+// Compiled in so the editor can draw before any font has been fetched
+const DEFAULT_FONT_NAME: &str = "Spectral ExtraLight";
+const DEFAULT_FONT_DATA: &[u8] = include_bytes!("assets/fonts/Spectral-ExtraLight.ttf");
+
+// A parsed font. Cloning shares the parsed tables and the file's bytes.
+#[derive(Clone)]
+pub struct LoadedFont {
+    pub id: FontId,
+    pub name: String,
+    pub font: Rc<Font>,
+    // The font file, for the shaping and outline tables
+    data: Rc<[u8]>,
+}
+
+impl LoadedFont {
+    // Shaping and outline tables, read from the font file. The file was validated when it
+    // was registered, so parsing it again cannot fail.
+    pub fn face(&self) -> rustybuzz::Face<'_> {
+        rustybuzz::Face::from_slice(&self.data, 0).expect("font was validated at registration")
+    }
+}
+
+pub struct FontRegistry {
+    fonts: Vec<LoadedFont>,
+    active: FontId,
+    fallbacks: Vec<FontId>,
+}
+
+impl FontRegistry {
+    pub fn new() -> Result<Self, JsValue> {
+        let mut registry = Self {
+            fonts: Vec::new(),
+            active: 0,
+            fallbacks: Vec::new(),
+        };
+        registry.insert(DEFAULT_FONT_NAME, Rc::from(DEFAULT_FONT_DATA))?;
+        Ok(registry)
+    }
+
+    // Add a font from TTF/OTF bytes. Registering a name again replaces that font's
+    // bytes and keeps its id.
+    pub fn register(&mut self, name: &str, data: Vec<u8>) -> Result<FontId, JsValue> {
+        if data.is_empty() {
+            return Err(JsValue::from_str(&format!("Font '{}' is empty", name)));
+        }
+        self.insert(name, Rc::from(data))
+    }
+
+    // A replaced font's bytes are freed once no renderer holds a clone of it
+    fn insert(&mut self, name: &str, data: Rc<[u8]>) -> Result<FontId, JsValue> {
+        // The shaper and the MSDF outline reader parse the same bytes, so they must accept them too
+        if rustybuzz::Face::from_slice(&data, 0).is_none() {
+            return Err(JsValue::from_str(&format!("Failed to parse font '{}'", name)));
+        }
+        let font = Font::from_bytes(&*data, FontSettings::default())
+            .map_err(|e| JsValue::from_str(&format!("Failed to load font '{}': {:?}", name, e)))?;
+
+        let id = self.find(name).unwrap_or(self.fonts.len());
+        let loaded = LoadedFont {
+            id,
+            name: name.to_string(),
+            font: Rc::new(font),
+            data,
+        };
+        if id == self.fonts.len() {
+            self.fonts.push(loaded);
+        } else {
+            self.fonts[id] = loaded;
+        }
+        Ok(id)
+    }
+
+    pub fn find(&self, name: &str) -> Option<FontId> {
+        self.fonts.iter().position(|font| font.name == name)
+    }
+
+    pub fn active(&self) -> &LoadedFont {
+        &self.fonts[self.active]
+    }
+
+    pub fn set_active(&mut self, id: FontId) -> Result<&LoadedFont, JsValue> {
+        if id >= self.fonts.len() {
+            return Err(JsValue::from_str(&format!("No font registered with id {}", id)));
+        }
+        self.active = id;
+        Ok(&self.fonts[id])
+    }
+
+    // Fonts tried in order, after the active font, for chars it lacks
+    pub fn set_fallbacks(&mut self, ids: &[FontId]) -> Result<(), JsValue> {
+        if let Some(&id) = ids.iter().find(|&&id| id >= self.fonts.len()) {
+            return Err(JsValue::from_str(&format!("No font registered with id {}", id)));
+        }
+        self.fallbacks = ids.to_vec();
+        Ok(())
+    }
+
+    // The active font followed by its fallbacks, each at most once
+    pub fn chain(&self) -> FontChain {
+        let mut fonts: Vec<LoadedFont> = Vec::with_capacity(1 + self.fallbacks.len());
+        for &id in std::iter::once(&self.active).chain(&self.fallbacks) {
+            if !fonts.iter().any(|font| font.id == id) {
+                fonts.push(self.fonts[id].clone());
+            }
+        }
+        FontChain { fonts }
+    }
+}
+
+// Ordered fonts a renderer draws with: the primary font, then its fallbacks
+#[derive(Clone)]
+pub struct FontChain {
+    fonts: Vec<LoadedFont>,
+}
+
+impl FontChain {
+    pub fn primary(&self) -> &LoadedFont {
+        &self.fonts[0]
+    }
+
+    pub fn fonts(&self) -> &[LoadedFont] {
+        &self.fonts
+    }
+
+    pub fn get(&self, id: FontId) -> Option<&LoadedFont> {
+        self.fonts.iter().find(|font| font.id == id)
+    }
+
+    // First font with a glyph for `ch`. Chars no font covers map to the primary
+    // font's missing-glyph box.
+    pub fn resolve(&self, ch: char) -> (&LoadedFont, u16) {
+        self.fonts
+            .iter()
+            .find_map(|font| match font.font.lookup_glyph_index(ch) {
+                0 => None,
+                glyph => Some((font, glyph)),
+            })
+            .unwrap_or((self.primary(), 0))
+    }
+}
+
+thread_local! {
+    static FONT_REGISTRY: RefCell<Option<FontRegistry>> = const { RefCell::new(None) };
+}
+
+// Run `f` against the shared registry, creating it with the default font on first use
+pub fn with_registry<R>(f: impl FnOnce(&mut FontRegistry) -> R) -> Result<R, JsValue> {
+    FONT_REGISTRY.with(|registry| {
+        let mut registry = registry.borrow_mut();
+        if registry.is_none() {
+            *registry = Some(FontRegistry::new()?);
+        }
+        Ok(f(registry.as_mut().expect("font registry was just created")))
+    })
+}
+
+pub fn active_font() -> Result<LoadedFont, JsValue> {
+    with_registry(|registry| registry.active().clone())
+}
+
+pub fn active_chain() -> Result<FontChain, JsValue> {
+    with_registry(|registry| registry.chain())
+}
+
+// Download a font file
+pub async fn fetch_font(url: &str) -> Result<Vec<u8>, JsValue> {
+    let window = web_sys::window().ok_or_else(|| JsValue::from_str("No window available"))?;
+    let response: web_sys::Response = JsFuture::from(window.fetch_with_str(url)).await?.dyn_into()?;
+    if !response.ok() {
+        return Err(JsValue::from_str(&format!("Fetching font {} failed with status {}", url, response.status())));
+    }
+    let buffer = JsFuture::from(response.array_buffer()?).await?;
+    Ok(js_sys::Uint8Array::new(&buffer).to_vec())
+}
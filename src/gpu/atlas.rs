@@ -0,0 +1,384 @@
+// Glyph Atlas Packing
+// Shelf allocator that hands out rectangles in a fixed-size atlas texture
+// Responsibilities:
+// - Place glyphs of varying sizes without a fixed grid
+// - Never move a rectangle once handed out, so cached coordinates stay valid
+// - Deterministic: the same sequence of requests always yields the same placement
+// - Spread glyphs over several pages and evict the least recently used ones when full
+
+use std::collections::HashMap;
+use std::hash::Hash;
+
+// Empty texels kept between neighbours so linear filtering doesn't bleed across glyphs
+const GUTTER: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AtlasRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+// A horizontal strip of the atlas filled left to right
+#[derive(Debug, Clone)]
+struct Shelf {
+    y: u32,
+    height: u32,
+    cursor_x: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct ShelfAllocator {
+    width: u32,
+    height: u32,
+    shelves: Vec<Shelf>,
+}
+
+impl ShelfAllocator {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            shelves: Vec::new(),
+        }
+    }
+
+    // Reserve a `width` x `height` rectangle, or None if the atlas has no room left.
+    // Picks the open shelf that wastes the least height; opens a new shelf otherwise.
+    pub fn allocate(&mut self, width: u32, height: u32) -> Option<AtlasRect> {
+        let padded_width = width + GUTTER;
+        let padded_height = height + GUTTER;
+        if padded_width > self.width || padded_height > self.height {
+            return None;
+        }
+
+        let best = self
+            .shelves
+            .iter()
+            .enumerate()
+            .filter(|(_, shelf)| shelf.height >= padded_height && self.width - shelf.cursor_x >= padded_width)
+            .min_by_key(|(idx, shelf)| (shelf.height - padded_height, *idx))
+            .map(|(idx, _)| idx);
+
+        // Don't park a small glyph on a much taller shelf when a snugger one can still be opened
+        let idx = match best {
+            Some(idx) if self.shelves[idx].height <= padded_height * 2 || !self.can_open(padded_height) => idx,
+            _ if self.can_open(padded_height) => {
+                let y = self.used_height();
+                self.shelves.push(Shelf {
+                    y,
+                    height: padded_height,
+                    cursor_x: 0,
+                });
+                self.shelves.len() - 1
+            }
+            _ => return None,
+        };
+
+        let shelf = &mut self.shelves[idx];
+        let rect = AtlasRect {
+            x: shelf.cursor_x,
+            y: shelf.y,
+            width,
+            height,
+        };
+        shelf.cursor_x += padded_width;
+        Some(rect)
+    }
+
+    // Free every rectangle on the shelf starting at row `y` so it can be refilled.
+    // The shelf keeps its height.
+    pub fn clear_shelf(&mut self, y: u32) {
+        if let Some(shelf) = self.shelves.iter_mut().find(|shelf| shelf.y == y) {
+            shelf.cursor_x = 0;
+        }
+    }
+
+    // Height of the shelf starting at row `y`
+    pub fn shelf_height(&self, y: u32) -> Option<u32> {
+        self.shelves.iter().find(|shelf| shelf.y == y).map(|shelf| shelf.height)
+    }
+
+    // Forget every allocation
+    pub fn reset(&mut self) {
+        self.shelves.clear();
+    }
+
+    // Rows of the atlas covered by shelves so far
+    pub fn used_height(&self) -> u32 {
+        self.shelves.last().map(|shelf| shelf.y + shelf.height).unwrap_or(0)
+    }
+
+    fn can_open(&self, height: u32) -> bool {
+        self.used_height() + height <= self.height
+    }
+}
+
+// Where a glyph lives: a texture array layer and a rectangle on it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AtlasSlot {
+    pub page: u32,
+    pub rect: AtlasRect,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct AtlasEntry {
+    slot: AtlasSlot,
+    last_used: u64,
+}
+
+// Result of adding a glyph: its slot, the glyphs evicted to make room, the regions they
+// freed (stale texels there must be cleared before anything samples next to them),
+// and whether a new page was added (the texture must grow to `page_count`)
+#[derive(Debug, Clone)]
+pub struct Insertion<K> {
+    pub slot: AtlasSlot,
+    pub evicted: Vec<K>,
+    pub cleared: Vec<AtlasSlot>,
+    pub grew: bool,
+}
+
+// Paged glyph cache with least-recently-used eviction.
+// Glyphs touched in the current frame are never evicted.
+pub struct GlyphAtlas<K> {
+    page_size: u32,
+    max_pages: u32,
+    pages: Vec<ShelfAllocator>,
+    entries: HashMap<K, AtlasEntry>,
+    frame: u64,
+}
+
+impl<K: Hash + Eq + Clone> GlyphAtlas<K> {
+    pub fn new(page_size: u32, max_pages: u32) -> Self {
+        Self {
+            page_size,
+            max_pages: max_pages.max(1),
+            pages: vec![ShelfAllocator::new(page_size, page_size)],
+            entries: HashMap::new(),
+            frame: 0,
+        }
+    }
+
+    pub fn page_count(&self) -> u32 {
+        self.pages.len() as u32
+    }
+
+    // Start a new frame; glyphs from earlier frames become evictable
+    pub fn begin_frame(&mut self) {
+        self.frame += 1;
+    }
+
+    // Mark a glyph as used this frame, returning its slot if it is cached
+    pub fn touch(&mut self, key: &K) -> Option<AtlasSlot> {
+        let frame = self.frame;
+        self.entries.get_mut(key).map(|entry| {
+            entry.last_used = frame;
+            entry.slot
+        })
+    }
+
+    // Reserve room for a new glyph, adding a page or evicting old glyphs if needed.
+    // Returns None when everything on every page is in use this frame.
+    pub fn insert(&mut self, key: K, width: u32, height: u32) -> Option<Insertion<K>> {
+        if width + GUTTER > self.page_size || height + GUTTER > self.page_size {
+            return None;
+        }
+
+        let mut evicted = Vec::new();
+        let mut cleared = Vec::new();
+        let mut grew = false;
+
+        let slot = loop {
+            if let Some(slot) = self.allocate(width, height) {
+                break slot;
+            }
+            if self.page_count() < self.max_pages {
+                self.pages.push(ShelfAllocator::new(self.page_size, self.page_size));
+                grew = true;
+                continue;
+            }
+            match self.evict_shelf(height, &mut evicted).or_else(|| self.evict_page(&mut evicted)) {
+                Some(region) => cleared.push(region),
+                None => return None,
+            }
+        };
+
+        self.entries.insert(key, AtlasEntry { slot, last_used: self.frame });
+        Some(Insertion { slot, evicted, cleared, grew })
+    }
+
+    // Drop every glyph and shrink back to one empty page
+    pub fn reset(&mut self) {
+        self.pages.truncate(1);
+        self.pages[0].reset();
+        self.entries.clear();
+    }
+
+    fn allocate(&mut self, width: u32, height: u32) -> Option<AtlasSlot> {
+        self.pages.iter_mut().enumerate().find_map(|(page, allocator)| {
+            allocator.allocate(width, height).map(|rect| AtlasSlot { page: page as u32, rect })
+        })
+    }
+
+    // Most recent use of anything on each (page, shelf y), in a stable order
+    fn shelf_usage(&self) -> Vec<((u32, u32), u64)> {
+        let mut usage: HashMap<(u32, u32), u64> = HashMap::new();
+        for entry in self.entries.values() {
+            let shelf = (entry.slot.page, entry.slot.rect.y);
+            let last_used = usage.entry(shelf).or_insert(0);
+            *last_used = (*last_used).max(entry.last_used);
+        }
+        let mut usage: Vec<_> = usage.into_iter().collect();
+        usage.sort_by_key(|&(shelf, last_used)| (last_used, shelf));
+        usage
+    }
+
+    // Empty the least recently used shelf tall enough for `height`, returning the whole
+    // strip it covers, gutters included
+    fn evict_shelf(&mut self, height: u32, evicted: &mut Vec<K>) -> Option<AtlasSlot> {
+        let candidate = self.shelf_usage().into_iter().find(|&((page, y), last_used)| {
+            last_used < self.frame
+                && self.pages[page as usize].shelf_height(y).is_some_and(|h| h >= height + GUTTER)
+        });
+        let ((page, y), _) = candidate?;
+
+        let allocator = &mut self.pages[page as usize];
+        let rect = AtlasRect { x: 0, y, width: self.page_size, height: allocator.shelf_height(y)? };
+        allocator.clear_shelf(y);
+        self.remove_where(|slot| slot.page == page && slot.rect.y == y, evicted);
+        Some(AtlasSlot { page, rect })
+    }
+
+    // Empty the least recently used page when no single shelf fits, returning all of it
+    fn evict_page(&mut self, evicted: &mut Vec<K>) -> Option<AtlasSlot> {
+        let mut page_usage = vec![0u64; self.pages.len()];
+        for entry in self.entries.values() {
+            let last_used = &mut page_usage[entry.slot.page as usize];
+            *last_used = (*last_used).max(entry.last_used);
+        }
+        let candidate = page_usage
+            .iter()
+            .enumerate()
+            .filter(|&(_, &last_used)| last_used < self.frame)
+            .min_by_key(|&(page, &last_used)| (last_used, page))
+            .map(|(page, _)| page as u32);
+        let page = candidate?;
+
+        self.pages[page as usize].reset();
+        self.remove_where(|slot| slot.page == page, evicted);
+        let rect = AtlasRect { x: 0, y: 0, width: self.page_size, height: self.page_size };
+        Some(AtlasSlot { page, rect })
+    }
+
+    fn remove_where(&mut self, doomed: impl Fn(&AtlasSlot) -> bool, evicted: &mut Vec<K>) {
+        self.entries.retain(|key, entry| {
+            if doomed(&entry.slot) {
+                evicted.push(key.clone());
+                false
+            } else {
+                true
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn overlaps(a: &AtlasRect, b: &AtlasRect) -> bool {
+        a.x < b.x + b.width && b.x < a.x + a.width && a.y < b.y + b.height && b.y < a.y + a.height
+    }
+
+    #[test]
+    fn packs_left_to_right_then_opens_shelves_downwards() {
+        let mut allocator = ShelfAllocator::new(64, 64);
+        let rects: Vec<_> = [(20, 10), (20, 10), (20, 10), (8, 9)]
+            .iter()
+            .map(|&(width, height)| allocator.allocate(width, height).unwrap())
+            .collect();
+        assert_eq!(rects, [
+            AtlasRect { x: 0, y: 0, width: 20, height: 10 },
+            AtlasRect { x: 21, y: 0, width: 20, height: 10 },
+            AtlasRect { x: 42, y: 0, width: 20, height: 10 },
+            // The first shelf is full, so a second opens below it, past the gutter
+            AtlasRect { x: 0, y: 11, width: 8, height: 9 },
+        ]);
+    }
+
+    #[test]
+    fn same_requests_give_the_same_placement() {
+        let requests = [(5, 7), (12, 3), (9, 9), (30, 14), (2, 2), (17, 7), (6, 20), (11, 4)];
+        let place = || {
+            let mut allocator = ShelfAllocator::new(48, 64);
+            requests.map(|(width, height)| allocator.allocate(width, height))
+        };
+        assert_eq!(place(), place());
+    }
+
+    #[test]
+    fn handed_out_rects_are_never_disturbed() {
+        let mut allocator = ShelfAllocator::new(128, 128);
+        let first = allocator.allocate(10, 12).unwrap();
+        let mut later = Vec::new();
+        for index in 0..40 {
+            if let Some(rect) = allocator.allocate(3 + index % 11, 4 + index % 9) {
+                later.push(rect);
+            }
+        }
+        // Later allocations neither move the first glyph nor land on it or on each other
+        let mut again = ShelfAllocator::new(128, 128);
+        assert_eq!(again.allocate(10, 12), Some(first));
+        for (index, rect) in later.iter().enumerate() {
+            assert!(!overlaps(rect, &first));
+            assert!(later[index + 1..].iter().all(|other| !overlaps(rect, other)));
+        }
+    }
+
+    #[test]
+    fn taller_glyph_opens_a_new_shelf() {
+        let mut allocator = ShelfAllocator::new(64, 64);
+        allocator.allocate(10, 10).unwrap();
+        let tall = allocator.allocate(10, 30).unwrap();
+        assert_eq!((tall.x, tall.y), (0, 11));
+        // A short glyph goes back on the snug first shelf, not the tall one
+        let short = allocator.allocate(10, 9).unwrap();
+        assert_eq!((short.x, short.y), (11, 0));
+        assert_eq!(allocator.used_height(), 11 + 31);
+    }
+
+    #[test]
+    fn returns_none_without_room() {
+        let mut allocator = ShelfAllocator::new(32, 32);
+        // The gutter must fit too
+        assert_eq!(allocator.allocate(32, 4), None);
+        assert_eq!(allocator.allocate(4, 32), None);
+
+        assert!(allocator.allocate(31, 15).is_some());
+        assert!(allocator.allocate(31, 15).is_some());
+        assert_eq!(allocator.allocate(1, 1), None);
+        assert_eq!(allocator.used_height(), 32);
+    }
+
+    #[test]
+    fn eviction_reports_the_regions_to_clear() {
+        let mut atlas: GlyphAtlas<u32> = GlyphAtlas::new(32, 1);
+        atlas.insert(1, 30, 14).unwrap();
+        atlas.insert(2, 30, 14).unwrap();
+        atlas.begin_frame();
+        atlas.touch(&2);
+
+        // The page is full; the shelf holding the glyph unused this frame is emptied whole
+        let insertion = atlas.insert(3, 10, 10).unwrap();
+        assert_eq!(insertion.evicted, [1]);
+        assert_eq!(insertion.cleared, [AtlasSlot { page: 0, rect: AtlasRect { x: 0, y: 0, width: 32, height: 15 } }]);
+        assert_eq!(insertion.slot.rect, AtlasRect { x: 0, y: 0, width: 10, height: 10 });
+
+        // With no shelf tall enough, a whole page goes
+        atlas.begin_frame();
+        let insertion = atlas.insert(4, 20, 20).unwrap();
+        assert_eq!(insertion.cleared, [AtlasSlot { page: 0, rect: AtlasRect { x: 0, y: 0, width: 32, height: 32 } }]);
+        assert!(atlas.insert(5, 1, 1).unwrap().cleared.is_empty());
+    }
+}
@@ -0,0 +1,105 @@
+// Caret Rendering
+// Describes how the text caret looks and when it blinks
+// Responsibilities:
+// - Caret style configuration (shape, width, color, blink timing)
+// - Computing the caret rectangle from the text layout
+// - Blink phase tracking, reset whenever the user edits or moves
+
+use crate::gpu::rect::Rect;
+use crate::layout::TextLayout;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaretShape {
+    // Thin vertical bar at the glyph boundary
+    Bar,
+    // Box covering the glyph after the cursor
+    Block,
+    // Line under the glyph after the cursor
+    Underline,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CaretStyle {
+    pub shape: CaretShape,
+    // Bar width or underline thickness in pixels
+    pub width: f32,
+    pub color: [f32; 4],
+    // Duration of each on/off phase; 0 disables blinking
+    pub blink_interval_ms: f64,
+}
+
+impl Default for CaretStyle {
+    fn default() -> Self {
+        Self {
+            shape: CaretShape::Bar,
+            width: 1.5,
+            color: [1.0, 1.0, 1.0, 0.9],
+            blink_interval_ms: 530.0,
+        }
+    }
+}
+
+pub struct Caret {
+    pub style: CaretStyle,
+    // Time the current blink cycle started; the caret is solid right after activity
+    blink_epoch_ms: f64,
+}
+
+impl Caret {
+    pub fn new(style: CaretStyle) -> Self {
+        Self {
+            style,
+            blink_epoch_ms: 0.0,
+        }
+    }
+
+    pub fn reset_blink(&mut self, now_ms: f64) {
+        self.blink_epoch_ms = now_ms;
+    }
+
+    pub fn is_visible(&self, now_ms: f64) -> bool {
+        if self.style.blink_interval_ms <= 0.0 {
+            return true;
+        }
+        let elapsed = (now_ms - self.blink_epoch_ms).max(0.0);
+        ((elapsed / self.style.blink_interval_ms) as u64).is_multiple_of(2)
+    }
+
+    // Milliseconds until the caret toggles visibility, or None when it doesn't blink
+    pub fn next_toggle_in(&self, now_ms: f64) -> Option<f64> {
+        let interval = self.style.blink_interval_ms;
+        if interval <= 0.0 {
+            return None;
+        }
+        let elapsed = (now_ms - self.blink_epoch_ms).max(0.0);
+        Some(interval - elapsed % interval)
+    }
+
+    // Rectangle for the caret at `char_idx`, in the layout's document space
+    pub fn rect(&self, layout: &TextLayout, char_idx: usize) -> Option<Rect> {
+        let position = layout.caret_position(char_idx)?;
+        let (x, y, width, height) = match self.style.shape {
+            CaretShape::Bar => (
+                position.x - self.style.width / 2.0,
+                position.top,
+                self.style.width,
+                position.height,
+            ),
+            CaretShape::Block => (position.cell_x, position.top, position.advance, position.height),
+            CaretShape::Underline => (
+                position.cell_x,
+                position.top + position.height - self.style.width,
+                position.advance,
+                self.style.width,
+            ),
+        };
+
+        Some(Rect {
+            x,
+            y,
+            width,
+            height,
+            color: self.style.color,
+        })
+    }
+}
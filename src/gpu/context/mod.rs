@@ -58,20 +58,7 @@ impl GpuContext {
         context.configure(&config);
         
         // Create persistent offscreen texture for double buffering
-        let offscreen_texture = device.create_texture(&{
-            let mut desc = web_sys::GpuTextureDescriptor::new(
-                web_sys::GpuTextureFormat::Bgra8unorm,
-                &{
-                    let mut extent = web_sys::GpuExtent3dDict::new(canvas.width());
-                    extent.set_height(canvas.height());
-                    extent.set_depth_or_array_layers(1);
-                    extent.into()
-                },
-                gpu_texture_usage::RENDER_ATTACHMENT | gpu_texture_usage::COPY_SRC,
-            );
-            desc.set_label("Offscreen Render Target");
-            desc
-        })?;
+        let offscreen_texture = create_offscreen_texture(&device, canvas.width(), canvas.height())?;
         
         let offscreen_view = offscreen_texture.create_view()?;
         
@@ -93,8 +80,39 @@ impl GpuContext {
         })
     }
     
+    // Resize the canvas backing store; the offscreen target is recreated to match it
+    pub fn resize(&mut self, width: u32, height: u32) -> Result<(), JsValue> {
+        let (width, height) = (width.max(1), height.max(1));
+        if width == self.canvas.width() && height == self.canvas.height() {
+            return Ok(());
+        }
+        self.canvas.set_width(width);
+        self.canvas.set_height(height);
+        self.offscreen_texture.destroy();
+        self.offscreen_texture = create_offscreen_texture(&self.device, width, height)?;
+        self.offscreen_view = self.offscreen_texture.create_view()?;
+        Ok(())
+    }
+    
     pub fn get_current_texture_view(&self) -> Result<web_sys::GpuTextureView, JsValue> {
         let current_texture = self.context.get_current_texture()?;
         current_texture.create_view()
       }
-}
\ No newline at end of file
+}
+
+fn create_offscreen_texture(device: &GpuDevice, width: u32, height: u32) -> Result<web_sys::GpuTexture, JsValue> {
+    device.create_texture(&{
+        let mut desc = web_sys::GpuTextureDescriptor::new(
+            web_sys::GpuTextureFormat::Bgra8unorm,
+            &{
+                let mut extent = web_sys::GpuExtent3dDict::new(width);
+                extent.set_height(height);
+                extent.set_depth_or_array_layers(1);
+                extent.into()
+            },
+            gpu_texture_usage::RENDER_ATTACHMENT | gpu_texture_usage::COPY_SRC,
+        );
+        desc.set_label("Offscreen Render Target");
+        desc
+    })
+}
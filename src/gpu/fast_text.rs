@@ -1,8 +1,21 @@
 use wasm_bindgen::prelude::*;
-use web_sys::{GpuDevice, GpuTextureView, GpuRenderPipeline, GpuBuffer, GpuComputePipeline};
+use web_sys::{GpuDevice, GpuTextureView, GpuRenderPipeline, GpuBuffer};
 use std::collections::HashMap;
-use fontdue::{Font, FontSettings, layout::{CoordinateSystem, Layout, LayoutSettings, TextStyle}};
+use std::ops::Range;
 use sdf_glyph_renderer::BitmapGlyph;
+use crate::document::{Change, Document, DocumentSnapshot};
+use crate::fonts::{self, FontChain, GlyphKey};
+use crate::layout::{CaretPosition, TextLayout};
+use crate::selection::{Granularity, Selection};
+use crate::viewport::Viewport;
+use crate::history::{Edit, EditKind, History};
+use crate::gpu::caret::{Caret, CaretStyle};
+use crate::gpu::rect::{Rect, RectRenderer};
+use crate::gpu::glyph_slots::{GlyphSlots, SlotMirror, NO_SLOT};
+use crate::gpu::glyph_positioning::{cpu_positions, Cluster, GlyphPositioner};
+use crate::gpu::atlas::{AtlasSlot, GlyphAtlas};
+use crate::gpu::msdf::{generate_msdf, MsdfBox};
+use crate::gpu::text_config::{TextConfig, WrapMode};
 
 #[wasm_bindgen]
 extern "C" {
@@ -14,134 +27,220 @@ macro_rules! console_log {
     ($($t:tt)*) => (log(&format_args!($($t)*).to_string()))
 }
 
+// Translucent blue used for selection highlights
+const SELECTION_COLOR: [f32; 4] = [0.26, 0.48, 0.86, 0.45];
+
+// Underline under text an input method is still composing
+const PREEDIT_UNDERLINE_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 0.8];
+const PREEDIT_UNDERLINE_WIDTH: f32 = 1.0;
+
 // Persistent GPU buffer for glyph instances
 struct GlyphInstanceBuffer {
     buffer: GpuBuffer,
-    capacity: usize,
     used: usize,
 }
 
-// Cached render command for reuse
-struct CachedRenderCommand {
-    vertex_count: u32,
-    instance_count: u32,
-    is_dirty: bool,
-}
-
-// Store glyph atlas info
+// Store glyph atlas info. Sizes are in raster pixels, at `TextConfig::raster_size`.
 struct GlyphInfo {
+    page: u32,
     atlas_x: f32,
     atlas_y: f32,
-    width: f32,
     height: f32,
     sdf_width: f32,
     sdf_height: f32,
+    // Bitmap offset from the pen position, x right and y up
+    xmin: f32,
+    ymin: f32,
 }
 
-// Dirty region tracking for incremental updates
-#[derive(Debug, Clone)]
-struct DirtyRegion {
-    start_char: usize,
-    end_char: usize,
-    needs_update: bool,
+// Per-glyph instance record: top-left position (f32x2), atlas rect (u16x4), color (unorm8x4),
+// atlas page (u32). The positioning shader writes the first two words.
+const INSTANCE_WORDS: usize = 6;
+const INSTANCE_STRIDE: usize = INSTANCE_WORDS * 4;
+
+// How glyph distance fields are built and sampled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GlyphMode {
+    // Single-channel SDF from a rasterized bitmap; cheap, but corners round off when zoomed
+    Sdf,
+    // Multi-channel SDF from the font outlines; keeps corners sharp at large sizes
+    Msdf,
+}
+
+impl GlyphMode {
+    fn texture_format(self) -> web_sys::GpuTextureFormat {
+        match self {
+            GlyphMode::Sdf => web_sys::GpuTextureFormat::R8unorm,
+            GlyphMode::Msdf => web_sys::GpuTextureFormat::Rgba8unorm,
+        }
+    }
+
+    fn bytes_per_texel(self) -> u32 {
+        match self {
+            GlyphMode::Sdf => 1,
+            GlyphMode::Msdf => 4,
+        }
+    }
+
+    // WGSL expression giving the distance at `tex_coord` on `page`
+    fn sample_distance(self) -> &'static str {
+        match self {
+            GlyphMode::Sdf => "textureSample(sdf_texture, sdf_sampler, tex_coord, page).r",
+            GlyphMode::Msdf => "median3(textureSample(sdf_texture, sdf_sampler, tex_coord, page).rgb)",
+        }
+    }
+}
+
+// Atlas texture array layers; past this, least recently used glyphs are evicted
+const MAX_ATLAS_PAGES: u32 = 4;
+
+// Atlas texel value meaning "far outside any glyph", in every channel
+const EMPTY_TEXEL: u8 = 255;
+
+// Glyph shader uniforms: screen size, atlas size, scroll offset, glyph scale, padded to 8 floats
+const GLOBALS_SIZE: usize = 32;
+
+// Glyph color written into each instance, as RGBA8
+const TEXT_COLOR: [u8; 4] = [255, 255, 255, 255];
+
+// A drawable glyph in document order, before its slot and position are known
+struct GlyphRecord {
+    char_idx: usize,
+    // Entry of the glyph in the positioning stream
+    cluster: usize,
+    // Quad offset from the pen position and absolute quad top
+    offset_x: f32,
+    top: f32,
+    instance: [u32; INSTANCE_WORDS],
 }
 
 pub struct FastTextRenderer {
     // GPU resources
     device: GpuDevice,
     render_pipeline: Option<GpuRenderPipeline>,
-    compute_pipeline: Option<GpuComputePipeline>,
+    positioner: Option<GlyphPositioner>,
     
     // Persistent buffers
     glyph_buffer: Option<GlyphInstanceBuffer>,
-    position_buffer: Option<GpuBuffer>,
-    vertex_buffer: Option<GpuBuffer>,
+    globals_buffer: Option<GpuBuffer>,
     atlas_texture: Option<web_sys::GpuTexture>,
     bind_group: Option<web_sys::GpuBindGroup>,
     
-    // Font and SDF atlas
-    font: Font,
-    sdf_atlas: Option<Vec<u8>>,
-    glyph_map: HashMap<char, GlyphInfo>,
+    // Fonts and SDF atlas, keyed by glyph so fallback fonts share the atlas
+    fonts: FontChain,
+    glyph_mode: GlyphMode,
+    glyph_atlas: GlyphAtlas<GlyphKey>,
+    glyph_map: HashMap<GlyphKey, GlyphInfo>,
+    
+    // Line index and cached glyph runs
+    layout: TextLayout,
+    
+    // Editor decorations drawn around the glyphs
+    rect_renderer: Option<RectRenderer>,
+    caret: Caret,
     
-    // Caching and optimization
-    cached_commands: HashMap<String, CachedRenderCommand>,
-    dirty_regions: Vec<DirtyRegion>,
-    last_text: String,
+    // Span of `last_snapshot` whose glyphs are not in the buffers yet, in its coordinates
+    dirty_region: Option<Change>,
+    last_snapshot: DocumentSnapshot,
+    
+    // Incremental buffer updates: glyph -> slot indirection and a CPU mirror of the instance buffer
+    glyph_slots: GlyphSlots,
+    glyph_chars: Vec<usize>,
+    instance_mirror: SlotMirror,
     
     // Text state management
-    text_buffer: Vec<char>,
-    cursor_position: usize,
+    document: Document,
+    selection: Selection,
+    history: History,
+    // Text an input method is composing; drawn over the selection but not yet in the document
+    preedit: Option<String>,
+    // The document with the preedit spliced in, edited in place while only the preedit
+    // changes so renderers can follow its edit log
+    composed: Option<Document>,
+    // Mouse press being dragged: the unit it selects by and what the press itself selected
+    press: Option<(Granularity, Range<usize>)>,
+    
+    // x the caret tries to return to when moving between lines, kept across short lines
+    goal_x: Option<f32>,
+    
+    // Canvas area the text is drawn into and how far it is scrolled
+    viewport: Viewport,
+    // Scroll the caret into view on the next layout, after it moved or the text changed
+    reveal_caret: bool,
     
     // Configuration
+    config: TextConfig,
     max_glyphs: usize,
     atlas_size: u32,
-    buffer_size: usize,
 }
 
 impl FastTextRenderer {
     pub fn new(device: GpuDevice, max_glyphs: usize) -> Result<Self, JsValue> {
+        Self::with_config(device, max_glyphs, TextConfig::default())
+    }
+    
+    pub fn with_config(device: GpuDevice, max_glyphs: usize, config: TextConfig) -> Result<Self, JsValue> {
         console_log!("Creating FastTextRenderer with {} max glyphs", max_glyphs);
         
-        // Load font
-        let font_data = include_bytes!("../assets/fonts/Spectral-ExtraLight.ttf");
-        if font_data.is_empty() {
-            return Err(JsValue::from_str("Font file is empty or not found"));
-        }
-        
-        let font = Font::from_bytes(font_data as &[u8], FontSettings::default())
-            .map_err(|e| JsValue::from_str(&format!("Failed to load font: {:?}", e)))?;
+        let fonts = fonts::active_chain()?;
         
         Ok(Self {
             device,
             render_pipeline: None,
-            compute_pipeline: None,
+            positioner: None,
             glyph_buffer: None,
-            position_buffer: None,
-            vertex_buffer: None,
+            globals_buffer: None,
             atlas_texture: None,
             bind_group: None,
-            font,
-            sdf_atlas: None,
+            fonts,
+            glyph_mode: GlyphMode::Sdf,
+            glyph_atlas: GlyphAtlas::new(1024, MAX_ATLAS_PAGES),
             glyph_map: HashMap::new(),
-            cached_commands: HashMap::new(),
-            dirty_regions: Vec::new(),
-            last_text: String::new(),
-            text_buffer: Vec::new(),
-            cursor_position: 0,
+            layout: TextLayout::new(config.pixel_size(), config.line_height),
+            rect_renderer: None,
+            caret: Caret::new(CaretStyle::default()),
+            dirty_region: None,
+            last_snapshot: DocumentSnapshot::default(),
+            glyph_slots: GlyphSlots::new(max_glyphs),
+            glyph_chars: Vec::new(),
+            instance_mirror: SlotMirror::new(INSTANCE_WORDS),
+            document: Document::new(),
+            selection: Selection::default(),
+            history: History::new(),
+            preedit: None,
+            composed: None,
+            press: None,
+            goal_x: None,
+            viewport: Viewport::new(800.0, 600.0),
+            reveal_caret: false,
+            config,
             max_glyphs,
             atlas_size: 1024, // Larger atlas for better performance
-            buffer_size: 4,
         })
     }
     
     pub fn initialize(&mut self) -> Result<(), JsValue> {
         console_log!("Initializing FastTextRenderer GPU resources");
         
-        // Pre-populate SDF atlas with common characters
-        let common_chars = "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789!@#$%^&*()_+-=[]{}|;:'\",.<>?/ ";
-        self.generate_sdf_atlas(common_chars)?;
-        
-        // Create persistent vertex buffer (ring buffer style)
-        let vertex_buffer_size = self.max_glyphs * 6 * 4 * 4; // 6 vertices * 4 floats * 4 bytes
-        let vertex_buffer = self.device.create_buffer(&{
+        // Screen size, atlas size, scroll offset and glyph scale for the glyph vertex shader
+        let globals_buffer = self.device.create_buffer(&{
             let mut desc = web_sys::GpuBufferDescriptor::new(
-                vertex_buffer_size as f64,
-                web_sys::gpu_buffer_usage::VERTEX | web_sys::gpu_buffer_usage::COPY_DST,
+                GLOBALS_SIZE as f64,
+                web_sys::gpu_buffer_usage::UNIFORM | web_sys::gpu_buffer_usage::COPY_DST,
             );
-            desc.set_label("Persistent Vertex Buffer");
+            desc.set_label("Fast Text Globals");
             desc.set_mapped_at_creation(false);
             desc
         })?;
         
-        self.vertex_buffer = Some(vertex_buffer);
+        self.globals_buffer = Some(globals_buffer);
         
-        // Create glyph instance buffer
-        let instance_buffer_size = self.max_glyphs * 16; // 4 floats per instance
+        // Create glyph instance buffer, read per instance by the render pipeline
+        let instance_buffer_size = self.max_glyphs * INSTANCE_STRIDE;
         let instance_buffer = self.device.create_buffer(&{
             let mut desc = web_sys::GpuBufferDescriptor::new(
                 instance_buffer_size as f64,
-                web_sys::gpu_buffer_usage::STORAGE | web_sys::gpu_buffer_usage::COPY_DST,
+                web_sys::gpu_buffer_usage::VERTEX | web_sys::gpu_buffer_usage::STORAGE | web_sys::gpu_buffer_usage::COPY_DST,
             );
             desc.set_label("Glyph Instance Buffer");
             desc.set_mapped_at_creation(false);
@@ -149,125 +248,75 @@ impl FastTextRenderer {
         })?;
         
         self.glyph_buffer = Some(GlyphInstanceBuffer {
-            buffer: instance_buffer,
-            capacity: self.max_glyphs,
+            buffer: instance_buffer.clone(),
             used: 0,
         });
         
-        // Create position compute buffer
-        let position_buffer = self.device.create_buffer(&{
-            let mut desc = web_sys::GpuBufferDescriptor::new(
-                (self.max_glyphs * 8) as f64, // 2 floats per position
-                web_sys::gpu_buffer_usage::STORAGE | web_sys::gpu_buffer_usage::COPY_DST,
-            );
-            desc.set_label("Position Buffer");
-            desc.set_mapped_at_creation(false);
-            desc
-        })?;
-        
-        self.position_buffer = Some(position_buffer);
-        
-        // Create compute pipeline for glyph positioning
-        self.create_compute_pipeline()?;
+        // Compute pass that lays glyphs out along their lines straight into the instance buffer.
+        // The stream has one entry per shaped glyph, so leave room for whitespace.
+        let positioner = GlyphPositioner::new(&self.device, &instance_buffer, self.max_glyphs * 2)?;
+        self.positioner = Some(positioner);
         
         // Create render pipeline
         self.create_render_pipeline()?;
         
-        // Create texture and bind group after atlas is populated
+        // Solid rect pipeline for the caret and selection highlights
+        self.rect_renderer = Some(RectRenderer::new(&self.device, 1024)?);
+        
+        // Create the atlas texture and bind group, then pre-populate common characters
         self.create_texture_and_bind_group()?;
+        let common_chars = "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789!@#$%^&*()_+-=[]{}|;:'\",.<>?/ ";
+        self.generate_sdf_atlas(common_chars)?;
         
         console_log!("FastTextRenderer initialized successfully");
         Ok(())
     }
     
-    fn create_compute_pipeline(&mut self) -> Result<(), JsValue> {
-        let compute_shader = self.device.create_shader_module(&{
-            let mut desc = web_sys::GpuShaderModuleDescriptor::new(r#"
-@group(0) @binding(0) var<storage, read> glyph_data: array<vec4<f32>>;
-@group(0) @binding(1) var<storage, read_write> positions: array<vec2<f32>>;
-
-@compute @workgroup_size(64)
-fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
-    let index = global_id.x;
-    if (index >= arrayLength(&glyph_data)) {
-        return;
-    }
-    
-    let glyph = glyph_data[index];
-    let char_code = u32(glyph.x);
-    let x_offset = glyph.y;
-    let y_offset = glyph.z;
-    let scale = glyph.w;
-    
-    // Calculate position based on character metrics
-    // This runs in parallel on GPU for all glyphs
-    positions[index] = vec2<f32>(x_offset, y_offset);
-}
-"#);
-            desc.set_label("Glyph Positioning Compute Shader");
-            desc
-        });
-        
-        // Create bind group layout entries
-        let entries = js_sys::Array::new();
-        
-        // Glyph data buffer (read-only storage)
-        let glyph_entry = web_sys::GpuBindGroupLayoutEntry::new(0, web_sys::gpu_shader_stage::COMPUTE);
-        let glyph_buffer_layout = web_sys::GpuBufferBindingLayout::new();
-        glyph_buffer_layout.set_type(web_sys::GpuBufferBindingType::ReadOnlyStorage);
-        glyph_entry.set_buffer(&glyph_buffer_layout);
-        entries.push(&glyph_entry);
-        
-        // Position buffer (read-write storage)
-        let position_entry = web_sys::GpuBindGroupLayoutEntry::new(1, web_sys::gpu_shader_stage::COMPUTE);
-        let position_buffer_layout = web_sys::GpuBufferBindingLayout::new();
-        position_buffer_layout.set_type(web_sys::GpuBufferBindingType::Storage);
-        position_entry.set_buffer(&position_buffer_layout);
-        entries.push(&position_entry);
-        
-        // Create bind group layout
-        let bind_group_layout_desc = web_sys::GpuBindGroupLayoutDescriptor::new(&entries);
-        bind_group_layout_desc.set_label("Compute Bind Group Layout");
-        let bind_group_layout = self.device.create_bind_group_layout(&bind_group_layout_desc)?;
-        
-        // Create pipeline layout
-        let layouts = js_sys::Array::new();
-        layouts.push(&bind_group_layout);
-        let pipeline_layout_desc = web_sys::GpuPipelineLayoutDescriptor::new(&layouts);
-        pipeline_layout_desc.set_label("Compute Pipeline Layout");
-        let pipeline_layout = self.device.create_pipeline_layout(&pipeline_layout_desc);
-        
-        // Create compute stage
-        let compute_stage = web_sys::GpuProgrammableStage::new(&compute_shader);
-        compute_stage.set_entry_point("main");
-        
-        // Create compute pipeline
-        let compute_pipeline_desc = web_sys::GpuComputePipelineDescriptor::new(&pipeline_layout, &compute_stage);
-        compute_pipeline_desc.set_label("Glyph Positioning Pipeline");
-        let compute_pipeline = self.device.create_compute_pipeline(&compute_pipeline_desc);
-        
-        self.compute_pipeline = Some(compute_pipeline);
-        Ok(())
-    }
-    
     fn create_render_pipeline(&mut self) -> Result<(), JsValue> {
         let vertex_shader = self.device.create_shader_module(&{
             let mut desc = web_sys::GpuShaderModuleDescriptor::new(r#"
-struct VertexInput {
+struct Globals {
+    screen_size: vec2<f32>,
+    atlas_size: vec2<f32>,
+    // Glyph positions are in document space; this moves them to where the text is scrolled
+    scroll: vec2<f32>,
+    // Screen pixels per atlas texel
+    glyph_scale: f32,
+}
+
+@group(0) @binding(2) var<uniform> globals: Globals;
+
+struct GlyphInstance {
     @location(0) position: vec2<f32>,
-    @location(1) tex_coord: vec2<f32>,
+    @location(1) atlas_rect: vec4<u32>,
+    @location(2) color: vec4<f32>,
+    @location(3) page: u32,
 }
 
 struct VertexOutput {
     @builtin(position) position: vec4<f32>,
     @location(0) tex_coord: vec2<f32>,
+    @location(1) color: vec4<f32>,
+    @location(2) @interpolate(flat) page: u32,
 }
 
 @vertex
-fn main(input: VertexInput) -> VertexOutput {
+fn main(@builtin(vertex_index) vertex_index: u32, glyph: GlyphInstance) -> VertexOutput {
+    // Triangle strip corners: (0,0) (1,0) (0,1) (1,1)
+    let corner = vec2<f32>(f32(vertex_index & 1u), f32(vertex_index >> 1u));
+    let size = vec2<f32>(glyph.atlas_rect.zw);
+    let pixel = glyph.position - globals.scroll + corner * size * globals.glyph_scale;
+    
     var output: VertexOutput;
-    output.position = vec4<f32>(input.position, 0.0, 1.0);
-    output.tex_coord = input.tex_coord;
+    output.position = vec4<f32>(
+        pixel.x / globals.screen_size.x * 2.0 - 1.0,
+        1.0 - pixel.y / globals.screen_size.y * 2.0,
+        0.0,
+        1.0,
+    );
+    output.tex_coord = (vec2<f32>(glyph.atlas_rect.xy) + corner * size) / globals.atlas_size;
+    output.color = glyph.color;
+    output.page = glyph.page;
     return output;
 }
 "#);
@@ -276,13 +325,22 @@ fn main(input: VertexInput) -> VertexOutput {
         });
         
         let fragment_shader = self.device.create_shader_module(&{
-            let mut desc = web_sys::GpuShaderModuleDescriptor::new(r#"
-@group(0) @binding(0) var sdf_texture: texture_2d<f32>;
+            let mut desc = web_sys::GpuShaderModuleDescriptor::new(&r#"
+@group(0) @binding(0) var sdf_texture: texture_2d_array<f32>;
 @group(0) @binding(1) var sdf_sampler: sampler;
 
+// MSDF channels agree on the outline except near corners, where the median picks the right one
+fn median3(v: vec3<f32>) -> f32 {
+    return max(min(v.r, v.g), min(max(v.r, v.g), v.b));
+}
+
 @fragment
-fn main(@location(0) tex_coord: vec2<f32>) -> @location(0) vec4<f32> {
-    let distance = textureSample(sdf_texture, sdf_sampler, tex_coord).r;
+fn main(
+    @location(0) tex_coord: vec2<f32>,
+    @location(1) color: vec4<f32>,
+    @location(2) @interpolate(flat) page: u32,
+) -> @location(0) vec4<f32> {
+    let distance = SAMPLE_DISTANCE;
     
     // Dynamic width based on derivatives for better quality at all scales
     var width = fwidth(distance);
@@ -298,9 +356,9 @@ fn main(@location(0) tex_coord: vec2<f32>) -> @location(0) vec4<f32> {
         discard;
     }
     
-    return vec4<f32>(1.0, 1.0, 1.0, alpha); // White text
+    return vec4<f32>(color.rgb, color.a * alpha);
 }
-"#);
+"#.replace("SAMPLE_DISTANCE", self.glyph_mode.sample_distance()));
             desc.set_label("Fast Text Fragment Shader");
             desc
         });
@@ -311,6 +369,7 @@ fn main(@location(0) tex_coord: vec2<f32>) -> @location(0) vec4<f32> {
         // SDF texture binding
         let texture_entry = web_sys::GpuBindGroupLayoutEntry::new(0, web_sys::gpu_shader_stage::FRAGMENT);
         let texture_binding = web_sys::GpuTextureBindingLayout::new();
+        texture_binding.set_view_dimension(web_sys::GpuTextureViewDimension::N2dArray);
         texture_entry.set_texture(&texture_binding);
         fragment_entries.push(&texture_entry);
         
@@ -320,6 +379,13 @@ fn main(@location(0) tex_coord: vec2<f32>) -> @location(0) vec4<f32> {
         sampler_entry.set_sampler(&sampler_binding);
         fragment_entries.push(&sampler_entry);
         
+        // Screen and atlas size for the vertex shader
+        let globals_entry = web_sys::GpuBindGroupLayoutEntry::new(2, web_sys::gpu_shader_stage::VERTEX);
+        let globals_binding = web_sys::GpuBufferBindingLayout::new();
+        globals_binding.set_type(web_sys::GpuBufferBindingType::Uniform);
+        globals_entry.set_buffer(&globals_binding);
+        fragment_entries.push(&globals_entry);
+        
         let bind_group_layout_desc = web_sys::GpuBindGroupLayoutDescriptor::new(&fragment_entries);
         bind_group_layout_desc.set_label("Fast Text Bind Group Layout");
         let bind_group_layout = self.device.create_bind_group_layout(&bind_group_layout_desc)?;
@@ -331,16 +397,20 @@ fn main(@location(0) tex_coord: vec2<f32>) -> @location(0) vec4<f32> {
         pipeline_layout_desc.set_label("Fast Text Pipeline Layout");
         let pipeline_layout = self.device.create_pipeline_layout(&pipeline_layout_desc);
         
-        // Create vertex attributes
+        // Create per-instance glyph attributes
         let vertex_attributes = js_sys::Array::new();
         let pos_attr = web_sys::GpuVertexAttribute::new(web_sys::GpuVertexFormat::Float32x2, 0.0, 0);
         vertex_attributes.push(&pos_attr);
-        let tex_attr = web_sys::GpuVertexAttribute::new(web_sys::GpuVertexFormat::Float32x2, 8.0, 1);
-        vertex_attributes.push(&tex_attr);
+        let rect_attr = web_sys::GpuVertexAttribute::new(web_sys::GpuVertexFormat::Uint16x4, 8.0, 1);
+        vertex_attributes.push(&rect_attr);
+        let color_attr = web_sys::GpuVertexAttribute::new(web_sys::GpuVertexFormat::Unorm8x4, 16.0, 2);
+        vertex_attributes.push(&color_attr);
+        let page_attr = web_sys::GpuVertexAttribute::new(web_sys::GpuVertexFormat::Uint32, 20.0, 3);
+        vertex_attributes.push(&page_attr);
         
-        // Create vertex buffer layout
-        let vertex_buffer_layout = web_sys::GpuVertexBufferLayout::new(16.0, &vertex_attributes);
-        vertex_buffer_layout.set_step_mode(web_sys::GpuVertexStepMode::Vertex);
+        // One record per glyph; the quad corners come from the vertex index
+        let vertex_buffer_layout = web_sys::GpuVertexBufferLayout::new(INSTANCE_STRIDE as f64, &vertex_attributes);
+        vertex_buffer_layout.set_step_mode(web_sys::GpuVertexStepMode::Instance);
         
         let vertex_buffers = js_sys::Array::new();
         vertex_buffers.push(&vertex_buffer_layout);
@@ -374,7 +444,7 @@ fn main(@location(0) tex_coord: vec2<f32>) -> @location(0) vec4<f32> {
         
         // Create primitive state
         let primitive = web_sys::GpuPrimitiveState::new();
-        primitive.set_topology(web_sys::GpuPrimitiveTopology::TriangleList);
+        primitive.set_topology(web_sys::GpuPrimitiveTopology::TriangleStrip);
         
         // Create render pipeline
         let render_pipeline_desc = web_sys::GpuRenderPipelineDescriptor::new(&pipeline_layout, &vertex_state);
@@ -388,209 +458,403 @@ fn main(@location(0) tex_coord: vec2<f32>) -> @location(0) vec4<f32> {
         Ok(())
     }
     
-    pub fn update_text(&mut self, new_text: &str) -> Result<(), JsValue> {
-        // Calculate dirty regions by comparing with previous text
-        self.calculate_dirty_regions(new_text);
+    pub fn update_text(&mut self, new_text: &DocumentSnapshot) -> Result<(), JsValue> {
+        // Drawing the same revision again (render and render_text both call this) is free
+        if new_text.revision() == self.last_snapshot.revision() {
+            return Ok(());
+        }
         
-        // Only update changed regions
-        if !self.dirty_regions.is_empty() {
-            self.update_dirty_regions(new_text)?;
+        // The spans the edits since the last text touched, applied to the GPU buffers on
+        // the next render once the layout is current. Spans not yet uploaded are folded
+        // together so they stay in one coordinate space.
+        for change in new_text.changes_since(&self.last_snapshot) {
+            let merged = match self.dirty_region {
+                Some(pending) => pending.merge(change),
+                None => change,
+            };
+            console_log!("Dirty region: chars {}..{} -> {}..{}", merged.start, merged.old_end, merged.start, merged.new_end);
+            self.dirty_region = Some(merged);
         }
         
-        self.last_text = new_text.to_string();
+        self.last_snapshot = new_text.clone();
         Ok(())
     }
     
-    fn calculate_dirty_regions(&mut self, new_text: &str) {
-        self.dirty_regions.clear();
+    // Map the pending dirty region onto the persistent instance buffer and write only the
+    // glyph records that changed. Glyphs keep their slots through the indirection table,
+    // whether the text around them was edited or scrolling brought other rows into view,
+    // so only glyphs that actually changed on screen are rewritten.
+    fn update_dirty_regions(&mut self) -> Result<(), JsValue> {
+        let records = self.collect_glyph_records();
+        let glyph_chars: Vec<usize> = records.iter().map(|record| record.char_idx).collect();
+        
+        // Where the glyphs in the buffer are in the current text; the ones whose chars
+        // were replaced have no place there
+        let region = self.dirty_region.take();
+        let previous: Vec<Option<usize>> = self.glyph_chars.iter()
+            .map(|&c| match region {
+                Some(region) if c >= region.start && c < region.old_end => None,
+                Some(region) => Some(region.map(c)),
+                None => Some(c),
+            })
+            .collect();
         
-        let old_chars: Vec<char> = self.last_text.chars().collect();
-        let new_chars: Vec<char> = new_text.chars().collect();
+        // Walk both char-ordered lists: a glyph at the same char as a previous one takes
+        // over its slot, every other previous glyph is dropped and every other new glyph
+        // gets a free slot
+        let (mut old, mut new, mut logical) = (0, 0, 0);
+        while old < previous.len() || new < glyph_chars.len() {
+            let (old_start, new_start) = (old, new);
+            loop {
+                match (previous.get(old), glyph_chars.get(new)) {
+                    (Some(Some(a)), Some(b)) if a == b => break,
+                    (Some(None), _) | (Some(_), None) => old += 1,
+                    (Some(Some(a)), Some(b)) if a < b => old += 1,
+                    (_, Some(_)) => new += 1,
+                    (None, None) => break,
+                }
+            }
+            if old > old_start || new > new_start {
+                for slot in self.glyph_slots.splice(logical..logical + old - old_start, new - new_start) {
+                    self.instance_mirror.clear(slot);
+                }
+                logical += new - new_start;
+            }
+            if old < previous.len() && new < glyph_chars.len() {
+                (old, new, logical) = (old + 1, new + 1, logical + 1);
+            }
+        }
         
-        let mut start = 0;
-        let mut end = 0;
-        let mut in_dirty_region = false;
+        // Full rebuild if the slot table no longer matches the layout
+        if self.glyph_slots.glyph_count() != glyph_chars.len() {
+            console_log!("Glyph slot table out of sync, rebuilding");
+            self.glyph_slots.reset();
+            self.glyph_slots.splice(0..0, glyph_chars.len());
+            self.instance_mirror.invalidate();
+        }
         
-        let max_len = old_chars.len().max(new_chars.len());
+        // Positions come from the compute pass when the stream fits its buffers,
+        // otherwise from the CPU reference, which gives the same results
+        let (origin_x, _) = self.layout.origin();
+        let mut clusters = self.build_clusters();
+        for (record, &slot) in records.iter().zip(self.glyph_slots.slots()) {
+            let cluster = &mut clusters[record.cluster];
+            cluster.slot = slot;
+            cluster.offset_x = record.offset_x;
+            cluster.top = record.top;
+        }
+        let cpu_positions = match self.positioner.as_mut() {
+            Some(positioner) if clusters.len() <= positioner.capacity() => {
+                positioner.upload(&clusters, origin_x)?;
+                None
+            }
+            Some(positioner) => {
+                console_log!("{} chars exceed GPU positioning capacity, positioning on CPU", clusters.len());
+                positioner.upload(&[], origin_x)?;
+                Some(cpu_positions(&clusters, origin_x))
+            }
+            None => Some(cpu_positions(&clusters, origin_x)),
+        };
         
-        for i in 0..max_len {
-            let old_char = old_chars.get(i);
-            let new_char = new_chars.get(i);
-            
-            if old_char != new_char {
-                if !in_dirty_region {
-                    start = i;
-                    in_dirty_region = true;
-                }
-                end = i;
-            } else if in_dirty_region {
-                self.dirty_regions.push(DirtyRegion {
-                    start_char: start,
-                    end_char: end + 1,
-                    needs_update: true,
-                });
-                in_dirty_region = false;
+        for (record, &slot) in records.iter().zip(self.glyph_slots.slots()) {
+            if slot == NO_SLOT {
+                continue;
+            }
+            let mut instance = record.instance;
+            if let Some(positions) = cpu_positions.as_ref() {
+                let [x, y] = positions[record.cluster];
+                instance[0] = x.to_bits();
+                instance[1] = y.to_bits();
             }
+            self.instance_mirror.write(slot, &instance);
         }
         
-        if in_dirty_region {
-            self.dirty_regions.push(DirtyRegion {
-                start_char: start,
-                end_char: end + 1,
-                needs_update: true,
-            });
+        let queue = self.device.queue();
+        if let Some(glyph_buffer) = self.glyph_buffer.as_mut() {
+            let ranges = self.instance_mirror.take_dirty_ranges();
+            if !ranges.is_empty() {
+                console_log!("Uploading {} dirty instance ranges", ranges.len());
+            }
+            for range in ranges {
+                let (offset, bytes) = self.instance_mirror.range_bytes(&range);
+                queue.write_buffer_with_u32_and_u8_slice(&glyph_buffer.buffer, offset, &bytes)?;
+            }
+            glyph_buffer.used = self.glyph_slots.slot_count() as usize;
         }
         
-        console_log!("Found {} dirty regions", self.dirty_regions.len());
-    }
-    
-    fn update_dirty_regions(&mut self, text: &str) -> Result<(), JsValue> {
-        // This would update only the changed character data
-        // For now, simplified implementation
-        console_log!("Updating dirty regions for text: {}", text);
+        self.glyph_chars = glyph_chars;
         Ok(())
     }
     
+    // Add the glyphs the chars in `text` resolve to through the font chain
     pub fn generate_sdf_atlas(&mut self, text: &str) -> Result<(), JsValue> {
-        console_log!("Generating SDF atlas for: '{}'", text);
-        
-        let atlas_size = (self.atlas_size * self.atlas_size) as usize;
-        let mut atlas_data = vec![128u8; atlas_size]; // Initialize with middle gray
-        
-        let mut unique_chars: Vec<char> = text.chars().filter(|c| !c.is_whitespace()).collect();
-        unique_chars.sort();
-        unique_chars.dedup();
-        
-        console_log!("Processing {} unique characters", unique_chars.len());
-        
-        let char_size = 64;
-        let chars_per_row = self.atlas_size / char_size;
+        let keys: Vec<GlyphKey> = text.chars()
+            .filter(|c| !c.is_whitespace())
+            .map(|c| {
+                let (font, glyph) = self.fonts.resolve(c);
+                GlyphKey { font: font.id, glyph }
+            })
+            .collect();
+        self.add_glyphs(keys)
+    }
+    
+    // Rasterize the glyphs that aren't in the atlas yet and upload only their
+    // rectangles. Glyphs already in the atlas keep their coordinates.
+    fn add_glyphs(&mut self, mut keys: Vec<GlyphKey>) -> Result<(), JsValue> {
+        keys.retain(|key| !self.glyph_map.contains_key(key));
+        keys.sort();
+        keys.dedup();
+        if keys.is_empty() {
+            return Ok(());
+        }
         
-        self.glyph_map.clear();
+        console_log!("Adding {} glyphs to SDF atlas", keys.len());
         
-        for (i, &ch) in unique_chars.iter().enumerate() {
-            let char_x = (i as u32 % chars_per_row) * char_size;
-            let char_y = (i as u32 / chars_per_row) * char_size;
+        for key in keys {
+            let Some(font) = self.fonts.get(key.font) else {
+                continue;
+            };
+            let (metrics, bitmap) = font.font.rasterize_indexed(key.glyph, self.config.raster_size);
             
-            let (metrics, bitmap) = self.font.rasterize(ch, 12.0);
-            
-            if !bitmap.is_empty() && metrics.width > 0 && metrics.height > 0 {
-                // Generate SDF
-                let bitmap_glyph = match BitmapGlyph::from_unbuffered(&bitmap, metrics.width, metrics.height, self.buffer_size) {
-                    Ok(glyph) => glyph,
-                    Err(e) => {
-                        console_log!("SDF glyph creation failed for '{}': {:?}", ch, e);
-                        continue;
-                    }
-                };
-                
-                let sdf_radius = 4.0;
-                let sdf_data = bitmap_glyph.render_sdf(sdf_radius as usize);
-                
-                let sdf_width = metrics.width + 2 * self.buffer_size;
-                let sdf_height = metrics.height + 2 * self.buffer_size;
-                
-                // Store glyph info
-                self.glyph_map.insert(ch, GlyphInfo {
-                    atlas_x: char_x as f32,
-                    atlas_y: char_y as f32,
-                    width: metrics.width as f32,
-                    height: metrics.height as f32,
-                    sdf_width: sdf_width as f32,
-                    sdf_height: sdf_height as f32,
+            // Glyphs with no ink get an empty entry so they aren't rasterized again
+            if bitmap.is_empty() || metrics.width == 0 || metrics.height == 0 {
+                self.glyph_map.insert(key, GlyphInfo {
+                    page: 0,
+                    atlas_x: 0.0,
+                    atlas_y: 0.0,
+                    height: 0.0,
+                    sdf_width: 0.0,
+                    sdf_height: 0.0,
+                    xmin: 0.0,
+                    ymin: 0.0,
                 });
-                
-                // Copy SDF data into atlas
-                let copy_width = sdf_width.min(char_size as usize);
-                let copy_height = sdf_height.min(char_size as usize);
-                
-                for y in 0..copy_height {
-                    for x in 0..copy_width {
-                        let atlas_x = char_x as usize + x;
-                        let atlas_y = char_y as usize + y;
-                        let atlas_idx = atlas_y * self.atlas_size as usize + atlas_x;
-                        
-                        if atlas_idx < atlas_data.len() {
-                            let sdf_idx = y * sdf_width + x;
-                            if sdf_idx < sdf_data.len() {
-                                let normalized_distance = sdf_data[sdf_idx] / sdf_radius;
-                                let sdf_value = ((normalized_distance + 1.0) * 127.5).clamp(0.0, 255.0) as u8;
-                                atlas_data[atlas_idx] = sdf_value;
-                            }
+                continue;
+            }
+            
+            let padding = self.config.sdf_padding;
+            let sdf_radius = self.config.sdf_radius as f64;
+            let sdf_width = metrics.width + 2 * padding;
+            let sdf_height = metrics.height + 2 * padding;
+            
+            let pixels = match self.glyph_mode {
+                GlyphMode::Sdf => {
+                    let bitmap_glyph = match BitmapGlyph::from_unbuffered(&bitmap, metrics.width, metrics.height, padding) {
+                        Ok(glyph) => glyph,
+                        Err(e) => {
+                            console_log!("SDF glyph creation failed for {:?}: {:?}", key, e);
+                            continue;
+                        }
+                    };
+                    bitmap_glyph.render_sdf(sdf_radius.ceil() as usize)
+                        .iter()
+                        .take(sdf_width * sdf_height)
+                        .map(|distance| ((distance / sdf_radius + 1.0) * 127.5).clamp(0.0, 255.0) as u8)
+                        .collect()
+                }
+                GlyphMode::Msdf => {
+                    // Same pixel box as the rasterized bitmap plus padding, so quads line up in both modes
+                    let bounds = MsdfBox {
+                        left: metrics.xmin as f32 - padding as f32,
+                        bottom: metrics.ymin as f32 - padding as f32,
+                        width: sdf_width,
+                        height: sdf_height,
+                    };
+                    match self.render_msdf(key, bounds, sdf_radius as f32) {
+                        Some(pixels) => pixels,
+                        None => {
+                            console_log!("MSDF generation failed for {:?}", key);
+                            continue;
                         }
                     }
                 }
+            };
+            
+            let Some(insertion) = self.glyph_atlas.insert(key, sdf_width as u32, sdf_height as u32) else {
+                console_log!("SDF atlas is full of glyphs in use, cannot add {:?}", key);
+                continue;
+            };
+            for evicted in &insertion.evicted {
+                self.glyph_map.remove(evicted);
+            }
+            for &region in &insertion.cleared {
+                self.clear_atlas_region(region)?;
+            }
+            if insertion.grew {
+                self.grow_atlas_texture()?;
             }
+            let slot = insertion.slot;
+            
+            self.upload_atlas_region(slot, &pixels)?;
+            
+            // Store glyph info
+            self.glyph_map.insert(key, GlyphInfo {
+                page: slot.page,
+                atlas_x: slot.rect.x as f32,
+                atlas_y: slot.rect.y as f32,
+                height: metrics.height as f32,
+                sdf_width: sdf_width as f32,
+                sdf_height: sdf_height as f32,
+                xmin: metrics.xmin as f32,
+                ymin: metrics.ymin as f32,
+            });
         }
         
-        self.sdf_atlas = Some(atlas_data);
-        console_log!("SDF atlas generated successfully");
         Ok(())
     }
     
-    pub fn render(&mut self, text: &str, x: f32, y: f32, screen_width: f32, screen_height: f32, context: &crate::gpu::context::GpuContext) -> Result<(), JsValue> {
+    fn render_msdf(&self, key: GlyphKey, bounds: MsdfBox, range: f32) -> Option<Vec<u8>> {
+        let font = self.fonts.get(key.font)?;
+        generate_msdf(&font.face(), ttf_parser::GlyphId(key.glyph), self.config.raster_size, bounds, range)
+    }
+    
+    // Reset an evicted atlas region to the empty distance. A new glyph may be smaller than
+    // the one it replaces, and filtering at its edge would otherwise pick up old texels.
+    fn clear_atlas_region(&self, slot: AtlasSlot) -> Result<(), JsValue> {
+        let texels = slot.rect.width * slot.rect.height * self.glyph_mode.bytes_per_texel();
+        self.upload_atlas_region(slot, &vec![EMPTY_TEXEL; texels as usize])
+    }
+    
+    // Write one glyph's texels into its atlas rectangle
+    fn upload_atlas_region(&self, slot: AtlasSlot, pixels: &[u8]) -> Result<(), JsValue> {
+        let rect = slot.rect;
+        let texture = self.atlas_texture.as_ref()
+            .ok_or_else(|| JsValue::from_str("Atlas texture must be created before uploading glyphs"))?;
+        
+        let destination = web_sys::GpuTexelCopyTextureInfo::new(texture);
+        let origin = web_sys::GpuOrigin3dDict::new();
+        origin.set_x(rect.x);
+        origin.set_y(rect.y);
+        origin.set_z(slot.page);
+        destination.set_origin(&origin);
+        
+        let data_layout = web_sys::GpuTexelCopyBufferLayout::new();
+        data_layout.set_bytes_per_row(rect.width * self.glyph_mode.bytes_per_texel());
+        data_layout.set_rows_per_image(rect.height);
+        
+        let copy_size = web_sys::GpuExtent3dDict::new(rect.width);
+        copy_size.set_height(rect.height);
+        copy_size.set_depth_or_array_layers(1);
+        
+        self.device.queue().write_texture_with_u8_slice_and_gpu_extent_3d_dict(
+            &destination,
+            pixels,
+            &data_layout,
+            &copy_size,
+        )
+    }
+    
+    pub fn render(&mut self, text: &DocumentSnapshot, selection: Selection, context: &crate::gpu::context::GpuContext) -> Result<(), JsValue> {
         // Update text if changed
-        if text != self.last_text {
-            self.update_text(text)?;
-        }
+        self.update_text(text)?;
         
         // Use the main render_text function
-        self.render_text(text, x, y, screen_width, screen_height, context)
+        self.render_text(text, selection, context)
     }
     
+    // Draw the part of `text` inside the viewport
     pub fn render_text(
         &mut self,
-        text: &str,
-        x: f32,
-        y: f32,
-        screen_width: f32,
-        screen_height: f32,
+        text: &DocumentSnapshot,
+        selection: Selection,
         context: &crate::gpu::context::GpuContext,
     ) -> Result<(), JsValue> {
         // Update text if changed
-        if text != self.last_text {
-            self.update_text(text)?;
+        self.update_text(text)?;
+        
+        // Lay out lines around the viewport, reusing cached runs for unchanged ones, and
+        // follow the caret if it moved out of view
+        self.lay_out(text, selection.head);
+        if std::mem::take(&mut self.reveal_caret)
+            && let Some(position) = self.layout.caret_position(selection.head)
+        {
+            let (left, top) = self.viewport.to_canvas(position.x.min(position.cell_x), position.top);
+            let (right, bottom) = self.viewport.to_canvas(position.x.max(position.cell_x + position.advance), position.top + position.height);
+            self.viewport.scroll_into_view(left, top, right, bottom);
+            self.lay_out(text, selection.head);
         }
-        
-        // Only regenerate atlas if we have new characters not in the pre-populated set
-        if !text.chars().all(|c| c.is_whitespace() || self.glyph_map.contains_key(&c)) {
-            console_log!("Extending SDF atlas for new characters in text: '{}'", text);
-            self.generate_sdf_atlas(text)?;
-            self.atlas_texture = None;
-            self.bind_group = None;
-            self.create_texture_and_bind_group()?;
+        let (screen_width, screen_height) = (self.viewport.width, self.viewport.height);
+        
+        // Mark glyphs in use so eviction spares them, and add the ones not in the atlas yet
+        self.glyph_atlas.begin_frame();
+        let mut missing = Vec::new();
+        for glyph in self.layout.lines()[self.drawn_rows()].iter().flat_map(|line| &line.run.glyphs) {
+            if self.glyph_atlas.touch(&glyph.key).is_none() && !self.glyph_map.contains_key(&glyph.key) {
+                missing.push(glyph.key);
+            }
         }
+        self.add_glyphs(missing)?;
         
-        // Generate vertices for text
-        let vertices = self.generate_text_vertices(text, x, y, screen_width, screen_height);
-        let vertex_count = vertices.len() / 4;
-        
-        console_log!("Generated {} vertices for text: '{}'", vertex_count, text);
-        
-        if vertex_count == 0 {
-            console_log!("No vertices generated, returning early");
-            return Ok(());
-        }
+        // Write only the glyph instances that changed into the persistent buffer
+        self.update_dirty_regions()?;
+        let instance_count = self.glyph_slots.slot_count();
         
         // Atlas and bind group already created above
         
         let pipeline = self.render_pipeline.as_ref()
             .ok_or_else(|| JsValue::from_str("Render pipeline not created"))?;
         
-        // Use persistent vertex buffer
-        let vertex_buffer = self.vertex_buffer.as_ref()
-            .ok_or_else(|| JsValue::from_str("Persistent vertex buffer not initialized"))?;
+        let glyph_buffer = self.glyph_buffer.as_ref()
+            .ok_or_else(|| JsValue::from_str("Glyph instance buffer not initialized"))?;
+        
+        if let Some(globals_buffer) = self.globals_buffer.as_ref() {
+            let mut globals = [0.0f32; GLOBALS_SIZE / 4];
+            let (scroll_x, scroll_y) = self.viewport.scroll();
+            globals[..7].copy_from_slice(&[
+                screen_width,
+                screen_height,
+                self.atlas_size as f32,
+                self.atlas_size as f32,
+                scroll_x,
+                scroll_y,
+                self.config.glyph_scale(),
+            ]);
+            let bytes: Vec<u8> = globals.iter().flat_map(|f| f.to_le_bytes()).collect();
+            self.device.queue().write_buffer_with_u32_and_u8_slice(globals_buffer, 0, &bytes)?;
+        }
         
-        let vertex_bytes: Vec<u8> = vertices.iter()
-            .flat_map(|&f| f.to_le_bytes())
+        // Selection highlights go first so they can be drawn behind the glyphs,
+        // followed by the caret, which is skipped during the "off" half of the blink cycle.
+        // The layout gives them in document space; they are scrolled here.
+        let viewport = &self.viewport;
+        let on_canvas = |(x, y, width, height): (f32, f32, f32, f32)| {
+            let (x, y) = viewport.to_canvas(x, y);
+            (x, y, width, height)
+        };
+        let visible = |&(_, y, _, height): &(f32, f32, f32, f32)| y < screen_height && y + height > 0.0;
+        let mut rects: Vec<Rect> = self.layout.selection_rects(selection.range())
+            .into_iter()
+            .map(on_canvas)
+            .filter(visible)
+            .map(|(x, y, width, height)| Rect { x, y, width, height, color: SELECTION_COLOR })
             .collect();
-        let vertex_data = unsafe {
-            js_sys::Uint8Array::view(&vertex_bytes)
+        let selection_rect_count = rects.len();
+        if let Some(preedit) = self.preedit_range() {
+            rects.extend(self.layout.selection_rects(preedit).into_iter().map(on_canvas).filter(visible).map(|(x, y, width, height)| Rect {
+                x,
+                y: y + height - PREEDIT_UNDERLINE_WIDTH,
+                width,
+                height: PREEDIT_UNDERLINE_WIDTH,
+                color: PREEDIT_UNDERLINE_COLOR,
+            }));
+        }
+        if self.caret.is_visible(js_sys::Date::now()) {
+            rects.extend(self.caret.rect(&self.layout, selection.head).map(|rect| {
+                let (x, y) = viewport.to_canvas(rect.x, rect.y);
+                Rect { x, y, ..rect }
+            }));
+        }
+        let rect_count = match self.rect_renderer.as_ref() {
+            Some(rect_renderer) => rect_renderer.upload(&rects, screen_width, screen_height)?,
+            None => 0,
         };
-        self.device.queue().write_buffer_with_u32_and_u8_array(vertex_buffer, 0, &vertex_data)?;
+        let selection_rect_count = selection_rect_count.min(rect_count);
+        let caret_rect_count = rect_count - selection_rect_count;
         
-        // Create command encoder and render pass
+        // Create command encoder; glyph positions are computed before the render pass reads them
         let command_encoder = self.device.create_command_encoder();
+        if let Some(positioner) = self.positioner.as_ref() {
+            positioner.encode(&command_encoder);
+        }
         
         let color_attachments = js_sys::Array::new();
         let clear_color = web_sys::GpuColorDict::new(0.1, 0.1, 0.1, 1.0); // Dark gray background
@@ -605,16 +869,43 @@ fn main(@location(0) tex_coord: vec2<f32>) -> @location(0) vec4<f32> {
         let render_pass_descriptor = web_sys::GpuRenderPassDescriptor::new(&color_attachments);
         let render_pass = command_encoder.begin_render_pass(&render_pass_descriptor)?;
         
+        // Translucent selection behind the text
+        if let Some(rect_renderer) = self.rect_renderer.as_ref() {
+            rect_renderer.draw(&render_pass, 0, selection_rect_count);
+        }
+        
         render_pass.set_pipeline(pipeline);
         
         if let Some(ref bind_group) = self.bind_group {
             render_pass.set_bind_group(0, Some(bind_group));
         }
         
-        render_pass.set_vertex_buffer(0, Some(vertex_buffer));
-        render_pass.draw(vertex_count as u32);
+        // One 4-vertex strip per glyph instance; free slots hold empty rects and draw nothing
+        if instance_count > 0 {
+            render_pass.set_vertex_buffer(0, Some(&glyph_buffer.buffer));
+            render_pass.draw_with_instance_count(4, instance_count);
+        }
         render_pass.end();
         
+        // Caret pass on top of the glyphs, loading the offscreen target instead of clearing it
+        if let Some(rect_renderer) = self.rect_renderer.as_ref()
+            && caret_rect_count > 0
+        {
+            let caret_attachments = js_sys::Array::new();
+            let caret_attachment = web_sys::GpuRenderPassColorAttachment::new(
+                web_sys::GpuLoadOp::Load,
+                web_sys::GpuStoreOp::Store,
+                &context.offscreen_view
+            );
+            caret_attachments.push(&caret_attachment);
+            
+            let caret_pass_descriptor = web_sys::GpuRenderPassDescriptor::new(&caret_attachments);
+            caret_pass_descriptor.set_label("Caret Pass");
+            let caret_pass = command_encoder.begin_render_pass(&caret_pass_descriptor)?;
+            rect_renderer.draw(&caret_pass, selection_rect_count, caret_rect_count);
+            caret_pass.end();
+        }
+        
         // Copy from offscreen texture to swapchain
         let swapchain_texture = context.context.get_current_texture()?;
         command_encoder.copy_texture_to_texture_with_u32_sequence(
@@ -638,210 +929,782 @@ fn main(@location(0) tex_coord: vec2<f32>) -> @location(0) vec4<f32> {
         Ok(())
     }
     
-    fn execute_cached_render(&self, _cached: &CachedRenderCommand, _context: &crate::gpu::context::GpuContext) -> Result<(), JsValue> {
-        // This function is deprecated - use render_text instead
-        console_log!("execute_cached_render is deprecated - use render_text instead");
-        Ok(())
+    // Create an empty single-page atlas texture and the bind group that samples it.
+    // Glyphs are written into it afterwards, one rectangle at a time.
+    pub fn create_texture_and_bind_group(&mut self) -> Result<(), JsValue> {
+        // Previously uploaded glyphs are gone with the old texture
+        self.glyph_map.clear();
+        self.glyph_atlas.reset();
+        
+        let texture = self.create_atlas_texture(self.glyph_atlas.page_count())?;
+        self.bind_atlas_texture(texture)
     }
     
-    pub fn create_texture_and_bind_group(&mut self) -> Result<(), JsValue> {
-        if let Some(ref atlas_data) = self.sdf_atlas {
-            let mut extent = web_sys::GpuExtent3dDict::new(self.atlas_size);
-            extent.set_height(self.atlas_size);
-            extent.set_depth_or_array_layers(1);
-            
-            let texture_desc = web_sys::GpuTextureDescriptor::new(
-                web_sys::GpuTextureFormat::R8unorm,
-                &extent,
-                web_sys::gpu_texture_usage::TEXTURE_BINDING | web_sys::gpu_texture_usage::COPY_DST,
-            );
-            
-            let texture = self.device.create_texture(&texture_desc)?;
-            
-            let data_layout = web_sys::GpuTexelCopyBufferLayout::new();
-            data_layout.set_bytes_per_row(self.atlas_size);
-            data_layout.set_rows_per_image(self.atlas_size);
-            
+    // Add layers to the atlas texture after the atlas gained a page, keeping existing glyphs
+    fn grow_atlas_texture(&mut self) -> Result<(), JsValue> {
+        let pages = self.glyph_atlas.page_count();
+        console_log!("Growing SDF atlas to {} pages", pages);
+        
+        let texture = self.create_atlas_texture(pages)?;
+        if let Some(old_texture) = self.atlas_texture.as_ref() {
             let mut copy_size = web_sys::GpuExtent3dDict::new(self.atlas_size);
             copy_size.set_height(self.atlas_size);
-            copy_size.set_depth_or_array_layers(1);
-            
-            let destination = web_sys::GpuTexelCopyTextureInfo::new(&texture);
+            copy_size.set_depth_or_array_layers(old_texture.depth_or_array_layers());
             
-            self.device.queue().write_texture_with_u8_slice_and_gpu_extent_3d_dict(
-                &destination,
-                atlas_data.as_slice(),
-                &data_layout,
+            let command_encoder = self.device.create_command_encoder();
+            command_encoder.copy_texture_to_texture_with_gpu_extent_3d_dict(
+                &web_sys::GpuTexelCopyTextureInfo::new(old_texture),
+                &web_sys::GpuTexelCopyTextureInfo::new(&texture),
                 &copy_size,
             )?;
+            self.device.queue().submit(&js_sys::Array::of1(&command_encoder.finish()));
+        }
+        self.bind_atlas_texture(texture)
+    }
+    
+    fn create_atlas_texture(&self, pages: u32) -> Result<web_sys::GpuTexture, JsValue> {
+        let mut extent = web_sys::GpuExtent3dDict::new(self.atlas_size);
+        extent.set_height(self.atlas_size);
+        extent.set_depth_or_array_layers(pages);
+        
+        let texture_desc = web_sys::GpuTextureDescriptor::new(
+            self.glyph_mode.texture_format(),
+            &extent,
+            web_sys::gpu_texture_usage::TEXTURE_BINDING
+                | web_sys::gpu_texture_usage::COPY_DST
+                | web_sys::gpu_texture_usage::COPY_SRC,
+        );
+        texture_desc.set_label("SDF Atlas");
+        
+        let texture = self.device.create_texture(&texture_desc)?;
+        
+        // Fill with the "far outside" distance so filtering at glyph edges fades to nothing
+        let bytes_per_texel = self.glyph_mode.bytes_per_texel();
+        let atlas_data = vec![EMPTY_TEXEL; (self.atlas_size * self.atlas_size * pages * bytes_per_texel) as usize];
+        
+        let data_layout = web_sys::GpuTexelCopyBufferLayout::new();
+        data_layout.set_bytes_per_row(self.atlas_size * bytes_per_texel);
+        data_layout.set_rows_per_image(self.atlas_size);
+        
+        let destination = web_sys::GpuTexelCopyTextureInfo::new(&texture);
+        
+        self.device.queue().write_texture_with_u8_slice_and_gpu_extent_3d_dict(
+            &destination,
+            atlas_data.as_slice(),
+            &data_layout,
+            &extent,
+        )?;
+        
+        Ok(texture)
+    }
+    
+    fn bind_atlas_texture(&mut self, texture: web_sys::GpuTexture) -> Result<(), JsValue> {
+        let sampler_desc = web_sys::GpuSamplerDescriptor::new();
+        sampler_desc.set_mag_filter(web_sys::GpuFilterMode::Linear);
+        sampler_desc.set_min_filter(web_sys::GpuFilterMode::Linear);
+        sampler_desc.set_mipmap_filter(web_sys::GpuMipmapFilterMode::Linear);
+        let sampler = self.device.create_sampler_with_descriptor(&sampler_desc);
+        
+        // Every page is sampled through one array view, even while there is only one
+        let view_desc = web_sys::GpuTextureViewDescriptor::new();
+        view_desc.set_dimension(web_sys::GpuTextureViewDimension::N2dArray);
+        let view = texture.create_view_with_descriptor(&view_desc)?;
+        
+        // Create bind group for fragment shader
+        let bind_entries = js_sys::Array::new();
+        
+        let texture_bind_entry = web_sys::GpuBindGroupEntry::new(0, &view.into());
+        bind_entries.push(&texture_bind_entry);
+        
+        let sampler_bind_entry = web_sys::GpuBindGroupEntry::new(1, &sampler);
+        bind_entries.push(&sampler_bind_entry);
+        
+        let globals_buffer = self.globals_buffer.as_ref()
+            .ok_or_else(|| JsValue::from_str("Globals buffer must be created before bind group"))?;
+        let globals_bind_entry = web_sys::GpuBindGroupEntry::new(2, &web_sys::GpuBufferBinding::new(globals_buffer));
+        bind_entries.push(&globals_bind_entry);
+        
+        // We need to get the bind group layout from the render pipeline
+        if let Some(ref pipeline) = self.render_pipeline {
+            let bind_group_layout = pipeline.get_bind_group_layout(0);
+            let bind_group_desc = web_sys::GpuBindGroupDescriptor::new(&bind_entries, &bind_group_layout);
+            let bind_group = self.device.create_bind_group(&bind_group_desc);
             
-            let sampler_desc = web_sys::GpuSamplerDescriptor::new();
-            sampler_desc.set_mag_filter(web_sys::GpuFilterMode::Linear);
-            sampler_desc.set_min_filter(web_sys::GpuFilterMode::Linear);
-            sampler_desc.set_mipmap_filter(web_sys::GpuMipmapFilterMode::Linear);
-            let sampler = self.device.create_sampler_with_descriptor(&sampler_desc);
-            
-            // Create bind group for fragment shader
-            let bind_entries = js_sys::Array::new();
-            
-            let texture_bind_entry = web_sys::GpuBindGroupEntry::new(0, &texture.create_view()?.into());
-            bind_entries.push(&texture_bind_entry);
-            
-            let sampler_bind_entry = web_sys::GpuBindGroupEntry::new(1, &sampler);
-            bind_entries.push(&sampler_bind_entry);
-            
-            // We need to get the bind group layout from the render pipeline
-            if let Some(ref pipeline) = self.render_pipeline {
-                let bind_group_layout = pipeline.get_bind_group_layout(0);
-                let bind_group_desc = web_sys::GpuBindGroupDescriptor::new(&bind_entries, &bind_group_layout);
-                let bind_group = self.device.create_bind_group(&bind_group_desc);
-                
-                self.atlas_texture = Some(texture);
-                self.bind_group = Some(bind_group);
-            } else {
-                return Err(JsValue::from_str("Render pipeline must be created before texture"));
-            }
+            self.atlas_texture = Some(texture);
+            self.bind_group = Some(bind_group);
+        } else {
+            return Err(JsValue::from_str("Render pipeline must be created before texture"));
         }
         
         Ok(())
     }
     
-    fn generate_text_vertices(&self, text: &str, x: f32, y: f32, screen_width: f32, screen_height: f32) -> Vec<f32> {
-        let mut layout = Layout::new(CoordinateSystem::PositiveYDown);
-        let fonts = &[&self.font];
-        
-        let mut layout_settings = LayoutSettings::default();
-        layout_settings.x = x;
-        layout_settings.y = y;
-        layout.reset(&layout_settings);
-        layout.append(fonts, &TextStyle::new(text, 12.0, 0));
-    
-        let mut vertices = Vec::new();
-        
-        for glyph in layout.glyphs() {
-            console_log!("Processing glyph: '{}' at position ({}, {})", glyph.parent, glyph.x, glyph.y);
-            if let Some(glyph_info) = self.glyph_map.get(&glyph.parent) {
-                console_log!("Found glyph info for: '{}' at atlas ({}, {})", glyph.parent, glyph_info.atlas_x, glyph_info.atlas_y);
-                // Use layout positions directly - fontdue handles baseline alignment
-                let glyph_left = glyph.x as f32;
-                let glyph_top = glyph.y as f32;
-                console_log!("Screen position: ({}, {})", glyph_left, glyph_top);
-                
-                // Account for the SDF buffer padding
-                let buffer_offset = self.buffer_size as f32;
-                let screen_left = glyph_left - buffer_offset;
-                let screen_top = glyph_top - buffer_offset;
-                let screen_right = screen_left + glyph_info.sdf_width;
-                let screen_bottom = screen_top + glyph_info.sdf_height;
-                
-                // Convert to NDC (-1 to 1 range)
-                let left = (screen_left / screen_width) * 2.0 - 1.0;
-                let right = (screen_right / screen_width) * 2.0 - 1.0;
-                let top = 1.0 - (screen_top / screen_height) * 2.0;
-                let bottom = 1.0 - (screen_bottom / screen_height) * 2.0;
-                
-                // UV coordinates
-                let u_left = glyph_info.atlas_x / self.atlas_size as f32;
-                let u_right = (glyph_info.atlas_x + glyph_info.sdf_width) / self.atlas_size as f32;
-                let v_top = glyph_info.atlas_y / self.atlas_size as f32;
-                let v_bottom = (glyph_info.atlas_y + glyph_info.sdf_height) / self.atlas_size as f32;
-    
-                // Two triangles for the quad
-                vertices.extend_from_slice(&[
-                    left, bottom,   u_left, v_bottom,
-                    right, bottom,  u_right, v_bottom,
-                    left, top,      u_left, v_top,
-                    
-                    right, bottom,  u_right, v_bottom,
-                    right, top,     u_right, v_top,
-                    left, top,      u_left, v_top,
-                ]);
+    // Rows drawn this frame: the ones inside the viewport
+    fn drawn_rows(&self) -> Range<usize> {
+        self.viewport.visible_rows(&self.layout)
+    }
+    
+    // Document-order glyph records of the drawn rows. Position words are left zero for the
+    // positioning pass. Glyphs missing from the atlas get an empty record so they keep their slot.
+    // Quads are placed from the atlas metrics scaled to the display size, so zooming
+    // never needs the glyphs rasterized again.
+    fn collect_glyph_records(&self) -> Vec<GlyphRecord> {
+        let scale = self.config.glyph_scale();
+        let padding = self.config.sdf_padding as f32;
+        let mut records = Vec::new();
+        let mut stream_base = 0;
+        
+        for line in &self.layout.lines()[self.drawn_rows()] {
+            let line_start = records.len();
+            for glyph in &line.run.glyphs {
+                let (offset_x, top) = match self.glyph_map.get(&glyph.key) {
+                    Some(info) => (
+                        glyph.offset_x + (info.xmin - padding) * scale,
+                        line.top + line.run.ascent + glyph.offset_y - (info.ymin + info.height + padding) * scale,
+                    ),
+                    None => (0.0, line.top),
+                };
+                records.push(GlyphRecord {
+                    char_idx: line.start_char + glyph.char_offset,
+                    cluster: stream_base + glyph.stream,
+                    offset_x,
+                    top,
+                    instance: self.glyph_instance(glyph.key),
+                });
             }
+            // Glyphs come in visual order; slots follow the document
+            records[line_start..].sort_by_key(|record| record.char_idx);
+            stream_base += line.run.advances.len();
         }
         
-        console_log!("Generated {} total vertices", vertices.len());
-        vertices
+        records
     }
     
-    fn generate_and_cache_render_command(&mut self, cache_key: &str, context: &crate::gpu::context::GpuContext) -> Result<(), JsValue> {
-        console_log!("Generating new render command for key: {}", cache_key);
-        
-        // Generate SDF atlas if needed
-        let current_text = self.last_text.clone();
-        if self.sdf_atlas.is_none() || !current_text.chars().all(|c| c.is_whitespace() || self.glyph_map.contains_key(&c)) {
-            self.generate_sdf_atlas(&current_text)?;
-            // Recreate texture with new atlas
-            self.atlas_texture = None;
-            self.bind_group = None;
-        }
-
-        if self.atlas_texture.is_none() {
-            self.create_texture_and_bind_group()?;
+    // Positioning stream with one cluster per shaped glyph of the drawn rows, restarting
+    // on every row
+    fn build_clusters(&self) -> Vec<Cluster> {
+        let mut clusters = Vec::new();
+        for line in &self.layout.lines()[self.drawn_rows()] {
+            clusters.extend(
+                line.run.advances.iter()
+                    .enumerate()
+                    .map(|(idx, &advance)| Cluster::new(advance, idx == 0)),
+            );
         }
-        
-        // Calculate vertex count based on text length
-        let text_len = self.last_text.len();
-        let vertex_count = (text_len * 6) as u32; // 6 vertices per character (2 triangles)
-        
-        // Generate new render command and cache it
-        let cached_command = CachedRenderCommand {
-            vertex_count,
-            instance_count: text_len as u32,
-            is_dirty: false,
+        clusters
+    }
+    
+    fn glyph_instance(&self, key: GlyphKey) -> [u32; INSTANCE_WORDS] {
+        let Some(glyph_info) = self.glyph_map.get(&key) else {
+            return [0; INSTANCE_WORDS];
         };
         
-        self.cached_commands.insert(cache_key.to_string(), cached_command);
-        
-        // For now, just return Ok - the actual rendering happens in render_text
-        Ok(())
+        let pack = |low: f32, high: f32| (low as u32 & 0xFFFF) | ((high as u32 & 0xFFFF) << 16);
+        [
+            0,
+            0,
+            pack(glyph_info.atlas_x, glyph_info.atlas_y),
+            pack(glyph_info.sdf_width, glyph_info.sdf_height),
+            u32::from_le_bytes(TEXT_COLOR),
+            glyph_info.page,
+        ]
+    }
+    
+    // Single entry point for document mutations so every change lands in the undo history
+    fn apply_edit(&mut self, range: Range<usize>, text: &str, kind: EditKind, after: Selection) {
+        let len = self.document.len_chars();
+        let range = range.start.min(len)..range.end.min(len);
+        let before = self.selection;
+        let edit = Edit {
+            start: range.start,
+            removed: self.document.slice(range).to_string(),
+            inserted: text.to_string(),
+        };
+        edit.apply(&mut self.document);
+        self.place_selection(after);
+        self.history.record(edit, kind, before, self.selection, js_sys::Date::now());
     }
 
     // Character-based text operations
     pub fn insert_char(&mut self, ch: char) -> Result<(), JsValue> {
-        if self.cursor_position <= self.text_buffer.len() {
-            self.text_buffer.insert(self.cursor_position, ch);
-            self.cursor_position += 1;
-            self.update_last_text();
-        }
+        let range = self.selection.range();
+        let after = Selection::caret(range.start + 1);
+        self.apply_edit(range, ch.encode_utf8(&mut [0; 4]), EditKind::Typing, after);
         Ok(())
     }
 
+    // Replace the selection with typed, pasted or dropped text. A single char coalesces
+    // with neighbouring keystrokes like insert_char does.
+    pub fn insert_text(&mut self, text: &str) {
+        let range = self.selection.range();
+        let len = text.chars().count();
+        if range.is_empty() && len == 0 {
+            return;
+        }
+        let kind = if len == 1 { EditKind::Typing } else { EditKind::Other };
+        let after = Selection::caret(range.start + len);
+        self.apply_edit(range, text, kind, after);
+    }
+
+    // Replace `range` with text an input method wrote straight into the hidden textarea,
+    // leaving the caret after it. Single chars coalesce like typed ones.
+    pub fn replace_range(&mut self, range: Range<usize>, text: &str) {
+        let len = text.chars().count();
+        if range.is_empty() && len == 0 {
+            return;
+        }
+        let kind = match len {
+            0 => EditKind::Deletion,
+            1 => EditKind::Typing,
+            _ => EditKind::Other,
+        };
+        let after = Selection::caret(range.start + len);
+        self.apply_edit(range, text, kind, after);
+    }
+
+    // Deletes the selection, or the grapheme cluster before the cursor when nothing is
+    // selected, so a combining mark or an emoji modifier never outlives its base
     pub fn delete_char_before_cursor(&mut self) -> Result<(), JsValue> {
-        if self.cursor_position > 0 {
-            self.text_buffer.remove(self.cursor_position - 1);
-            self.cursor_position -= 1;
-            self.update_last_text();
+        if !self.selection.is_empty() {
+            self.delete_selection();
+        } else if self.selection.head > 0 {
+            let range = self.document.cluster_before(self.selection.head);
+            let after = Selection::caret(range.start);
+            self.apply_edit(range, "", EditKind::Deletion, after);
         }
         Ok(())
     }
 
-    pub fn move_cursor_left(&mut self) {
-        if self.cursor_position > 0 {
-            self.cursor_position -= 1;
+    // Forward Delete: the selection, or the grapheme cluster after the cursor
+    pub fn delete_char_after_cursor(&mut self) {
+        if !self.selection.is_empty() {
+            self.delete_selection();
+        } else {
+            let range = self.document.cluster_after(self.selection.head);
+            let after = Selection::caret(range.start);
+            self.apply_edit(range, "", EditKind::Deletion, after);
+        }
+    }
+
+    // Ctrl+Backspace / Ctrl+Delete: the selection, or up to the previous word start or
+    // next word end
+    pub fn delete_word(&mut self, forward: bool, subword: bool) {
+        if !self.selection.is_empty() {
+            self.delete_selection();
+            return;
+        }
+        let head = self.selection.head;
+        let range = if forward {
+            head..self.document.next_word_end(head, subword)
+        } else {
+            self.document.prev_word_start(head, subword)..head
+        };
+        if !range.is_empty() {
+            let after = Selection::caret(range.start);
+            self.apply_edit(range, "", EditKind::Deletion, after);
+        }
+    }
+
+    // A whole selection removed at once is an undo step of its own; Backspace or Delete
+    // right after it starts a new one
+    pub fn delete_selection(&mut self) {
+        let range = self.selection.range();
+        if !range.is_empty() {
+            let after = Selection::caret(range.start);
+            self.apply_edit(range, "", EditKind::Other, after);
+        }
+    }
+
+    pub fn undo(&mut self) -> bool {
+        match self.history.undo(&mut self.document) {
+            Some(selection) => {
+                self.place_selection(selection);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn redo(&mut self) -> bool {
+        match self.history.redo(&mut self.document) {
+            Some(selection) => {
+                self.place_selection(selection);
+                true
+            }
+            None => false,
         }
     }
 
+    // Arrows move visually, so on mixed-direction lines the caret follows the screen
+    // rather than the char order. Plain arrows collapse an active selection to the
+    // matching edge.
+    pub fn move_cursor_left(&mut self) {
+        let target = if self.selection.is_empty() {
+            self.visual_neighbour(self.selection.head, false)
+        } else {
+            self.selection.start()
+        };
+        self.set_selection(Selection::caret(target));
+    }
+
     pub fn move_cursor_right(&mut self) {
-        if self.cursor_position < self.text_buffer.len() {
-            self.cursor_position += 1;
+        let target = if self.selection.is_empty() {
+            self.visual_neighbour(self.selection.head, true)
+        } else {
+            self.selection.end()
+        };
+        self.set_selection(Selection::caret(target));
+    }
+
+    // Shift+arrows move the head and keep the anchor
+    pub fn extend_selection_left(&mut self) {
+        let head = self.visual_neighbour(self.selection.head, false);
+        self.set_selection(self.selection.extend_to(head));
+    }
+
+    pub fn extend_selection_right(&mut self) {
+        let head = self.visual_neighbour(self.selection.head, true);
+        self.set_selection(self.selection.extend_to(head));
+    }
+
+    // Ctrl+arrows move by word (in logical order), or by subword for code identifiers
+    pub fn move_word(&mut self, forward: bool, subword: bool, extend: bool) {
+        let head = if forward {
+            self.document.next_word_end(self.selection.head, subword)
+        } else {
+            self.document.prev_word_start(self.selection.head, subword)
+        };
+        self.move_head(head, extend);
+    }
+
+    // Up/down arrows. The caret lands on the target line where it is closest to the goal
+    // column; moving up from the first line or down from the last goes to the document's
+    // start or end.
+    pub fn move_lines(&mut self, delta: isize, extend: bool) {
+        self.update_layout();
+        let Some(position) = self.layout.caret_position(self.selection.head) else {
+            return;
+        };
+        self.move_to_line(position.line as isize + delta, extend);
+    }
+
+    // PageUp/PageDown move the caret by a screen height and scroll the text with it
+    pub fn move_pages(&mut self, delta: isize, extend: bool) {
+        self.update_layout();
+        let Some(position) = self.layout.caret_position(self.selection.head) else {
+            return;
+        };
+        // Scroll first, so the rows the caret lands on are laid out
+        let distance = delta as f32 * self.viewport.height;
+        self.scroll_by(0.0, distance);
+        self.update_layout();
+        let target_y = position.top + position.height * 0.5 + distance;
+        let line = if target_y < self.layout.line_to_y(0) {
+            -1
+        } else if target_y >= self.layout.line_to_y(self.layout.line_count()) {
+            self.layout.line_count() as isize
+        } else {
+            self.layout.y_to_line(target_y) as isize
+        };
+        self.move_to_line(line, extend);
+    }
+
+    fn move_to_line(&mut self, line: isize, extend: bool) {
+        let goal_x = match (self.goal_x, self.layout.caret_position(self.selection.head)) {
+            (Some(goal_x), _) => goal_x,
+            (None, Some(position)) => position.x,
+            (None, None) => return,
+        };
+        let head = match usize::try_from(line).ok().map(|line| self.layout.line(line)) {
+            None => 0,
+            Some(Some(target)) => self.layout.hit_test(goal_x, target.top + target.height() * 0.5),
+            Some(None) => self.document.len_chars(),
+        };
+        self.move_head(head, extend);
+        self.goal_x = Some(goal_x);
+    }
+
+    // Home/End: the start or end of the caret's row. The end of a wrapped row is the
+    // start of the next, so End stops before the row's last cluster instead.
+    pub fn move_to_line_edge(&mut self, end: bool, extend: bool) {
+        self.update_layout();
+        let Some(line) = self.layout.line(self.layout.char_to_line(self.selection.head)) else {
+            return;
+        };
+        let head = match (end, line.wraps) {
+            (false, _) => line.start_char,
+            (true, false) => line.end_char(),
+            (true, true) => self.document.prev_grapheme_boundary(line.end_char()),
+        };
+        self.move_head(head, extend);
+    }
+
+    // Ctrl+Home/Ctrl+End
+    pub fn move_to_document_edge(&mut self, end: bool, extend: bool) {
+        let head = if end { self.document.len_chars() } else { 0 };
+        self.move_head(head, extend);
+    }
+
+    fn move_head(&mut self, head: usize, extend: bool) {
+        let selection = if extend { self.selection.extend_to(head) } else { Selection::caret(head) };
+        self.set_selection(selection);
+    }
+
+    // Lay out the current document, e.g. before hit-testing or moving the caret by rows
+    fn update_layout(&mut self) {
+        let document = self.document.snapshot();
+        self.lay_out(&document, self.selection.head);
+    }
+
+    // Lay out `document` for the viewport, with the rows around `caret` always shaped, and
+    // keep the scroll offset within the laid-out text
+    fn lay_out(&mut self, document: &Document, caret: usize) {
+        self.layout.set_wrap_width(self.wrap_width(self.viewport.text_width()));
+        self.layout.set_viewport(&self.viewport);
+        self.layout.update(&self.fonts, document, caret);
+        let scroll = self.viewport.scroll();
+        
+        // At most the last line can be scrolled to the top, and the widest line to the left edge
+        let last_line = self.layout.line_count().saturating_sub(1);
+        let max_y = self.layout.total_height() - self.layout.line_height(last_line);
+        self.viewport.set_scroll_limits(self.layout.max_line_width(), max_y);
+        // Rows shaped above the view for the first time pushed the text down; the scroll
+        // offset moves with it so nothing on screen jumps
+        let shift = self.layout.take_anchor_shift();
+        self.viewport.scroll_by(0.0, shift);
+        
+        // Shape the rows that came into the band if the limits or the anchor scrolled the text
+        if self.viewport.scroll() != scroll {
+            self.layout.set_viewport(&self.viewport);
+            self.layout.update(&self.fonts, document, caret);
+        }
+    }
+
+    // Caret stop next to `char_idx` on screen, laying out the current document first
+    fn visual_neighbour(&mut self, char_idx: usize, right: bool) -> usize {
+        self.update_layout();
+        self.layout.move_visually(char_idx, right).min(self.document.len_chars())
+    }
+
+    pub fn select_all(&mut self) {
+        self.set_selection(Selection::new(0, self.document.len_chars()));
+    }
+
+    // Document position under a point in canvas pixels
+    pub fn hit_test(&mut self, x: f32, y: f32) -> usize {
+        self.update_layout();
+        let (x, y) = self.viewport.to_document(x, y);
+        self.layout.hit_test(x, y).min(self.document.len_chars())
+    }
+
+    // A click at a point in canvas pixels. One click places the caret (or moves the head
+    // when extending), two select the word under the pointer and three the whole line.
+    // The press stays active so dragging extends the selection by the same unit.
+    pub fn click(&mut self, x: f32, y: f32, clicks: u32, extend: bool) {
+        let position = self.hit_test(x, y);
+        let granularity = match clicks {
+            0 | 1 => Granularity::Char,
+            2 => Granularity::Word,
+            _ => Granularity::Line,
+        };
+        let selection = match granularity {
+            Granularity::Char if extend => self.selection.extend_to(position),
+            Granularity::Char => Selection::caret(position),
+            _ => {
+                let unit = self.unit_at(position, granularity);
+                Selection::new(unit.start, unit.end)
+            }
+        };
+        self.set_selection(selection);
+        let anchor = self.selection.anchor;
+        let initial = match granularity {
+            Granularity::Char => anchor..anchor,
+            _ => self.selection.range(),
+        };
+        self.press = Some((granularity, initial));
+    }
+
+    // Drag the active press to a point in canvas pixels. The selection always covers
+    // what the press selected, and grows towards the pointer by whole units.
+    pub fn drag_to(&mut self, x: f32, y: f32) {
+        let Some((granularity, initial)) = self.press.clone() else {
+            return;
+        };
+        let position = self.hit_test(x, y);
+        let selection = match granularity {
+            Granularity::Char => Selection::new(initial.start, position),
+            _ => {
+                let unit = self.unit_at(position, granularity);
+                if unit.start < initial.start {
+                    Selection::new(initial.end, unit.start)
+                } else {
+                    Selection::new(initial.start, unit.end.max(initial.end))
+                }
+            }
+        };
+        self.set_selection(selection);
+        // The pointer scrolls the text itself while dragging past the edges
+        self.reveal_caret = false;
+    }
+
+    pub fn release(&mut self) {
+        self.press = None;
+    }
+
+    fn unit_at(&self, position: usize, granularity: Granularity) -> Range<usize> {
+        match granularity {
+            Granularity::Char => position..position,
+            Granularity::Word => self.document.word_range_at(position),
+            Granularity::Line => self.document.line_range(self.document.char_to_line(position)),
+        }
+    }
+
+    // Scroll by a pixel offset, staying within the text. The layout follows on its next
+    // update, before anything hit-tests against it.
+    pub fn scroll_by(&mut self, dx: f32, dy: f32) {
+        self.update_layout();
+        self.viewport.scroll_by(dx, dy);
+    }
+
+    // Wheel or trackpad scroll, in pixels; the text glides there over the next few steps
+    pub fn scroll_wheel(&mut self, dx: f32, dy: f32) {
+        self.viewport.fling(dx, dy, js_sys::Date::now());
+    }
+
+    // Advance a wheel glide. Returns whether it is still moving.
+    pub fn step_scroll(&mut self) -> bool {
+        self.update_layout();
+        self.viewport.step(js_sys::Date::now())
+    }
+
+    pub fn viewport_mut(&mut self) -> &mut Viewport {
+        &mut self.viewport
+    }
+
+    pub fn selection(&self) -> Selection {
+        self.selection
+    }
+
+    // Moving the selection ends the current undo step. Ends that fall inside a grapheme
+    // cluster are moved to its start.
+    pub fn set_selection(&mut self, selection: Selection) {
+        let selection = selection.clamp(self.document.len_chars());
+        let selection = Selection::new(
+            self.document.snap_to_grapheme(selection.anchor),
+            self.document.snap_to_grapheme(selection.head),
+        );
+        if selection != self.selection {
+            self.history.seal();
+        }
+        self.place_selection(selection);
+    }
+
+    // Any motion other than a vertical one forgets the goal column. The caret is
+    // scrolled into view on the next render.
+    fn place_selection(&mut self, selection: Selection) {
+        self.goal_x = None;
+        self.reveal_caret = true;
+        self.selection = selection.clamp(self.document.len_chars());
+        self.caret.reset_blink(js_sys::Date::now());
+        self.compose();
+    }
+
+    // Show in-progress IME text in place of the selection. The document is untouched
+    // until the composition is committed.
+    pub fn set_preedit(&mut self, text: &str) {
+        let previous = self.preedit_range();
+        self.preedit = Some(text.to_string());
+        match (self.composed.as_mut(), previous) {
+            (Some(composed), Some(previous)) => composed.replace(previous, text),
+            _ => self.compose(),
+        }
+        self.reveal_caret = true;
+        self.caret.reset_blink(js_sys::Date::now());
+    }
+
+    // Replace the selection with the text the input method settled on, as one undo step
+    pub fn commit_preedit(&mut self, text: &str) {
+        self.preedit = None;
+        self.composed = None;
+        if !text.is_empty() {
+            let range = self.selection.range();
+            let after = Selection::caret(range.start + text.chars().count());
+            self.apply_edit(range, text, EditKind::Other, after);
+            self.history.seal();
+        } else {
+            self.caret.reset_blink(js_sys::Date::now());
         }
     }
 
-    pub fn get_cursor_position(&self) -> usize {
-        self.cursor_position
+    // Chars the preedit text occupies in the displayed text
+    fn preedit_range(&self) -> Option<Range<usize>> {
+        let preedit = self.preedit.as_ref()?;
+        let start = self.selection.start();
+        Some(start..start + preedit.chars().count())
+    }
+
+    // What to draw: the document with any preedit text spliced in, and the selection
+    // (a caret after the preedit while composing)
+    pub fn display(&self) -> (DocumentSnapshot, Selection) {
+        match (&self.composed, self.preedit_range()) {
+            (Some(composed), Some(range)) => (composed.snapshot(), Selection::caret(range.end)),
+            _ => (self.snapshot(), self.selection),
+        }
+    }
+
+    // Splice the preedit text over the selection in a fresh copy of the document
+    fn compose(&mut self) {
+        self.composed = self.preedit.as_ref().map(|preedit| {
+            let mut composed = self.document.clone();
+            composed.replace(self.selection.range(), preedit);
+            composed
+        });
+    }
+
+    // Where the caret was last drawn, in canvas pixels
+    pub fn caret_position(&self, char_idx: usize) -> Option<CaretPosition> {
+        let position = self.layout.caret_position(char_idx)?;
+        let (x, top) = self.viewport.to_canvas(position.x, position.top);
+        let (cell_x, _) = self.viewport.to_canvas(position.cell_x, position.top);
+        Some(CaretPosition { x, top, cell_x, ..position })
+    }
+
+    pub fn selected_text(&self) -> String {
+        self.document.slice(self.selection.range()).to_string()
     }
 
     pub fn get_text(&self) -> String {
-        self.text_buffer.iter().collect()
+        self.document.to_string()
+    }
+
+    // Cheap copy of the current document for the render path
+    pub fn snapshot(&self) -> DocumentSnapshot {
+        self.document.snapshot()
+    }
+
+    pub fn document(&self) -> &Document {
+        &self.document
+    }
+
+    // Replace the whole text, recording only the span that actually changed. `selection`
+    // is where the caret ended up in the new text (char indices); the changed span is
+    // anchored at it, so typing "a" inside "aaa" is recorded where it happened rather
+    // than at the end of the run of equal chars. Moving the caret this way does not end
+    // the undo step, so consecutive keystrokes coalesce.
+    pub fn set_text(&mut self, text: &str, selection: Selection) {
+        let old_len = self.document.len_chars();
+        let new_chars: Vec<char> = text.chars().collect();
+        let selection = selection.clamp(new_chars.len());
+
+        // The edit starts no later than the old selection and the new caret, and ends no
+        // earlier than either of them
+        let old_range = self.selection.range();
+        let new_range = selection.range();
+        let max_prefix = old_range.start.min(new_range.start);
+        let prefix = self.document.chars()
+            .zip(new_chars.iter())
+            .take(max_prefix)
+            .take_while(|(old, new)| old == *new)
+            .count();
+        let max_suffix = (old_len - old_range.end.max(prefix))
+            .min(new_chars.len() - new_range.end.max(prefix));
+        let suffix = self.document.chars_at(old_len).reversed()
+            .zip(new_chars.iter().rev())
+            .take(max_suffix)
+            .take_while(|(old, new)| old == *new)
+            .count();
+
+        if prefix + suffix == old_len && old_len == new_chars.len() {
+            self.place_selection(selection);
+            return;
+        }
+
+        let inserted: String = new_chars[prefix..new_chars.len() - suffix].iter().collect();
+        let kind = match inserted.chars().count() {
+            0 => EditKind::Deletion,
+            1 => EditKind::Typing,
+            _ => EditKind::Other,
+        };
+        self.apply_edit(prefix..old_len - suffix, &inserted, kind, selection);
+    }
+
+    // Width rows wrap at, given the room between the text's left edge and the viewport's right edge
+    fn wrap_width(&self, available: f32) -> Option<f32> {
+        match self.config.wrap {
+            WrapMode::None => None,
+            WrapMode::Viewport => Some(available.max(1.0)),
+            WrapMode::Column(columns) => {
+                let column = self.fonts.primary().font.metrics('0', self.config.pixel_size()).advance_width;
+                Some((columns as f32 * column).max(1.0))
+            }
+        }
+    }
+
+    pub fn config(&self) -> TextConfig {
+        self.config
+    }
+
+    // Apply new sizes. Display size and line height only change the layout; the atlas is
+    // rebuilt only when glyphs would rasterize differently.
+    pub fn set_config(&mut self, config: TextConfig) -> Result<(), JsValue> {
+        let rebuild_atlas = !config.same_atlas(&self.config);
+        self.config = config;
+        self.layout.set_metrics(config.pixel_size(), config.line_height);
+        if rebuild_atlas && self.render_pipeline.is_some() {
+            self.create_texture_and_bind_group()?;
+        }
+        Ok(())
+    }
+
+    // Scale the display size by `factor`, reusing the glyphs already in the atlas
+    pub fn zoom(&mut self, factor: f32) {
+        let config = self.config.with_font_size(self.config.font_size * factor);
+        self.config = config;
+        self.layout.set_metrics(config.pixel_size(), config.line_height);
+    }
+
+    pub fn reset_zoom(&mut self) {
+        self.zoom(TextConfig::default().font_size / self.config.font_size);
+    }
+
+    // Draw with a different font chain. Cached line layouts and every atlas glyph belong
+    // to the old fonts, so both are dropped.
+    pub fn set_fonts(&mut self, fonts: FontChain) -> Result<(), JsValue> {
+        console_log!("FastTextRenderer switching to font '{}'", fonts.primary().name);
+        self.fonts = fonts;
+        self.layout.invalidate();
+        if self.render_pipeline.is_some() {
+            self.create_texture_and_bind_group()?;
+        }
+        Ok(())
     }
 
-    pub fn set_text(&mut self, text: &str) {
-        self.text_buffer = text.chars().collect();
-        self.cursor_position = self.text_buffer.len();
-        self.update_last_text();
+    // Switch between SDF and MSDF glyphs. The atlas changes format, so every glyph
+    // is regenerated the next time it is drawn.
+    pub fn set_glyph_mode(&mut self, mode: GlyphMode) -> Result<(), JsValue> {
+        if mode == self.glyph_mode {
+            return Ok(());
+        }
+        self.glyph_mode = mode;
+        if self.render_pipeline.is_some() {
+            self.create_render_pipeline()?;
+            self.create_texture_and_bind_group()?;
+        }
+        Ok(())
     }
 
-    fn update_last_text(&mut self) {
-        self.last_text = self.text_buffer.iter().collect();
+    // Caret appearance
+    pub fn caret_style(&self) -> CaretStyle {
+        self.caret.style
     }
-}
\ No newline at end of file
+
+    pub fn set_caret_style(&mut self, style: CaretStyle) {
+        self.caret.style = style;
+        self.caret.reset_blink(js_sys::Date::now());
+    }
+
+    // Milliseconds until the caret blink phase flips and a redraw is needed
+    pub fn next_caret_toggle_in(&self, now_ms: f64) -> Option<f64> {
+        self.caret.next_toggle_in(now_ms)
+    }
+}
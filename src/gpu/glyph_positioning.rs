@@ -0,0 +1,455 @@
+// Glyph Positioning
+// Computes glyph x positions on the GPU from a stream of per-glyph advances
+// Responsibilities:
+// - Upload the cluster stream (fixed-point advance, line start flag, slot, bearing, top)
+// - Segmented prefix sum of advances per line in a compute pass
+// - Write finished positions straight into the glyph instance buffer
+// - CPU reference implementation producing bit-identical positions
+
+use wasm_bindgen::prelude::*;
+use web_sys::{GpuBindGroup, GpuBuffer, GpuComputePipeline, GpuDevice};
+use crate::gpu::glyph_slots::NO_SLOT;
+use crate::layout::FIXED_SCALE;
+
+// Clusters scanned per workgroup; must match BLOCK_SIZE in the shader
+const BLOCK_SIZE: usize = 256;
+const CLUSTER_WORDS: usize = 5;
+
+// Set on the first glyph of each line, where the running advance restarts
+pub const LINE_START: u32 = 1;
+
+const POSITIONING_SHADER: &str = r#"
+const BLOCK_SIZE: u32 = 256u;
+const LINE_START: u32 = 1u;
+const NO_SLOT: u32 = 0xffffffffu;
+const FIXED_SCALE: f32 = 0.015625;
+// Must match INSTANCE_WORDS in fast_text
+const INSTANCE_WORDS: u32 = 6u;
+
+struct Params {
+    origin_x: f32,
+    cluster_count: u32,
+    block_count: u32,
+    _pad: u32,
+}
+
+struct Cluster {
+    advance: i32,
+    flags: u32,
+    slot: u32,
+    offset_x: f32,
+    top: f32,
+}
+
+@group(0) @binding(0) var<uniform> params: Params;
+@group(0) @binding(1) var<storage, read> clusters: array<Cluster>;
+// Inclusive in-block sum and whether a line started at or before this cluster in its block
+@group(0) @binding(2) var<storage, read_write> prefix: array<vec2<i32>>;
+// Block totals after pass 1, carry into each block after pass 2
+@group(0) @binding(3) var<storage, read_write> blocks: array<vec2<i32>>;
+@group(0) @binding(4) var<storage, read_write> instances: array<u32>;
+
+var<workgroup> scan_values: array<i32, 256>;
+var<workgroup> scan_flags: array<u32, 256>;
+
+// Pass 1: inclusive segmented scan of advances within each block
+@compute @workgroup_size(256)
+fn scan_blocks(
+    @builtin(global_invocation_id) global_id: vec3<u32>,
+    @builtin(local_invocation_id) local_id: vec3<u32>,
+    @builtin(workgroup_id) workgroup_id: vec3<u32>,
+) {
+    let index = global_id.x;
+    let lane = local_id.x;
+
+    var value: i32 = 0;
+    var flag: u32 = 0u;
+    if (index < params.cluster_count) {
+        value = clusters[index].advance;
+        flag = clusters[index].flags & LINE_START;
+    }
+    scan_values[lane] = value;
+    scan_flags[lane] = flag;
+    workgroupBarrier();
+
+    for (var offset = 1u; offset < BLOCK_SIZE; offset = offset << 1u) {
+        var v = scan_values[lane];
+        var f = scan_flags[lane];
+        if (lane >= offset) {
+            // A line start cuts off everything to its left
+            if (f == 0u) {
+                v = v + scan_values[lane - offset];
+            }
+            f = f | scan_flags[lane - offset];
+        }
+        workgroupBarrier();
+        scan_values[lane] = v;
+        scan_flags[lane] = f;
+        workgroupBarrier();
+    }
+
+    if (index < params.cluster_count) {
+        prefix[index] = vec2<i32>(scan_values[lane], i32(scan_flags[lane]));
+    }
+    if (lane == BLOCK_SIZE - 1u) {
+        blocks[workgroup_id.x] = vec2<i32>(scan_values[lane], i32(scan_flags[lane]));
+    }
+}
+
+// Pass 2: exclusive segmented scan over the block totals (few blocks, one thread)
+@compute @workgroup_size(1)
+fn scan_carries() {
+    var carry: i32 = 0;
+    for (var block = 0u; block < params.block_count; block = block + 1u) {
+        let total = blocks[block];
+        blocks[block].x = carry;
+        if (total.y != 0) {
+            carry = total.x;
+        } else {
+            carry = carry + total.x;
+        }
+    }
+}
+
+// Pass 3: add the carry, turn sums into pen positions and write the instance records
+@compute @workgroup_size(256)
+fn scatter(
+    @builtin(global_invocation_id) global_id: vec3<u32>,
+    @builtin(workgroup_id) workgroup_id: vec3<u32>,
+) {
+    let index = global_id.x;
+    if (index >= params.cluster_count) {
+        return;
+    }
+
+    let cluster = clusters[index];
+    if (cluster.slot == NO_SLOT) {
+        return;
+    }
+
+    var sum = prefix[index].x;
+    if (prefix[index].y == 0) {
+        sum = sum + blocks[workgroup_id.x].x;
+    }
+    let pen = sum - cluster.advance;
+    let x = params.origin_x + f32(pen) * FIXED_SCALE + cluster.offset_x;
+
+    let base = cluster.slot * INSTANCE_WORDS;
+    instances[base] = bitcast<u32>(x);
+    instances[base + 1u] = bitcast<u32>(cluster.top);
+}
+"#;
+
+// One shaped glyph in the positioning stream
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cluster {
+    // Fixed-point advance to the next glyph
+    pub advance: i32,
+    pub flags: u32,
+    // Instance slot of the glyph, or NO_SLOT if it draws nothing
+    pub slot: u32,
+    // Glyph quad offset from the pen position
+    pub offset_x: f32,
+    // Absolute y of the glyph quad
+    pub top: f32,
+}
+
+impl Cluster {
+    pub fn new(advance: i32, line_start: bool) -> Self {
+        Self {
+            advance,
+            flags: if line_start { LINE_START } else { 0 },
+            slot: NO_SLOT,
+            offset_x: 0.0,
+            top: 0.0,
+        }
+    }
+
+    fn words(&self) -> [u32; CLUSTER_WORDS] {
+        [
+            self.advance as u32,
+            self.flags,
+            self.slot,
+            self.offset_x.to_bits(),
+            self.top.to_bits(),
+        ]
+    }
+}
+
+// Reference for the compute pass: the glyph quad position of every cluster.
+// Uses the same fixed-point sums and float operations, so results match the GPU exactly.
+pub fn cpu_positions(clusters: &[Cluster], origin_x: f32) -> Vec<[f32; 2]> {
+    let mut pen = 0;
+    clusters
+        .iter()
+        .map(|cluster| {
+            if cluster.flags & LINE_START != 0 {
+                pen = 0;
+            }
+            let x = origin_x + pen as f32 * FIXED_SCALE + cluster.offset_x;
+            pen += cluster.advance;
+            [x, cluster.top]
+        })
+        .collect()
+}
+
+pub struct GlyphPositioner {
+    device: GpuDevice,
+    scan_pipeline: GpuComputePipeline,
+    carry_pipeline: GpuComputePipeline,
+    scatter_pipeline: GpuComputePipeline,
+    bind_group: GpuBindGroup,
+    params_buffer: GpuBuffer,
+    cluster_buffer: GpuBuffer,
+    capacity: usize,
+    // Last uploaded stream, so unchanged frames skip the upload
+    uploaded: Vec<Cluster>,
+}
+
+impl GlyphPositioner {
+    // `instance_buffer` must have STORAGE usage and room for every slot referenced by a cluster
+    pub fn new(device: &GpuDevice, instance_buffer: &GpuBuffer, capacity: usize) -> Result<Self, JsValue> {
+        let block_capacity = capacity.div_ceil(BLOCK_SIZE).max(1);
+
+        let create_buffer = |label: &str, size: usize, usage: u32| {
+            let desc = web_sys::GpuBufferDescriptor::new(size as f64, usage);
+            desc.set_label(label);
+            desc.set_mapped_at_creation(false);
+            device.create_buffer(&desc)
+        };
+        let storage = web_sys::gpu_buffer_usage::STORAGE | web_sys::gpu_buffer_usage::COPY_DST;
+        let params_buffer = create_buffer(
+            "Glyph Positioning Params",
+            16,
+            web_sys::gpu_buffer_usage::UNIFORM | web_sys::gpu_buffer_usage::COPY_DST,
+        )?;
+        let cluster_buffer = create_buffer("Glyph Cluster Buffer", capacity * CLUSTER_WORDS * 4, storage)?;
+        let prefix_buffer = create_buffer("Glyph Prefix Buffer", capacity * 8, storage)?;
+        let block_buffer = create_buffer("Glyph Block Buffer", block_capacity * 8, storage)?;
+
+        let shader = device.create_shader_module(&{
+            let desc = web_sys::GpuShaderModuleDescriptor::new(POSITIONING_SHADER);
+            desc.set_label("Glyph Positioning Compute Shader");
+            desc
+        });
+
+        let entries = js_sys::Array::new();
+        for binding in 0..5 {
+            let entry = web_sys::GpuBindGroupLayoutEntry::new(binding, web_sys::gpu_shader_stage::COMPUTE);
+            let buffer_layout = web_sys::GpuBufferBindingLayout::new();
+            buffer_layout.set_type(match binding {
+                0 => web_sys::GpuBufferBindingType::Uniform,
+                1 => web_sys::GpuBufferBindingType::ReadOnlyStorage,
+                _ => web_sys::GpuBufferBindingType::Storage,
+            });
+            entry.set_buffer(&buffer_layout);
+            entries.push(&entry);
+        }
+
+        let bind_group_layout_desc = web_sys::GpuBindGroupLayoutDescriptor::new(&entries);
+        bind_group_layout_desc.set_label("Glyph Positioning Bind Group Layout");
+        let bind_group_layout = device.create_bind_group_layout(&bind_group_layout_desc)?;
+
+        let layouts = js_sys::Array::new();
+        layouts.push(&bind_group_layout);
+        let pipeline_layout_desc = web_sys::GpuPipelineLayoutDescriptor::new(&layouts);
+        pipeline_layout_desc.set_label("Glyph Positioning Pipeline Layout");
+        let pipeline_layout = device.create_pipeline_layout(&pipeline_layout_desc);
+
+        let create_pipeline = |entry_point: &str| {
+            let stage = web_sys::GpuProgrammableStage::new(&shader);
+            stage.set_entry_point(entry_point);
+            let desc = web_sys::GpuComputePipelineDescriptor::new(&pipeline_layout, &stage);
+            desc.set_label(entry_point);
+            device.create_compute_pipeline(&desc)
+        };
+        let scan_pipeline = create_pipeline("scan_blocks");
+        let carry_pipeline = create_pipeline("scan_carries");
+        let scatter_pipeline = create_pipeline("scatter");
+
+        let bind_entries = js_sys::Array::new();
+        for (binding, buffer) in [&params_buffer, &cluster_buffer, &prefix_buffer, &block_buffer, instance_buffer]
+            .into_iter()
+            .enumerate()
+        {
+            let entry = web_sys::GpuBindGroupEntry::new(binding as u32, &web_sys::GpuBufferBinding::new(buffer));
+            bind_entries.push(&entry);
+        }
+        let bind_group_desc = web_sys::GpuBindGroupDescriptor::new(&bind_entries, &bind_group_layout);
+        bind_group_desc.set_label("Glyph Positioning Bind Group");
+        let bind_group = device.create_bind_group(&bind_group_desc);
+
+        Ok(Self {
+            device: device.clone(),
+            scan_pipeline,
+            carry_pipeline,
+            scatter_pipeline,
+            bind_group,
+            params_buffer,
+            cluster_buffer,
+            capacity,
+            uploaded: Vec::new(),
+        })
+    }
+
+    // Largest cluster stream the GPU buffers can hold
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    // Upload the cluster stream (only if it changed) and the line origin
+    pub fn upload(&mut self, clusters: &[Cluster], origin_x: f32) -> Result<(), JsValue> {
+        if clusters.len() > self.capacity {
+            return Err(JsValue::from_str("Cluster stream exceeds positioning capacity"));
+        }
+
+        let queue = self.device.queue();
+        if clusters != self.uploaded.as_slice() && !clusters.is_empty() {
+            let bytes: Vec<u8> = clusters
+                .iter()
+                .flat_map(|cluster| cluster.words())
+                .flat_map(|word| word.to_le_bytes())
+                .collect();
+            queue.write_buffer_with_u32_and_u8_slice(&self.cluster_buffer, 0, &bytes)?;
+        }
+        self.uploaded = clusters.to_vec();
+
+        let block_count = clusters.len().div_ceil(BLOCK_SIZE) as u32;
+        let params = [origin_x.to_bits(), clusters.len() as u32, block_count, 0];
+        let bytes: Vec<u8> = params.iter().flat_map(|word| word.to_le_bytes()).collect();
+        queue.write_buffer_with_u32_and_u8_slice(&self.params_buffer, 0, &bytes)?;
+        Ok(())
+    }
+
+    // Record the three scan passes; must come after any CPU writes to the instance buffer
+    pub fn encode(&self, encoder: &web_sys::GpuCommandEncoder) {
+        let block_count = self.uploaded.len().div_ceil(BLOCK_SIZE) as u32;
+        if block_count == 0 {
+            return;
+        }
+
+        let pass = encoder.begin_compute_pass();
+        pass.set_label("Glyph Positioning Pass");
+        pass.set_bind_group(0, Some(&self.bind_group));
+
+        pass.set_pipeline(&self.scan_pipeline);
+        pass.dispatch_workgroups(block_count);
+        pass.set_pipeline(&self.carry_pipeline);
+        pass.dispatch_workgroups(1);
+        pass.set_pipeline(&self.scatter_pipeline);
+        pass.dispatch_workgroups(block_count);
+        pass.end();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // One pixel in fixed point
+    const PX: i32 = 64;
+
+    fn xs(positions: &[[f32; 2]]) -> Vec<f32> {
+        positions.iter().map(|position| position[0]).collect()
+    }
+
+    // The three compute passes replayed on the CPU: an inclusive segmented scan inside
+    // each block, an exclusive scan of the block totals, then the carry added to every
+    // cluster not preceded by a line start within its block
+    fn blocked_positions(clusters: &[Cluster], origin_x: f32) -> Vec<[f32; 2]> {
+        let prefix: Vec<(i32, bool)> = clusters
+            .chunks(BLOCK_SIZE)
+            .flat_map(|block| {
+                let mut sum = 0;
+                let mut seen_start = false;
+                block.iter().map(move |cluster| {
+                    if cluster.flags & LINE_START != 0 {
+                        sum = 0;
+                        seen_start = true;
+                    }
+                    sum += cluster.advance;
+                    (sum, seen_start)
+                })
+            })
+            .collect();
+
+        let mut carries = Vec::new();
+        let mut carry = 0;
+        for block in prefix.chunks(BLOCK_SIZE) {
+            carries.push(carry);
+            let &(total, restarted) = block.last().unwrap();
+            carry = if restarted { total } else { carry + total };
+        }
+
+        clusters
+            .iter()
+            .zip(&prefix)
+            .enumerate()
+            .map(|(index, (cluster, &(sum, restarted)))| {
+                let sum = if restarted { sum } else { sum + carries[index / BLOCK_SIZE] };
+                let pen = sum - cluster.advance;
+                [origin_x + pen as f32 * FIXED_SCALE + cluster.offset_x, cluster.top]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn empty_stream_has_no_positions() {
+        assert!(cpu_positions(&[], 10.0).is_empty());
+        assert!(blocked_positions(&[], 10.0).is_empty());
+    }
+
+    #[test]
+    fn line_start_restarts_the_pen() {
+        let clusters = [
+            Cluster::new(10 * PX, true),
+            Cluster::new(5 * PX, false),
+            Cluster::new(7 * PX, true),
+            Cluster::new(3 * PX, false),
+            Cluster::new(4 * PX, false),
+        ];
+        assert_eq!(xs(&cpu_positions(&clusters, 100.0)), [100.0, 110.0, 100.0, 107.0, 110.0]);
+    }
+
+    #[test]
+    fn zero_width_clusters_share_the_pen_position() {
+        // A base letter followed by two combining marks drawn back over it
+        let mut mark = Cluster::new(0, false);
+        mark.offset_x = -4.0;
+        let clusters = [Cluster::new(8 * PX, true), mark, mark, Cluster::new(6 * PX, false)];
+        assert_eq!(xs(&cpu_positions(&clusters, 0.0)), [0.0, 4.0, 4.0, 8.0]);
+    }
+
+    #[test]
+    fn offset_and_top_are_carried_through() {
+        let mut cluster = Cluster::new(PX, true);
+        cluster.offset_x = 1.5;
+        cluster.top = 42.0;
+        assert_eq!(cpu_positions(&[cluster], 10.0), [[11.5, 42.0]]);
+    }
+
+    #[test]
+    fn segment_crossing_a_block_boundary_keeps_its_carry() {
+        // One line from cluster 200 running through the next two blocks, after a line
+        // in the first block
+        let clusters: Vec<Cluster> = (0..700)
+            .map(|index| Cluster::new((index % 7 + 1) * PX / 2, index == 0 || index == 200))
+            .collect();
+        let positions = cpu_positions(&clusters, 3.0);
+
+        let expected_pen: i32 = clusters[200..BLOCK_SIZE].iter().map(|cluster| cluster.advance).sum();
+        assert_eq!(positions[BLOCK_SIZE][0], 3.0 + expected_pen as f32 * FIXED_SCALE);
+        assert_eq!(positions, blocked_positions(&clusters, 3.0));
+    }
+
+    #[test]
+    fn line_starting_on_a_block_boundary_drops_the_carry() {
+        let clusters: Vec<Cluster> = (0..2 * BLOCK_SIZE)
+            .map(|index| Cluster::new(PX, index == 0 || index == BLOCK_SIZE))
+            .collect();
+        let positions = cpu_positions(&clusters, 0.0);
+        assert_eq!(positions[BLOCK_SIZE - 1][0], (BLOCK_SIZE - 1) as f32);
+        assert_eq!(positions[BLOCK_SIZE][0], 0.0);
+        assert_eq!(positions, blocked_positions(&clusters, 0.0));
+    }
+}
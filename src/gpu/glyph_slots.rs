@@ -0,0 +1,230 @@
+// Glyph Slot Table
+// Indirection between document-order glyphs and their slots in the persistent GPU buffers
+// Responsibilities:
+// - Give every visible glyph a stable slot that survives edits elsewhere in the text
+// - Recycle slots freed by deletions instead of shifting the buffer tail
+// - Mirror slot contents on the CPU and report only the byte ranges that changed
+
+use std::ops::Range;
+
+// Marks a glyph that did not fit in the buffers
+pub const NO_SLOT: u32 = u32::MAX;
+
+pub struct GlyphSlots {
+    // Logical (document-order) glyph index -> buffer slot
+    order: Vec<u32>,
+    free: Vec<u32>,
+    // High-water mark; slots below it may be drawn
+    slot_count: u32,
+    capacity: u32,
+}
+
+impl GlyphSlots {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            order: Vec::new(),
+            free: Vec::new(),
+            slot_count: 0,
+            capacity: capacity as u32,
+        }
+    }
+
+    pub fn glyph_count(&self) -> usize {
+        self.order.len()
+    }
+
+    pub fn slot_count(&self) -> u32 {
+        self.slot_count
+    }
+
+    pub fn slots(&self) -> &[u32] {
+        &self.order
+    }
+
+    // Replace the logical glyphs in `removed` with `inserted` new ones.
+    // Glyphs outside the range keep their slots. Returns the released slots so the
+    // caller can blank them; some may already be reused by the inserted glyphs.
+    pub fn splice(&mut self, removed: Range<usize>, inserted: usize) -> Vec<u32> {
+        let freed: Vec<u32> = self.order[removed.clone()]
+            .iter()
+            .copied()
+            .filter(|&slot| slot != NO_SLOT)
+            .collect();
+        self.free.extend(freed.iter().copied());
+
+        let new_slots: Vec<u32> = (0..inserted).map(|_| self.allocate()).collect();
+        self.order.splice(removed, new_slots);
+        freed
+    }
+
+    // Drop every glyph; all slots become free
+    pub fn reset(&mut self) {
+        self.order.clear();
+        self.free.clear();
+        self.slot_count = 0;
+    }
+
+    fn allocate(&mut self) -> u32 {
+        // Prefer the lowest free slot so the drawn range stays compact
+        if let Some((idx, _)) = self.free.iter().enumerate().min_by_key(|(_, slot)| **slot) {
+            return self.free.swap_remove(idx);
+        }
+        if self.slot_count < self.capacity {
+            self.slot_count += 1;
+            return self.slot_count - 1;
+        }
+        NO_SLOT
+    }
+}
+
+// CPU copy of a per-slot GPU buffer, tracking which slots changed since the last upload.
+// Records are raw 32-bit words so packed integer and float fields can share a slot.
+pub struct SlotMirror {
+    stride: usize,
+    data: Vec<u32>,
+    // Whether the GPU is known to hold `data` for each slot
+    valid: Vec<bool>,
+    dirty: Vec<u32>,
+}
+
+impl SlotMirror {
+    // `stride` is the number of 32-bit words stored per slot
+    pub fn new(stride: usize) -> Self {
+        Self {
+            stride,
+            data: Vec::new(),
+            valid: Vec::new(),
+            dirty: Vec::new(),
+        }
+    }
+
+    // Store a slot's record, marking it dirty only if it differs from what the GPU has
+    pub fn write(&mut self, slot: u32, record: &[u32]) {
+        debug_assert_eq!(record.len(), self.stride);
+        let index = slot as usize;
+        let start = index * self.stride;
+        if self.valid.len() <= index {
+            self.valid.resize(index + 1, false);
+            self.data.resize((index + 1) * self.stride, 0);
+        }
+        let current = &mut self.data[start..start + self.stride];
+        if !self.valid[index] || current != record {
+            current.copy_from_slice(record);
+            self.valid[index] = true;
+            self.dirty.push(slot);
+        }
+    }
+
+    // Zero a slot so it draws nothing
+    pub fn clear(&mut self, slot: u32) {
+        let zeros = vec![0; self.stride];
+        self.write(slot, &zeros);
+    }
+
+    // Forget what the GPU holds so the next writes re-upload every slot
+    pub fn invalidate(&mut self) {
+        self.valid.fill(false);
+        self.dirty.clear();
+    }
+
+    // Contiguous dirty slot ranges, coalesced and cleared
+    pub fn take_dirty_ranges(&mut self) -> Vec<Range<u32>> {
+        self.dirty.sort_unstable();
+        self.dirty.dedup();
+
+        let mut ranges: Vec<Range<u32>> = Vec::new();
+        for &slot in &self.dirty {
+            match ranges.last_mut() {
+                Some(range) if range.end == slot => range.end += 1,
+                _ => ranges.push(slot..slot + 1),
+            }
+        }
+        self.dirty.clear();
+        ranges
+    }
+
+    // Byte offset into the GPU buffer and the bytes to upload for a slot range
+    pub fn range_bytes(&self, range: &Range<u32>) -> (u32, Vec<u8>) {
+        let start = range.start as usize * self.stride;
+        let end = range.end as usize * self.stride;
+        let bytes = self.data[start..end]
+            .iter()
+            .flat_map(|word| word.to_le_bytes())
+            .collect();
+        ((start * 4) as u32, bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splices_keep_slots_outside_the_edit() {
+        let mut slots = GlyphSlots::new(8);
+        assert!(slots.splice(0..0, 4).is_empty());
+        assert_eq!(slots.slots(), [0, 1, 2, 3]);
+
+        // Inserting in the middle takes new slots without moving the glyphs after it
+        slots.splice(2..2, 2);
+        assert_eq!(slots.slots(), [0, 1, 4, 5, 2, 3]);
+
+        // Deleting releases the removed glyphs' slots, and the next insert takes the lowest
+        assert_eq!(slots.splice(1..3, 0), [1, 4]);
+        assert_eq!(slots.slots(), [0, 5, 2, 3]);
+        slots.splice(0..0, 1);
+        assert_eq!(slots.slots(), [1, 0, 5, 2, 3]);
+
+        // Replacing glyphs may hand their own slots straight back
+        assert_eq!(slots.splice(1..3, 2), [0, 5]);
+        assert_eq!(slots.slots(), [1, 0, 4, 2, 3]);
+        assert_eq!(slots.slot_count(), 6);
+    }
+
+    #[test]
+    fn scrolling_reuses_the_slots_of_rows_that_left() {
+        let mut slots = GlyphSlots::new(6);
+        slots.splice(0..0, 6);
+        // Two glyphs scroll out at the top and two come in at the bottom
+        assert_eq!(slots.splice(0..2, 0), [0, 1]);
+        slots.splice(4..4, 2);
+        assert_eq!(slots.slots(), [2, 3, 4, 5, 0, 1]);
+        assert_eq!(slots.slot_count(), 6);
+
+        // Glyphs past the capacity get no slot and give none back
+        slots.splice(6..6, 1);
+        assert_eq!(slots.slots()[6], NO_SLOT);
+        assert_eq!(slots.splice(5..7, 0), [1]);
+        assert_eq!(slots.glyph_count(), 5);
+
+        slots.reset();
+        slots.splice(0..0, 2);
+        assert_eq!(slots.slots(), [0, 1]);
+    }
+
+    #[test]
+    fn dirty_slots_coalesce_into_ranges() {
+        let mut mirror = SlotMirror::new(2);
+        for slot in [3, 1, 2, 7, 7] {
+            mirror.write(slot, &[slot, 1]);
+        }
+        assert_eq!(mirror.take_dirty_ranges(), [1..4, 7..8]);
+        assert!(mirror.take_dirty_ranges().is_empty());
+
+        // Writing what the GPU already has is not a change; anything else is
+        mirror.write(2, &[2, 1]);
+        mirror.write(3, &[3, 9]);
+        mirror.clear(1);
+        assert_eq!(mirror.take_dirty_ranges(), [1..2, 3..4]);
+
+        // Once the GPU contents are unknown, every written slot is uploaded again
+        mirror.invalidate();
+        mirror.write(2, &[2, 1]);
+        mirror.write(3, &[3, 9]);
+        let ranges = mirror.take_dirty_ranges();
+        assert_eq!(ranges, vec![Range { start: 2, end: 4 }]);
+        let (offset, bytes) = mirror.range_bytes(&ranges[0]);
+        assert_eq!(offset, 2 * 2 * 4);
+        assert_eq!(bytes, [2, 0, 0, 0, 1, 0, 0, 0, 3, 0, 0, 0, 9, 0, 0, 0]);
+    }
+}
@@ -11,5 +11,12 @@ pub mod shaders;
 pub mod square;
 pub mod text;
 pub mod fast_text;
+pub mod rect;
+pub mod caret;
+pub mod glyph_slots;
+pub mod glyph_positioning;
+pub mod atlas;
+pub mod text_config;
+pub mod msdf;
 pub mod utils;
 
@@ -0,0 +1,310 @@
+// Multi-channel Signed Distance Fields
+// Builds MSDF glyph bitmaps directly from font outlines
+// Responsibilities:
+// - Flatten glyph outlines into line segments, remembering which outline edge each came from
+// - Color edges so the two sides of every corner land in different channels
+// - Compute per-channel signed pseudo-distances, then fix signs with a winding-rule fill
+
+use ttf_parser::{Face, GlyphId, OutlineBuilder};
+
+// Line segments per curve when flattening
+const CURVE_STEPS: usize = 8;
+
+// Edges whose directions differ by more than this (sine of the angle) meet at a corner
+const CORNER_CROSS_THRESHOLD: f32 = 0.14;
+
+const RED: u8 = 1;
+const GREEN: u8 = 2;
+const BLUE: u8 = 4;
+const YELLOW: u8 = RED | GREEN;
+const MAGENTA: u8 = RED | BLUE;
+const CYAN: u8 = GREEN | BLUE;
+const WHITE: u8 = RED | GREEN | BLUE;
+
+type Point = [f32; 2];
+
+fn sub(a: Point, b: Point) -> Point {
+    [a[0] - b[0], a[1] - b[1]]
+}
+
+fn dot(a: Point, b: Point) -> f32 {
+    a[0] * b[0] + a[1] * b[1]
+}
+
+fn cross(a: Point, b: Point) -> f32 {
+    a[0] * b[1] - a[1] * b[0]
+}
+
+fn length(a: Point) -> f32 {
+    dot(a, a).sqrt()
+}
+
+fn normalize(a: Point) -> Point {
+    let len = length(a);
+    if len > 0.0 { [a[0] / len, a[1] / len] } else { [0.0, 0.0] }
+}
+
+// An outline edge (line or curve) as a polyline in pixel space
+struct Edge {
+    points: Vec<Point>,
+}
+
+impl Edge {
+    fn start_direction(&self) -> Point {
+        normalize(sub(self.points[1], self.points[0]))
+    }
+
+    fn end_direction(&self) -> Point {
+        let n = self.points.len();
+        normalize(sub(self.points[n - 1], self.points[n - 2]))
+    }
+}
+
+// Collects closed contours from a glyph outline, scaled from font units to pixels
+struct ContourBuilder {
+    scale: f32,
+    contours: Vec<Vec<Edge>>,
+    start: Point,
+    current: Point,
+}
+
+impl ContourBuilder {
+    fn point(&self, x: f32, y: f32) -> Point {
+        [x * self.scale, y * self.scale]
+    }
+
+    fn push_edge(&mut self, points: Vec<Point>) {
+        let end = points[points.len() - 1];
+        if points.iter().any(|&p| p != points[0])
+            && let Some(contour) = self.contours.last_mut()
+        {
+            contour.push(Edge { points });
+        }
+        self.current = end;
+    }
+}
+
+impl OutlineBuilder for ContourBuilder {
+    fn move_to(&mut self, x: f32, y: f32) {
+        self.start = self.point(x, y);
+        self.current = self.start;
+        self.contours.push(Vec::new());
+    }
+
+    fn line_to(&mut self, x: f32, y: f32) {
+        let end = self.point(x, y);
+        self.push_edge(vec![self.current, end]);
+    }
+
+    fn quad_to(&mut self, x1: f32, y1: f32, x: f32, y: f32) {
+        let (p0, p1, p2) = (self.current, self.point(x1, y1), self.point(x, y));
+        let points = (0..=CURVE_STEPS)
+            .map(|step| {
+                let t = step as f32 / CURVE_STEPS as f32;
+                let mt = 1.0 - t;
+                [
+                    mt * mt * p0[0] + 2.0 * mt * t * p1[0] + t * t * p2[0],
+                    mt * mt * p0[1] + 2.0 * mt * t * p1[1] + t * t * p2[1],
+                ]
+            })
+            .collect();
+        self.push_edge(points);
+    }
+
+    fn curve_to(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, x: f32, y: f32) {
+        let (p0, p1, p2, p3) = (self.current, self.point(x1, y1), self.point(x2, y2), self.point(x, y));
+        let points = (0..=CURVE_STEPS)
+            .map(|step| {
+                let t = step as f32 / CURVE_STEPS as f32;
+                let mt = 1.0 - t;
+                let (a, b, c, d) = (mt * mt * mt, 3.0 * mt * mt * t, 3.0 * mt * t * t, t * t * t);
+                [
+                    a * p0[0] + b * p1[0] + c * p2[0] + d * p3[0],
+                    a * p0[1] + b * p1[1] + c * p2[1] + d * p3[1],
+                ]
+            })
+            .collect();
+        self.push_edge(points);
+    }
+
+    fn close(&mut self) {
+        if self.current != self.start {
+            self.push_edge(vec![self.current, self.start]);
+        }
+    }
+}
+
+// A flattened piece of an edge. Only the ends of the original edge are extended
+// into pseudo-distances; joints inside a curve are not.
+struct Segment {
+    a: Point,
+    b: Point,
+    color: u8,
+    extend_start: bool,
+    extend_end: bool,
+}
+
+impl Segment {
+    // (absolute distance, how far from orthogonal the nearest point is, signed pseudo-distance)
+    fn distance(&self, p: Point) -> (f32, f32, f32) {
+        let ab = sub(self.b, self.a);
+        let ap = sub(p, self.a);
+        let len = length(ab);
+        let t = dot(ap, ab) / (len * len);
+        let tc = t.clamp(0.0, 1.0);
+        let nearest = [self.a[0] + ab[0] * tc, self.a[1] + ab[1] * tc];
+        let to_point = sub(p, nearest);
+        let distance = length(to_point);
+        let side = cross(ab, ap);
+        let signed = if side < 0.0 { -distance } else { distance };
+
+        let at_end = tc == 0.0 || tc == 1.0;
+        let ortho = if at_end { dot(normalize(ab), normalize(to_point)).abs() } else { 0.0 };
+
+        let extended = (t < 0.0 && self.extend_start) || (t > 1.0 && self.extend_end);
+        let pseudo = if extended {
+            let perpendicular = side / len;
+            if perpendicular.abs() <= distance { perpendicular } else { signed }
+        } else {
+            signed
+        };
+        (distance, ortho, pseudo)
+    }
+
+    // Nonzero-rule crossing of a ray from `p` towards +x
+    fn winding(&self, p: Point) -> i32 {
+        let (a, b) = (self.a, self.b);
+        if (a[1] <= p[1]) != (b[1] <= p[1]) {
+            let x = a[0] + (p[1] - a[1]) / (b[1] - a[1]) * (b[0] - a[0]);
+            if x > p[0] {
+                return if b[1] > a[1] { 1 } else { -1 };
+            }
+        }
+        0
+    }
+}
+
+fn is_corner(incoming: Point, outgoing: Point) -> bool {
+    dot(incoming, outgoing) <= 0.0 || cross(incoming, outgoing).abs() > CORNER_CROSS_THRESHOLD
+}
+
+// Give each edge of a contour a color so that edges meeting at a corner share only one channel
+fn color_contour(edges: &[Edge]) -> Vec<u8> {
+    let n = edges.len();
+    let corners: Vec<usize> = (0..n)
+        .filter(|&i| is_corner(edges[(i + n - 1) % n].end_direction(), edges[i].start_direction()))
+        .collect();
+
+    match corners.len() {
+        0 => vec![WHITE; n],
+        // Teardrop: split the contour in three so the lone corner still gets two colors
+        1 if n >= 3 => {
+            let mut colors = vec![WHITE; n];
+            for k in 0..n {
+                colors[(corners[0] + k) % n] = [MAGENTA, WHITE, YELLOW][k * 3 / n];
+            }
+            colors
+        }
+        1 => vec![WHITE; n],
+        splines => {
+            let mut colors = vec![WHITE; n];
+            for s in 0..splines {
+                let color = if s == splines - 1 && s % 2 == 0 {
+                    YELLOW
+                } else if s % 2 == 0 {
+                    CYAN
+                } else {
+                    MAGENTA
+                };
+                let (start, end) = (corners[s], corners[(s + 1) % splines]);
+                let span = (end + n - start - 1) % n + 1;
+                for k in 0..span {
+                    colors[(start + k) % n] = color;
+                }
+            }
+            colors
+        }
+    }
+}
+
+// Pixel box a glyph's MSDF covers. (`left`, `bottom`) is its bottom-left corner in
+// pixel space relative to the glyph origin, y up.
+#[derive(Debug, Clone, Copy)]
+pub struct MsdfBox {
+    pub left: f32,
+    pub bottom: f32,
+    pub width: usize,
+    pub height: usize,
+}
+
+// Render an RGBA MSDF for `glyph_id` into `bounds`. Distances are clamped to `range`
+// pixels and stored like the single-channel SDF: 0.5 on the outline, lower inside.
+// Returns None for glyphs without an outline.
+pub fn generate_msdf(face: &Face, glyph_id: GlyphId, font_size: f32, bounds: MsdfBox, range: f32) -> Option<Vec<u8>> {
+    let MsdfBox { left, bottom, width, height } = bounds;
+    let mut builder = ContourBuilder {
+        scale: font_size / face.units_per_em() as f32,
+        contours: Vec::new(),
+        start: [0.0, 0.0],
+        current: [0.0, 0.0],
+    };
+    face.outline_glyph(glyph_id, &mut builder)?;
+
+    let mut segments = Vec::new();
+    for contour in builder.contours.iter().filter(|contour| !contour.is_empty()) {
+        for (edge, color) in contour.iter().zip(color_contour(contour)) {
+            let last = edge.points.len() - 2;
+            for (j, pair) in edge.points.windows(2).enumerate() {
+                if pair[0] != pair[1] {
+                    segments.push(Segment {
+                        a: pair[0],
+                        b: pair[1],
+                        color,
+                        extend_start: j == 0,
+                        extend_end: j == last,
+                    });
+                }
+            }
+        }
+    }
+    if segments.is_empty() {
+        return None;
+    }
+
+    let mut pixels = vec![255u8; width * height * 4];
+    for row in 0..height {
+        for col in 0..width {
+            // Texel centers, with row 0 at the top of the box
+            let p = [left + col as f32 + 0.5, bottom + (height - row) as f32 - 0.5];
+
+            let mut channels = [0.0f32; 3];
+            for (channel, value) in channels.iter_mut().enumerate() {
+                let mask = 1 << channel;
+                let mut best = (f32::INFINITY, f32::INFINITY, range);
+                for segment in segments.iter().filter(|segment| segment.color & mask != 0) {
+                    let candidate = segment.distance(p);
+                    let closer = candidate.0 < best.0 - 1e-4
+                        || ((candidate.0 - best.0).abs() <= 1e-4 && candidate.1 < best.1);
+                    if closer {
+                        best = candidate;
+                    }
+                }
+                *value = best.2;
+            }
+
+            // Outline winding varies between font formats, so orient by the fill rule:
+            // distances must be negative inside and positive outside
+            let inside = segments.iter().map(|segment| segment.winding(p)).sum::<i32>() != 0;
+            let median = channels[0].max(channels[1]).min(channels[0].min(channels[1]).max(channels[2]));
+            if inside == (median > 0.0) {
+                channels.iter_mut().for_each(|value| *value = -*value);
+            }
+
+            let texel = (row * width + col) * 4;
+            for (channel, value) in channels.iter().enumerate() {
+                pixels[texel + channel] = ((value / range * 0.5 + 0.5) * 255.0).clamp(0.0, 255.0) as u8;
+            }
+        }
+    }
+    Some(pixels)
+}
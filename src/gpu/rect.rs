@@ -0,0 +1,171 @@
+// Solid Rectangle Rendering
+// Draws flat-colored, alpha-blended rectangles in screen space
+// Used for editor decorations that sit around the glyphs (caret, highlights)
+// Rects are uploaded once per frame and drawn as sub-ranges of one buffer
+
+use wasm_bindgen::prelude::*;
+use web_sys::{GpuBuffer, GpuDevice, GpuRenderPassEncoder, GpuRenderPipeline};
+
+// 6 vertices * (2 position + 4 color) floats
+const FLOATS_PER_RECT: usize = 6 * 6;
+const BYTES_PER_RECT: usize = FLOATS_PER_RECT * 4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rect {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+    pub color: [f32; 4],
+}
+
+pub struct RectRenderer {
+    device: GpuDevice,
+    pipeline: GpuRenderPipeline,
+    vertex_buffer: GpuBuffer,
+    capacity: usize,
+}
+
+impl RectRenderer {
+    pub fn new(device: &GpuDevice, capacity: usize) -> Result<Self, JsValue> {
+        let pipeline = Self::create_pipeline(device)?;
+
+        let vertex_buffer = device.create_buffer(&{
+            let desc = web_sys::GpuBufferDescriptor::new(
+                (capacity * BYTES_PER_RECT) as f64,
+                web_sys::gpu_buffer_usage::VERTEX | web_sys::gpu_buffer_usage::COPY_DST,
+            );
+            desc.set_label("Rect Vertex Buffer");
+            desc.set_mapped_at_creation(false);
+            desc
+        })?;
+
+        Ok(Self {
+            device: device.clone(),
+            pipeline,
+            vertex_buffer,
+            capacity,
+        })
+    }
+
+    fn create_pipeline(device: &GpuDevice) -> Result<GpuRenderPipeline, JsValue> {
+        let shader = device.create_shader_module(&{
+            let desc = web_sys::GpuShaderModuleDescriptor::new(r#"
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+}
+
+@vertex
+fn vs_main(@location(0) position: vec2<f32>, @location(1) color: vec4<f32>) -> VertexOutput {
+    var output: VertexOutput;
+    output.position = vec4<f32>(position, 0.0, 1.0);
+    output.color = color;
+    return output;
+}
+
+@fragment
+fn fs_main(@location(0) color: vec4<f32>) -> @location(0) vec4<f32> {
+    return color;
+}
+"#);
+            desc.set_label("Rect Shader");
+            desc
+        });
+
+        let pipeline_layout_desc = web_sys::GpuPipelineLayoutDescriptor::new(&js_sys::Array::new());
+        pipeline_layout_desc.set_label("Rect Pipeline Layout");
+        let pipeline_layout = device.create_pipeline_layout(&pipeline_layout_desc);
+
+        // Position + color per vertex
+        let vertex_attributes = js_sys::Array::new();
+        vertex_attributes.push(&web_sys::GpuVertexAttribute::new(web_sys::GpuVertexFormat::Float32x2, 0.0, 0));
+        vertex_attributes.push(&web_sys::GpuVertexAttribute::new(web_sys::GpuVertexFormat::Float32x4, 8.0, 1));
+
+        let vertex_buffer_layout = web_sys::GpuVertexBufferLayout::new(24.0, &vertex_attributes);
+        vertex_buffer_layout.set_step_mode(web_sys::GpuVertexStepMode::Vertex);
+        let vertex_buffers = js_sys::Array::new();
+        vertex_buffers.push(&vertex_buffer_layout);
+
+        let vertex_state = web_sys::GpuVertexState::new(&shader);
+        vertex_state.set_entry_point("vs_main");
+        vertex_state.set_buffers(&vertex_buffers);
+
+        // Alpha blending so highlights can be translucent
+        let color_target_state = web_sys::GpuColorTargetState::new(web_sys::GpuTextureFormat::Bgra8unorm);
+        let color_component = web_sys::GpuBlendComponent::new();
+        color_component.set_operation(web_sys::GpuBlendOperation::Add);
+        color_component.set_src_factor(web_sys::GpuBlendFactor::SrcAlpha);
+        color_component.set_dst_factor(web_sys::GpuBlendFactor::OneMinusSrcAlpha);
+
+        let alpha_component = web_sys::GpuBlendComponent::new();
+        alpha_component.set_operation(web_sys::GpuBlendOperation::Add);
+        alpha_component.set_src_factor(web_sys::GpuBlendFactor::One);
+        alpha_component.set_dst_factor(web_sys::GpuBlendFactor::OneMinusSrcAlpha);
+
+        color_target_state.set_blend(&web_sys::GpuBlendState::new(&color_component, &alpha_component));
+
+        let targets = js_sys::Array::new();
+        targets.push(&color_target_state);
+        let fragment_state = web_sys::GpuFragmentState::new(&shader, &targets);
+        fragment_state.set_entry_point("fs_main");
+
+        let primitive = web_sys::GpuPrimitiveState::new();
+        primitive.set_topology(web_sys::GpuPrimitiveTopology::TriangleList);
+
+        let pipeline_desc = web_sys::GpuRenderPipelineDescriptor::new(&pipeline_layout, &vertex_state);
+        pipeline_desc.set_fragment(&fragment_state);
+        pipeline_desc.set_primitive(&primitive);
+        pipeline_desc.set_label("Rect Render Pipeline");
+
+        device.create_render_pipeline(&pipeline_desc)
+    }
+
+    // Upload this frame's rects; returns how many fit in the buffer
+    pub fn upload(&self, rects: &[Rect], screen_width: f32, screen_height: f32) -> Result<usize, JsValue> {
+        let count = rects.len().min(self.capacity);
+        if count == 0 {
+            return Ok(0);
+        }
+
+        let mut vertices = Vec::with_capacity(count * FLOATS_PER_RECT);
+        for rect in &rects[..count] {
+            // Convert to NDC (-1 to 1 range)
+            let left = (rect.x / screen_width) * 2.0 - 1.0;
+            let right = ((rect.x + rect.width) / screen_width) * 2.0 - 1.0;
+            let top = 1.0 - (rect.y / screen_height) * 2.0;
+            let bottom = 1.0 - ((rect.y + rect.height) / screen_height) * 2.0;
+            let [r, g, b, a] = rect.color;
+
+            vertices.extend_from_slice(&[
+                left, bottom,   r, g, b, a,
+                right, bottom,  r, g, b, a,
+                left, top,      r, g, b, a,
+
+                right, bottom,  r, g, b, a,
+                right, top,     r, g, b, a,
+                left, top,      r, g, b, a,
+            ]);
+        }
+
+        let vertex_bytes: Vec<u8> = vertices.iter()
+            .flat_map(|&f| f.to_le_bytes())
+            .collect();
+        self.device.queue().write_buffer_with_u32_and_u8_slice(&self.vertex_buffer, 0, &vertex_bytes)?;
+        Ok(count)
+    }
+
+    // Draw `count` previously uploaded rects starting at `first`
+    pub fn draw(&self, render_pass: &GpuRenderPassEncoder, first: usize, count: usize) {
+        if count == 0 {
+            return;
+        }
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_vertex_buffer(0, Some(&self.vertex_buffer));
+        render_pass.draw_with_instance_count_and_first_vertex(
+            (count * 6) as u32,
+            1,
+            (first * 6) as u32,
+        );
+    }
+}
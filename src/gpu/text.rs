@@ -1,8 +1,11 @@
-use fontdue::{Font, FontSettings, layout::{CoordinateSystem, Layout, LayoutSettings, TextStyle}};
+use fontdue::{Font, layout::{CoordinateSystem, Layout, LayoutSettings, TextStyle}};
 use sdf_glyph_renderer::BitmapGlyph;
 use wasm_bindgen::prelude::*;
 use web_sys::{GpuDevice, GpuTextureView, GpuRenderPipeline};
 use std::collections::HashMap;
+use std::rc::Rc;
+use crate::fonts::{self, LoadedFont};
+use crate::gpu::text_config::TextConfig;
 
 #[wasm_bindgen]
 extern "C" {
@@ -25,7 +28,7 @@ struct GlyphInfo {
 }
 
 pub struct TextRenderer {
-    font: Font,
+    font: Rc<Font>,
     pipeline: Option<GpuRenderPipeline>,
     bind_group_layout: Option<web_sys::GpuBindGroupLayout>,
     sdf_atlas: Option<Vec<u8>>,
@@ -34,22 +37,15 @@ pub struct TextRenderer {
     atlas_texture: Option<web_sys::GpuTexture>,
     bind_group: Option<web_sys::GpuBindGroup>,
     glyph_map: HashMap<char, GlyphInfo>,
-    buffer_size: usize,
+    config: TextConfig,
 }
 
 impl TextRenderer {
     pub fn new() -> Result<Self, JsValue> {
-        let font_data = include_bytes!("../assets/fonts/Spectral-ExtraLight.ttf");
-        
-        if font_data.is_empty() {
-            return Err(JsValue::from_str("Font file is empty or not found"));
-        }
-        
-        let font = Font::from_bytes(font_data as &[u8], FontSettings::default())
-            .map_err(|e| JsValue::from_str(&format!("Failed to load font Spectral-ExtraLight.ttf: {:?}", e)))?;
+        let font = fonts::active_font()?;
         
         Ok(Self {
-            font,
+            font: font.font,
             pipeline: None,
             bind_group_layout: None,
             sdf_atlas: None,
@@ -58,10 +54,18 @@ impl TextRenderer {
             atlas_texture: None,
             bind_group: None,
             glyph_map: HashMap::new(),
-            buffer_size: 8,
+            config: TextConfig::default(),
         })
     }
 
+    // Draw with a different font; the atlas is regenerated on the next render.
+    // This renderer lays text out with a single font, so fallbacks are not used.
+    pub fn set_font(&mut self, font: LoadedFont) {
+        self.font = font.font;
+        self.sdf_atlas = None;
+        self.glyph_map.clear();
+    }
+
     pub fn create_text_pipeline(&mut self, device: &GpuDevice) -> Result<(), JsValue> {
         let vertex_shader = device.create_shader_module(&web_sys::GpuShaderModuleDescriptor::new(r#"
 struct VertexInput {
@@ -187,17 +191,21 @@ fn main(@location(0) tex_coord: vec2<f32>) -> @location(0) vec4<f32> {
     pub fn generate_sdf_atlas(&mut self, text: &str) -> Result<(), JsValue> {
         console_log!("Generating SDF atlas for: '{}'", text);
         
-        let atlas_size = (self.atlas_width * self.atlas_height) as usize;
-        let mut atlas_data = vec![128u8; atlas_size]; // Initialize with middle gray
-        
         let mut unique_chars: Vec<char> = text.chars().filter(|c| !c.is_whitespace()).collect();
         unique_chars.sort();
         unique_chars.dedup();
         
         console_log!("Processing {} unique characters", unique_chars.len());
         
-        let char_size = 64;
+        // Cells grow with the raster size, so grow the atlas downwards to fit every char
+        let char_size = self.config.cell_size();
+        let padding = self.config.sdf_padding;
         let chars_per_row = self.atlas_width / char_size;
+        let rows = (unique_chars.len() as u32).div_ceil(chars_per_row);
+        self.atlas_height = self.atlas_width.max(rows * char_size);
+        
+        let atlas_size = (self.atlas_width * self.atlas_height) as usize;
+        let mut atlas_data = vec![128u8; atlas_size]; // Initialize with middle gray
         
         self.glyph_map.clear();
         
@@ -205,11 +213,11 @@ fn main(@location(0) tex_coord: vec2<f32>) -> @location(0) vec4<f32> {
             let char_x = (i as u32 % chars_per_row) * char_size;
             let char_y = (i as u32 / chars_per_row) * char_size;
             
-            let (metrics, bitmap) = self.font.rasterize(ch, 12.0);
+            let (metrics, bitmap) = self.font.rasterize(ch, self.config.raster_size);
             
             if !bitmap.is_empty() && metrics.width > 0 && metrics.height > 0 {
                 // Generate SDF
-                let bitmap_glyph = match BitmapGlyph::from_unbuffered(&bitmap, metrics.width, metrics.height, self.buffer_size) {
+                let bitmap_glyph = match BitmapGlyph::from_unbuffered(&bitmap, metrics.width, metrics.height, padding) {
                     Ok(glyph) => glyph,
                     Err(e) => {
                         console_log!("SDF glyph creation failed for '{}': {:?}", ch, e);
@@ -217,11 +225,11 @@ fn main(@location(0) tex_coord: vec2<f32>) -> @location(0) vec4<f32> {
                     }
                 };
                 
-                let sdf_radius = 4.0;
-                let sdf_data = bitmap_glyph.render_sdf(sdf_radius as usize);
+                let sdf_radius = self.config.sdf_radius as f64;
+                let sdf_data = bitmap_glyph.render_sdf(sdf_radius.ceil() as usize);
                 
-                let sdf_width = metrics.width + 2 * self.buffer_size;
-                let sdf_height = metrics.height + 2 * self.buffer_size;
+                let sdf_width = metrics.width + 2 * padding;
+                let sdf_height = metrics.height + 2 * padding;
                 
                 // Store glyph info
                 self.glyph_map.insert(ch, GlyphInfo {
@@ -321,13 +329,13 @@ fn main(@location(0) tex_coord: vec2<f32>) -> @location(0) vec4<f32> {
 
     fn generate_text_vertices(&self, text: &str, x: f32, y: f32, screen_width: f32, screen_height: f32) -> Vec<f32> {
         let mut layout = Layout::new(CoordinateSystem::PositiveYDown);
-        let fonts = &[&self.font];
+        let fonts = &[&*self.font];
         
         let mut layout_settings = LayoutSettings::default();
         layout_settings.x = x;
         layout_settings.y = y;
         layout.reset(&layout_settings);
-        layout.append(fonts, &TextStyle::new(text, 12.0, 0));
+        layout.append(fonts, &TextStyle::new(text, self.config.pixel_size(), 0));
     
         let mut vertices = Vec::new();
         
@@ -337,12 +345,13 @@ fn main(@location(0) tex_coord: vec2<f32>) -> @location(0) vec4<f32> {
                 let glyph_left = glyph.x as f32;
                 let glyph_top = glyph.y as f32;
                 
-                // Account for the SDF buffer padding
-                let buffer_offset = self.buffer_size as f32;
+                // Account for the SDF buffer padding; the atlas holds glyphs at the raster size
+                let scale = self.config.glyph_scale();
+                let buffer_offset = self.config.sdf_padding as f32 * scale;
                 let screen_left = glyph_left - buffer_offset;
                 let screen_top = glyph_top - buffer_offset;
-                let screen_right = screen_left + glyph_info.sdf_width;
-                let screen_bottom = screen_top + glyph_info.sdf_height;
+                let screen_right = screen_left + glyph_info.sdf_width * scale;
+                let screen_bottom = screen_top + glyph_info.sdf_height * scale;
                 
                 // Convert to NDC (-1 to 1 range)
                 let left = (screen_left / screen_width) * 2.0 - 1.0;
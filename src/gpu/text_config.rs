@@ -0,0 +1,85 @@
+// Text Rendering Configuration
+// Sizes shared by the text renderers
+// Responsibilities:
+// - Separate the size text is displayed at from the size glyphs are rasterized into the atlas at
+// - Describe the distance field spread and the padding kept around each glyph
+// - Clamp display sizes so zooming stays within what the atlas can reproduce
+// - Scale display sizes from CSS pixels to device pixels
+// - Choose where long lines soft-wrap
+
+// Display sizes reachable by zooming, in CSS pixels
+pub const MIN_FONT_SIZE: f32 = 6.0;
+pub const MAX_FONT_SIZE: f32 = 96.0;
+
+// Where long lines wrap onto further rows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WrapMode {
+    // Lines run past the right edge
+    None,
+    // At the right edge of the viewport
+    Viewport,
+    // After this many columns, measured in widths of the font's "0"
+    Column(u32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TextConfig {
+    // Size glyphs are drawn at, in CSS pixels
+    pub font_size: f32,
+    // Device pixels per CSS pixel of the canvas being drawn to
+    pub pixel_ratio: f32,
+    // Size glyphs are rasterized into the atlas at; quads are scaled from it to `font_size`
+    pub raster_size: f32,
+    // Distance (in raster pixels) covered by the 0..1 range of the distance field
+    pub sdf_radius: f32,
+    // Empty texels around each glyph bitmap so the field can fade out
+    pub sdf_padding: usize,
+    // Line spacing as a multiple of the font's own line height
+    pub line_height: f32,
+    pub wrap: WrapMode,
+}
+
+impl Default for TextConfig {
+    fn default() -> Self {
+        Self {
+            font_size: 12.0,
+            pixel_ratio: 1.0,
+            raster_size: 32.0,
+            sdf_radius: 6.0,
+            sdf_padding: 6,
+            line_height: 1.0,
+            wrap: WrapMode::None,
+        }
+    }
+}
+
+impl TextConfig {
+    // Size glyphs are drawn at in device pixels, which is what layout measures in
+    pub fn pixel_size(&self) -> f32 {
+        self.font_size * self.pixel_ratio
+    }
+
+    // Atlas texels to device pixels
+    pub fn glyph_scale(&self) -> f32 {
+        self.pixel_size() / self.raster_size
+    }
+
+    pub fn with_font_size(self, font_size: f32) -> Self {
+        Self {
+            font_size: font_size.clamp(MIN_FONT_SIZE, MAX_FONT_SIZE),
+            ..self
+        }
+    }
+
+    // Whether glyphs rasterized under `other` can be reused as they are
+    pub fn same_atlas(&self, other: &TextConfig) -> bool {
+        self.raster_size == other.raster_size
+            && self.sdf_radius == other.sdf_radius
+            && self.sdf_padding == other.sdf_padding
+    }
+
+    // Grid cell that fits any glyph at the raster size, for fixed-grid atlases
+    pub fn cell_size(&self) -> u32 {
+        (self.raster_size * 2.0).ceil() as u32 + 2 * self.sdf_padding as u32
+    }
+}
@@ -0,0 +1,261 @@
+// Edit History
+// Records reversible edits so they can be undone and redone
+// Responsibilities:
+// - Store each edit with enough text to build its inverse
+// - Coalesce consecutive typing (or backspacing) into a single undo step, one word at a time
+// - Restore the selection from before/after each transaction
+
+use crate::document::Document;
+use crate::selection::Selection;
+
+// Keystrokes further apart than this start a new undo step
+const COALESCE_WINDOW_MS: f64 = 1000.0;
+const MAX_UNDO_DEPTH: usize = 1000;
+
+// Replacement of `removed` at char index `start` with `inserted`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Edit {
+    pub start: usize,
+    pub removed: String,
+    pub inserted: String,
+}
+
+impl Edit {
+    pub fn inverse(&self) -> Edit {
+        Edit {
+            start: self.start,
+            removed: self.inserted.clone(),
+            inserted: self.removed.clone(),
+        }
+    }
+
+    pub fn apply(&self, document: &mut Document) {
+        let removed_len = self.removed.chars().count();
+        document.replace(self.start..self.start + removed_len, &self.inserted);
+    }
+
+    fn inserted_end(&self) -> usize {
+        self.start + self.inserted.chars().count()
+    }
+
+    fn removed_len(&self) -> usize {
+        self.removed.chars().count()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EditKind {
+    Typing,
+    Deletion,
+    // Pastes, selection deletes and anything else that is an undo step on its own
+    Other,
+}
+
+#[derive(Debug, Clone)]
+pub struct Transaction {
+    edits: Vec<Edit>,
+    kind: EditKind,
+    selection_before: Selection,
+    selection_after: Selection,
+    last_edit_ms: f64,
+}
+
+impl Transaction {
+    // Try to fold `edit` into this transaction; returns false if it must start a new step
+    fn coalesce(&mut self, edit: &Edit, kind: EditKind, now_ms: f64) -> bool {
+        if kind != self.kind || now_ms - self.last_edit_ms > COALESCE_WINDOW_MS {
+            return false;
+        }
+        let Some(last) = self.edits.last_mut() else {
+            return false;
+        };
+
+        match kind {
+            // Typing continues right where the previous insertion ended, until a new word
+            // starts after whitespace
+            EditKind::Typing if edit.removed.is_empty()
+                && edit.start == last.inserted_end()
+                && !starts_word(&last.inserted, &edit.inserted) =>
+            {
+                last.inserted.push_str(&edit.inserted);
+                true
+            }
+            // Backspacing removes the text just before the previous deletion
+            EditKind::Deletion if edit.inserted.is_empty() && edit.start + edit.removed_len() == last.start => {
+                last.removed.insert_str(0, &edit.removed);
+                last.start = edit.start;
+                true
+            }
+            // Forward deletes keep removing at the same position
+            EditKind::Deletion if edit.inserted.is_empty() && last.inserted.is_empty() && edit.start == last.start => {
+                last.removed.push_str(&edit.removed);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+// Whether `next` begins a word after `typed` ended in whitespace
+fn starts_word(typed: &str, next: &str) -> bool {
+    typed.chars().next_back().is_some_and(char::is_whitespace)
+        && next.chars().next().is_some_and(|ch| !ch.is_whitespace())
+}
+
+#[derive(Default)]
+pub struct History {
+    undo_stack: Vec<Transaction>,
+    redo_stack: Vec<Transaction>,
+    // Set when the cursor moves or an undo happens so the next edit starts a fresh step
+    sealed: bool,
+}
+
+impl History {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Record an edit that has already been applied to the document
+    pub fn record(&mut self, edit: Edit, kind: EditKind, before: Selection, after: Selection, now_ms: f64) {
+        self.redo_stack.clear();
+
+        if !self.sealed
+            && let Some(current) = self.undo_stack.last_mut()
+            && current.coalesce(&edit, kind, now_ms)
+        {
+            current.selection_after = after;
+            current.last_edit_ms = now_ms;
+            return;
+        }
+
+        self.undo_stack.push(Transaction {
+            edits: vec![edit],
+            kind,
+            selection_before: before,
+            selection_after: after,
+            last_edit_ms: now_ms,
+        });
+        self.sealed = kind == EditKind::Other;
+
+        if self.undo_stack.len() > MAX_UNDO_DEPTH {
+            self.undo_stack.remove(0);
+        }
+    }
+
+    // Stop coalescing into the current transaction
+    pub fn seal(&mut self) {
+        self.sealed = true;
+    }
+
+    // Revert the latest transaction, returning the selection to restore
+    pub fn undo(&mut self, document: &mut Document) -> Option<Selection> {
+        let transaction = self.undo_stack.pop()?;
+        for edit in transaction.edits.iter().rev() {
+            edit.inverse().apply(document);
+        }
+        let selection = transaction.selection_before;
+        self.redo_stack.push(transaction);
+        self.sealed = true;
+        Some(selection)
+    }
+
+    // Re-apply the latest undone transaction, returning the selection to restore
+    pub fn redo(&mut self, document: &mut Document) -> Option<Selection> {
+        let transaction = self.redo_stack.pop()?;
+        for edit in &transaction.edits {
+            edit.apply(document);
+        }
+        let selection = transaction.selection_after;
+        self.undo_stack.push(transaction);
+        self.sealed = true;
+        Some(selection)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Type `text` one char at a time at `at`, `interval_ms` apart starting from `now_ms`
+    fn type_text(history: &mut History, document: &mut Document, at: usize, text: &str, now_ms: f64, interval_ms: f64) {
+        for (i, ch) in text.chars().enumerate() {
+            let edit = Edit { start: at + i, removed: String::new(), inserted: ch.to_string() };
+            edit.apply(document);
+            let time = now_ms + i as f64 * interval_ms;
+            history.record(edit, EditKind::Typing, Selection::caret(at + i), Selection::caret(at + i + 1), time);
+        }
+    }
+
+    // Remove `range` as Backspace, Delete or a selection delete would
+    fn delete(history: &mut History, document: &mut Document, range: std::ops::Range<usize>, kind: EditKind, now_ms: f64) {
+        let edit = Edit { start: range.start, removed: document.slice(range.clone()).to_string(), inserted: String::new() };
+        edit.apply(document);
+        history.record(edit, kind, Selection::caret(range.end), Selection::caret(range.start), now_ms);
+    }
+
+    #[test]
+    fn typing_coalesces_into_one_step() {
+        let (mut history, mut document) = (History::new(), Document::new());
+        type_text(&mut history, &mut document, 0, "word", 0.0, 100.0);
+        assert_eq!(history.undo(&mut document), Some(Selection::caret(0)));
+        assert_eq!(document.to_string(), "");
+        assert_eq!(history.undo(&mut document), None);
+
+        // A pause longer than the coalescing window starts a new step
+        type_text(&mut history, &mut document, 0, "ab", 0.0, COALESCE_WINDOW_MS + 1.0);
+        history.undo(&mut document);
+        assert_eq!(document.to_string(), "a");
+    }
+
+    #[test]
+    fn typing_breaks_at_word_boundaries() {
+        let (mut history, mut document) = (History::new(), Document::new());
+        type_text(&mut history, &mut document, 0, "one two  three", 0.0, 10.0);
+        // Each word keeps the whitespace typed after it
+        assert_eq!(history.undo(&mut document), Some(Selection::caret(9)));
+        assert_eq!(document.to_string(), "one two  ");
+        history.undo(&mut document);
+        assert_eq!(document.to_string(), "one ");
+        history.undo(&mut document);
+        assert_eq!(document.to_string(), "");
+    }
+
+    #[test]
+    fn selection_delete_then_backspace_are_separate_steps() {
+        let (mut history, mut document) = (History::new(), Document::from("hello world"));
+        delete(&mut history, &mut document, 5..11, EditKind::Other, 0.0);
+        delete(&mut history, &mut document, 4..5, EditKind::Deletion, 10.0);
+        delete(&mut history, &mut document, 3..4, EditKind::Deletion, 20.0);
+        assert_eq!(document.to_string(), "hel");
+        // Both backspaces undo together, then the selection comes back
+        assert_eq!(history.undo(&mut document), Some(Selection::caret(5)));
+        assert_eq!(document.to_string(), "hello");
+        assert_eq!(history.undo(&mut document), Some(Selection::caret(11)));
+        assert_eq!(document.to_string(), "hello world");
+
+        // Forward deletes at one position coalesce too
+        delete(&mut history, &mut document, 0..1, EditKind::Deletion, 30.0);
+        delete(&mut history, &mut document, 0..1, EditKind::Deletion, 40.0);
+        assert_eq!(document.to_string(), "llo world");
+        history.undo(&mut document);
+        assert_eq!(document.to_string(), "hello world");
+    }
+
+    #[test]
+    fn redo_replays_until_a_new_edit() {
+        let (mut history, mut document) = (History::new(), Document::new());
+        type_text(&mut history, &mut document, 0, "abc", 0.0, 10.0);
+        history.undo(&mut document);
+        assert_eq!(history.redo(&mut document), Some(Selection::caret(3)));
+        assert_eq!(document.to_string(), "abc");
+        assert_eq!(history.redo(&mut document), None);
+
+        // An edit after an undo drops what could have been redone
+        history.undo(&mut document);
+        type_text(&mut history, &mut document, 0, "x", 100.0, 10.0);
+        assert_eq!(history.redo(&mut document), None);
+        assert_eq!(document.to_string(), "x");
+        history.undo(&mut document);
+        assert_eq!(document.to_string(), "");
+    }
+}
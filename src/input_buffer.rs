@@ -1,4 +1,8 @@
 use wasm_bindgen::prelude::*;
+use crate::gpu::caret::{CaretShape, CaretStyle};
+use crate::gpu::fast_text::{FastTextRenderer, GlyphMode};
+use crate::gpu::text_config::{TextConfig, WrapMode};
+use crate::selection::Selection;
 
 // Simplified input buffer - operations are sent directly to GPU renderer
 
@@ -16,55 +20,23 @@ async fn get_renderer() -> Result<std::rc::Rc<std::cell::RefCell<crate::text_inp
     crate::text_input::get_or_init_webgpu_resources().await
 }
 
-// High-performance input operations
-#[wasm_bindgen]
-pub fn insert_char_at_cursor(char_code: u32) {
-    if let Some(ch) = char::from_u32(char_code) {
-        console_log!("insert_char_at_cursor: '{}'", ch);
-        
-        wasm_bindgen_futures::spawn_local(async move {
-            match get_renderer().await {
-                Ok(resources) => {
-                    let mut borrowed = resources.borrow_mut();
-                    if let Some(renderer) = borrowed.fast_text_renderer.as_mut() {
-                        if let Err(e) = renderer.insert_char(ch) {
-                            console_log!("Error inserting char: {:?}", e);
-                        } else {
-                            // Trigger render with updated text
-                            let text = renderer.get_text();
-                            let cursor_pos = renderer.get_cursor_position();
-                            drop(borrowed); // Drop the borrow before calling render_from_buffer
-                            if let Err(e) = crate::fast_text_input::render_from_buffer(&text, cursor_pos) {
-                                console_log!("Render error: {:?}", e);
-                            }
-                        }
-                    } else {
-                        console_log!("Fast text renderer not initialized");
-                    }
-                }
-                Err(e) => console_log!("Failed to get renderer: {:?}", e),
-            }
-        });
-    }
-}
-
-#[wasm_bindgen]
-pub fn delete_char_at_cursor() {
-    console_log!("delete_char_at_cursor");
-    
+// Run an editor operation against the fast renderer, then re-render its new state
+fn apply_to_renderer<F>(name: &'static str, op: F)
+where
+    F: FnOnce(&mut FastTextRenderer) -> Result<(), JsValue> + 'static,
+{
     wasm_bindgen_futures::spawn_local(async move {
         match get_renderer().await {
             Ok(resources) => {
                 let mut borrowed = resources.borrow_mut();
                 if let Some(renderer) = borrowed.fast_text_renderer.as_mut() {
-                    if let Err(e) = renderer.delete_char_before_cursor() {
-                        console_log!("Error deleting char: {:?}", e);
+                    if let Err(e) = op(renderer) {
+                        console_log!("Error in {}: {:?}", name, e);
                     } else {
                         // Trigger render with updated text
-                        let text = renderer.get_text();
-                        let cursor_pos = renderer.get_cursor_position();
+                        let (snapshot, selection) = renderer.display();
                         drop(borrowed); // Drop the borrow before calling render_from_buffer
-                        if let Err(e) = crate::fast_text_input::render_from_buffer(&text, cursor_pos) {
+                        if let Err(e) = crate::fast_text_input::render_from_buffer(snapshot, selection) {
                             console_log!("Render error: {:?}", e);
                         }
                     }
@@ -77,57 +49,495 @@ pub fn delete_char_at_cursor() {
     });
 }
 
+// High-performance input operations
+#[wasm_bindgen]
+pub fn insert_char_at_cursor(char_code: u32) {
+    if let Some(ch) = char::from_u32(char_code) {
+        console_log!("insert_char_at_cursor: '{}'", ch);
+        apply_to_renderer("insert_char_at_cursor", move |renderer| renderer.insert_char(ch));
+    }
+}
+
+// Replace the selection with typed, pasted or dropped text
+#[wasm_bindgen]
+pub fn insert_text(text: &str) {
+    console_log!("insert_text: {} chars", text.chars().count());
+    let text = text.to_string();
+    apply_to_renderer("insert_text", move |renderer| {
+        renderer.insert_text(&text);
+        Ok(())
+    });
+}
+
+// Replace a span given in UTF-16 code units with `text`, leaving the caret after it
+#[wasm_bindgen]
+pub fn replace_range(start: u32, end: u32, text: &str) {
+    console_log!("replace_range: {}..{} with {} chars", start, end, text.chars().count());
+    let text = text.to_string();
+    apply_to_renderer("replace_range", move |renderer| {
+        let document = renderer.document();
+        let range = document.utf16_to_char(start as usize)..document.utf16_to_char(end as usize);
+        renderer.replace_range(range, &text);
+        Ok(())
+    });
+}
+
+#[wasm_bindgen]
+pub fn delete_char_at_cursor() {
+    console_log!("delete_char_at_cursor");
+    apply_to_renderer("delete_char_at_cursor", |renderer| renderer.delete_char_before_cursor());
+}
+
+// Forward Delete
+#[wasm_bindgen]
+pub fn delete_char_after_cursor() {
+    console_log!("delete_char_after_cursor");
+    apply_to_renderer("delete_char_after_cursor", |renderer| {
+        renderer.delete_char_after_cursor();
+        Ok(())
+    });
+}
+
+#[wasm_bindgen]
+pub fn delete_word_before_cursor(subword: bool) {
+    console_log!("delete_word_before_cursor: subword={}", subword);
+    apply_to_renderer("delete_word_before_cursor", move |renderer| {
+        renderer.delete_word(false, subword);
+        Ok(())
+    });
+}
+
+#[wasm_bindgen]
+pub fn delete_word_after_cursor(subword: bool) {
+    console_log!("delete_word_after_cursor: subword={}", subword);
+    apply_to_renderer("delete_word_after_cursor", move |renderer| {
+        renderer.delete_word(true, subword);
+        Ok(())
+    });
+}
+
+#[wasm_bindgen]
+pub fn delete_selection() {
+    console_log!("delete_selection");
+    apply_to_renderer("delete_selection", |renderer| {
+        renderer.delete_selection();
+        Ok(())
+    });
+}
+
+// The selected text, for copy and cut. Read synchronously because the clipboard can only
+// be written while its event is being dispatched.
+#[wasm_bindgen]
+pub fn selected_text() -> Option<String> {
+    let resources = crate::text_input::webgpu_resources()?;
+    let borrowed = resources.try_borrow().ok()?;
+    borrowed.fast_text_renderer.as_ref().map(|renderer| renderer.selected_text())
+}
+
 #[wasm_bindgen]
 pub fn move_cursor_left() {
     console_log!("move_cursor_left");
-    
-    wasm_bindgen_futures::spawn_local(async move {
-        match get_renderer().await {
-            Ok(resources) => {
-                let mut borrowed = resources.borrow_mut();
-                if let Some(renderer) = borrowed.fast_text_renderer.as_mut() {
-                    renderer.move_cursor_left();
-                    // Trigger render with updated cursor position
-                    let text = renderer.get_text();
-                    let cursor_pos = renderer.get_cursor_position();
-                    drop(borrowed); // Drop the borrow before calling render_from_buffer
-                    if let Err(e) = crate::fast_text_input::render_from_buffer(&text, cursor_pos) {
-                        console_log!("Render error: {:?}", e);
-                    }
-                } else {
-                    console_log!("Fast text renderer not initialized");
-                }
-            }
-            Err(e) => console_log!("Failed to get renderer: {:?}", e),
-        }
+    apply_to_renderer("move_cursor_left", |renderer| {
+        renderer.move_cursor_left();
+        Ok(())
     });
 }
 
 #[wasm_bindgen]
 pub fn move_cursor_right() {
     console_log!("move_cursor_right");
+    apply_to_renderer("move_cursor_right", |renderer| {
+        renderer.move_cursor_right();
+        Ok(())
+    });
+}
+
+#[wasm_bindgen]
+pub fn extend_selection_left() {
+    console_log!("extend_selection_left");
+    apply_to_renderer("extend_selection_left", |renderer| {
+        renderer.extend_selection_left();
+        Ok(())
+    });
+}
+
+#[wasm_bindgen]
+pub fn extend_selection_right() {
+    console_log!("extend_selection_right");
+    apply_to_renderer("extend_selection_right", |renderer| {
+        renderer.extend_selection_right();
+        Ok(())
+    });
+}
+
+// Word motions; `subword` stops inside camelCase and snake_case identifiers
+#[wasm_bindgen]
+pub fn move_word_left(subword: bool, extend: bool) {
+    console_log!("move_word_left: subword={} extend={}", subword, extend);
+    apply_to_renderer("move_word_left", move |renderer| {
+        renderer.move_word(false, subword, extend);
+        Ok(())
+    });
+}
+
+#[wasm_bindgen]
+pub fn move_word_right(subword: bool, extend: bool) {
+    console_log!("move_word_right: subword={} extend={}", subword, extend);
+    apply_to_renderer("move_word_right", move |renderer| {
+        renderer.move_word(true, subword, extend);
+        Ok(())
+    });
+}
+
+// Vertical motions; `extend` keeps the anchor (Shift held)
+#[wasm_bindgen]
+pub fn move_cursor_up(extend: bool) {
+    console_log!("move_cursor_up: extend={}", extend);
+    apply_to_renderer("move_cursor_up", move |renderer| {
+        renderer.move_lines(-1, extend);
+        Ok(())
+    });
+}
+
+#[wasm_bindgen]
+pub fn move_cursor_down(extend: bool) {
+    console_log!("move_cursor_down: extend={}", extend);
+    apply_to_renderer("move_cursor_down", move |renderer| {
+        renderer.move_lines(1, extend);
+        Ok(())
+    });
+}
+
+#[wasm_bindgen]
+pub fn page_up(extend: bool) {
+    console_log!("page_up: extend={}", extend);
+    apply_to_renderer("page_up", move |renderer| {
+        renderer.move_pages(-1, extend);
+        Ok(())
+    });
+}
+
+#[wasm_bindgen]
+pub fn page_down(extend: bool) {
+    console_log!("page_down: extend={}", extend);
+    apply_to_renderer("page_down", move |renderer| {
+        renderer.move_pages(1, extend);
+        Ok(())
+    });
+}
+
+// Home/End; with `document` set (Ctrl held) they go to the start or end of the text
+#[wasm_bindgen]
+pub fn move_cursor_home(document: bool, extend: bool) {
+    console_log!("move_cursor_home: document={} extend={}", document, extend);
+    apply_to_renderer("move_cursor_home", move |renderer| {
+        if document {
+            renderer.move_to_document_edge(false, extend);
+        } else {
+            renderer.move_to_line_edge(false, extend);
+        }
+        Ok(())
+    });
+}
+
+#[wasm_bindgen]
+pub fn move_cursor_end(document: bool, extend: bool) {
+    console_log!("move_cursor_end: document={} extend={}", document, extend);
+    apply_to_renderer("move_cursor_end", move |renderer| {
+        if document {
+            renderer.move_to_document_edge(true, extend);
+        } else {
+            renderer.move_to_line_edge(true, extend);
+        }
+        Ok(())
+    });
+}
+
+#[wasm_bindgen]
+pub fn select_all() {
+    console_log!("select_all");
+    apply_to_renderer("select_all", |renderer| {
+        renderer.select_all();
+        Ok(())
+    });
+}
+
+// The document and selection for script callers. Offsets are UTF-16 code units, the way
+// JavaScript strings count, so they can index the text directly.
+#[wasm_bindgen]
+pub fn get_text() -> Option<String> {
+    let resources = crate::text_input::webgpu_resources()?;
+    let borrowed = resources.try_borrow().ok()?;
+    borrowed.fast_text_renderer.as_ref().map(|renderer| renderer.get_text())
+}
+
+// Replace the whole document, as one undo step, with the caret at the start
+#[wasm_bindgen]
+pub fn set_text(text: &str) {
+    console_log!("set_text: {} chars", text.chars().count());
+    let text = text.to_string();
+    apply_to_renderer("set_text", move |renderer| {
+        renderer.set_text(&text, Selection::caret(0));
+        Ok(())
+    });
+}
+
+// [anchor, head] in UTF-16 code units
+#[wasm_bindgen]
+pub fn get_selection() -> Option<Vec<u32>> {
+    let resources = crate::text_input::webgpu_resources()?;
+    let borrowed = resources.try_borrow().ok()?;
+    let renderer = borrowed.fast_text_renderer.as_ref()?;
+    let selection = renderer.selection();
+    let document = renderer.document();
+    Some(vec![
+        document.char_to_utf16(selection.anchor) as u32,
+        document.char_to_utf16(selection.head) as u32,
+    ])
+}
+
+// Offsets inside a surrogate pair or a grapheme cluster move to the cluster's start
+#[wasm_bindgen]
+pub fn set_selection(anchor: u32, head: u32) {
+    console_log!("set_selection: {}..{}", anchor, head);
+    apply_to_renderer("set_selection", move |renderer| {
+        let anchor = renderer.document().utf16_to_caret(anchor as usize);
+        let head = renderer.document().utf16_to_caret(head as usize);
+        renderer.set_selection(Selection::new(anchor, head));
+        Ok(())
+    });
+}
+
+// Mouse press at a point in canvas pixels; `clicks` is the click count (2 = word, 3 = line)
+#[wasm_bindgen]
+pub fn click_at(x: f32, y: f32, clicks: u32, extend: bool) {
+    console_log!("click_at: ({}, {}) clicks={} extend={}", x, y, clicks, extend);
+    apply_to_renderer("click_at", move |renderer| {
+        renderer.click(x, y, clicks, extend);
+        Ok(())
+    });
+}
+
+// Pointer moved while pressed, in canvas pixels
+#[wasm_bindgen]
+pub fn drag_to(x: f32, y: f32) {
+    apply_to_renderer("drag_to", move |renderer| {
+        renderer.drag_to(x, y);
+        Ok(())
+    });
+}
+
+// Scroll while dragging outside the text, keeping the selection under the pointer
+#[wasm_bindgen]
+pub fn drag_scroll(dx: f32, dy: f32, x: f32, y: f32) {
+    apply_to_renderer("drag_scroll", move |renderer| {
+        renderer.scroll_by(dx, dy);
+        renderer.drag_to(x, y);
+        Ok(())
+    });
+}
+
+// Wheel or trackpad scroll in canvas pixels, finished off by a glide
+#[wasm_bindgen]
+pub fn scroll_wheel(dx: f32, dy: f32) {
+    apply_to_renderer("scroll_wheel", move |renderer| {
+        renderer.scroll_wheel(dx, dy);
+        crate::fast_text_input::start_momentum();
+        Ok(())
+    });
+}
+
+// One step of the wheel glide; the timer driving it stops once the text is at rest
+pub fn step_scroll() {
+    apply_to_renderer("step_scroll", |renderer| {
+        if !renderer.step_scroll() {
+            crate::fast_text_input::stop_momentum();
+        }
+        Ok(())
+    });
+}
+
+#[wasm_bindgen]
+pub fn release_pointer() {
+    console_log!("release_pointer");
+    apply_to_renderer("release_pointer", |renderer| {
+        renderer.release();
+        Ok(())
+    });
+}
+
+#[wasm_bindgen]
+pub fn undo() {
+    console_log!("undo");
+    apply_to_renderer("undo", |renderer| {
+        renderer.undo();
+        Ok(())
+    });
+}
+
+#[wasm_bindgen]
+pub fn redo() {
+    console_log!("redo");
+    apply_to_renderer("redo", |renderer| {
+        renderer.redo();
+        Ok(())
+    });
+}
+
+// In-progress IME text, drawn underlined at the caret until the composition ends
+#[wasm_bindgen]
+pub fn update_composition(text: &str) {
+    console_log!("update_composition: '{}'", text);
+    let text = text.to_string();
+    apply_to_renderer("update_composition", move |renderer| {
+        renderer.set_preedit(&text);
+        Ok(())
+    });
+}
+
+// Commit the composed text into the document
+#[wasm_bindgen]
+pub fn end_composition(text: &str) {
+    console_log!("end_composition: '{}'", text);
+    let text = text.to_string();
+    apply_to_renderer("end_composition", move |renderer| {
+        renderer.commit_preedit(&text);
+        Ok(())
+    });
+}
+
+#[wasm_bindgen]
+pub fn set_glyph_mode(msdf: bool) {
+    console_log!("set_glyph_mode: msdf={}", msdf);
+    let mode = if msdf { GlyphMode::Msdf } else { GlyphMode::Sdf };
+    apply_to_renderer("set_glyph_mode", move |renderer| renderer.set_glyph_mode(mode));
+}
+
+// Scale the editor's display font size; glyphs already in the atlas are reused
+#[wasm_bindgen]
+pub fn zoom(factor: f32) {
+    console_log!("zoom: {}", factor);
+    apply_to_renderer("zoom", move |renderer| {
+        renderer.zoom(factor);
+        Ok(())
+    });
+}
+
+#[wasm_bindgen]
+pub fn reset_zoom() {
+    console_log!("reset_zoom");
+    apply_to_renderer("reset_zoom", |renderer| {
+        renderer.reset_zoom();
+        Ok(())
+    });
+}
+
+// Replace the renderer's sizes. Changing the raster size or spread re-rasterizes the atlas.
+#[wasm_bindgen]
+pub fn set_text_config(font_size: f32, raster_size: f32, sdf_radius: f32, line_height: f32) {
+    console_log!("set_text_config: size={} raster={} radius={} line_height={}", font_size, raster_size, sdf_radius, line_height);
+    apply_to_renderer("set_text_config", move |renderer| {
+        let config = TextConfig {
+            raster_size: raster_size.max(1.0),
+            sdf_radius: sdf_radius.max(1.0),
+            // Padding must hold the whole spread or the field is cut off at the glyph box
+            sdf_padding: sdf_radius.max(1.0).ceil() as usize,
+            line_height: line_height.max(0.5),
+            ..renderer.config()
+        };
+        renderer.set_config(config.with_font_size(font_size))
+    });
+}
+
+// Soft-wrap long lines at the viewport edge, or after `column` columns when given
+#[wasm_bindgen]
+pub fn set_soft_wrap(enabled: bool, column: Option<u32>) {
+    console_log!("set_soft_wrap: enabled={} column={:?}", enabled, column);
+    let wrap = match (enabled, column) {
+        (false, _) => WrapMode::None,
+        (true, Some(column)) => WrapMode::Column(column.max(1)),
+        (true, None) => WrapMode::Viewport,
+    };
+    apply_to_renderer("set_soft_wrap", move |renderer| {
+        renderer.set_config(TextConfig { wrap, ..renderer.config() })
+    });
+}
+
+// Caret look: `shape` is "bar", "block" or "underline"; `width` is the bar width or
+// underline thickness in pixels and `blink_interval_ms` 0 keeps the caret solid. Omitted
+// values keep their current setting.
+#[wasm_bindgen]
+pub fn set_caret_style(shape: &str, width: Option<f32>, blink_interval_ms: Option<f64>) -> Result<(), JsValue> {
+    console_log!("set_caret_style: shape={} width={:?} blink={:?}", shape, width, blink_interval_ms);
+    let shape = match shape {
+        "bar" => CaretShape::Bar,
+        "block" => CaretShape::Block,
+        "underline" => CaretShape::Underline,
+        _ => return Err(JsValue::from_str(&format!("Unknown caret shape: {}", shape))),
+    };
+    apply_to_renderer("set_caret_style", move |renderer| {
+        let style = renderer.caret_style();
+        renderer.set_caret_style(CaretStyle {
+            shape,
+            width: width.map_or(style.width, |width| width.max(0.5)),
+            blink_interval_ms: blink_interval_ms.map_or(style.blink_interval_ms, |interval| interval.max(0.0)),
+            ..style
+        });
+        Ok(())
+    });
+    Ok(())
+}
+
+// Add a font from TTF/OTF bytes, returning its id for `set_active_font`
+#[wasm_bindgen]
+pub fn register_font(name: &str, data: Vec<u8>) -> Result<usize, JsValue> {
+    console_log!("register_font: '{}' ({} bytes)", name, data.len());
+    crate::fonts::with_registry(|registry| registry.register(name, data))?
+}
+
+// Fetch a font file, register it under `name` and switch to it
+#[wasm_bindgen]
+pub async fn load_font(name: String, url: String) -> Result<usize, JsValue> {
+    console_log!("load_font: '{}' from {}", name, url);
+    let data = crate::fonts::fetch_font(&url).await?;
+    let id = register_font(&name, data)?;
+    set_active_font(id)?;
+    Ok(id)
+}
+
+// Make a registered font current and rebuild both renderers against it
+#[wasm_bindgen]
+pub fn set_active_font(id: usize) -> Result<(), JsValue> {
+    crate::fonts::with_registry(|registry| registry.set_active(id).map(|_| ()))??;
+    apply_font_chain()
+}
+
+// Fonts to try, in order, for chars the active font has no glyph for
+#[wasm_bindgen]
+pub fn set_fallback_fonts(ids: Vec<usize>) -> Result<(), JsValue> {
+    crate::fonts::with_registry(|registry| registry.set_fallbacks(&ids))??;
+    apply_font_chain()
+}
+
+fn apply_font_chain() -> Result<(), JsValue> {
+    let chain = crate::fonts::active_chain()?;
+    console_log!("Font chain: {}", chain.fonts().iter().map(|font| font.name.as_str()).collect::<Vec<_>>().join(", "));
     
+    let primary = chain.primary().clone();
     wasm_bindgen_futures::spawn_local(async move {
         match get_renderer().await {
             Ok(resources) => {
-                let mut borrowed = resources.borrow_mut();
-                if let Some(renderer) = borrowed.fast_text_renderer.as_mut() {
-                    renderer.move_cursor_right();
-                    // Trigger render with updated cursor position
-                    let text = renderer.get_text();
-                    let cursor_pos = renderer.get_cursor_position();
-                    drop(borrowed); // Drop the borrow before calling render_from_buffer
-                    if let Err(e) = crate::fast_text_input::render_from_buffer(&text, cursor_pos) {
-                        console_log!("Render error: {:?}", e);
-                    }
-                } else {
-                    console_log!("Fast text renderer not initialized");
+                if let Some(text_renderer) = resources.borrow_mut().text_renderer.as_mut() {
+                    text_renderer.set_font(primary);
                 }
             }
             Err(e) => console_log!("Failed to get renderer: {:?}", e),
         }
     });
+    apply_to_renderer("set_font_chain", move |renderer| renderer.set_fonts(chain));
+    Ok(())
 }
+
 // Legacy compatibility functions for fast_text_input.rs
 static mut TEMP_BUFFER: [u8; 1024] = [0; 1024];
 
@@ -135,28 +545,15 @@ pub fn get_input_buffer_ptr() -> *mut u8 {
     unsafe { std::ptr::addr_of_mut!(TEMP_BUFFER).cast::<u8>() }
 }
 
-pub fn commit_input(length: usize, _cursor_pos: usize) {
+pub fn commit_input(length: usize, cursor_pos: usize) {
     unsafe {
         let text_bytes = &TEMP_BUFFER[..length];
         if let Ok(text) = std::str::from_utf8(text_bytes) {
             // Set the text in the GPU renderer
-            wasm_bindgen_futures::spawn_local(async move {
-                match get_renderer().await {
-                    Ok(resources) => {
-                        let mut borrowed = resources.borrow_mut();
-                        if let Some(renderer) = borrowed.fast_text_renderer.as_mut() {
-                            renderer.set_text(text);
-                            let text = renderer.get_text();
-                            let cursor_pos = renderer.get_cursor_position();
-                            drop(borrowed);
-                            if let Err(e) = crate::fast_text_input::render_from_buffer(&text, cursor_pos) {
-                                console_log!("Render error: {:?}", e);
-                            }
-                        }
-                    }
-                    Err(e) => console_log!("Failed to get renderer: {:?}", e),
-                }
+            apply_to_renderer("commit_input", move |renderer| {
+                renderer.set_text(text, Selection::caret(cursor_pos));
+                Ok(())
             });
         }
     }
-}
\ No newline at end of file
+}
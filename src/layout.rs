@@ -0,0 +1,972 @@
+// Text Layout
+// Splits a document into lines and positions glyphs for rendering
+// Responsibilities:
+// - Maintain a line index with per-line y offsets and heights
+// - Cache laid-out glyph runs per line so unchanged lines are reused
+// - Map between line numbers and y coordinates
+// - Mix glyphs from fallback fonts on one line around a shared baseline
+// - Turn shaped glyph runs into a fixed-point advance stream and per-char caret stops
+// - Move the caret and hit-test in visual order on lines that mix text directions
+// - Soft-wrap long lines into visual rows at Unicode line break opportunities
+// - Only shape lines near the viewport, standing in for the rest with estimated rows
+
+use std::collections::HashMap;
+use std::ops::Range;
+use std::rc::Rc;
+use crate::document::{Change, Document, DocumentSnapshot};
+use crate::fonts::{FontChain, GlyphKey};
+use crate::shaping::{shape_line, ShapedRun};
+use crate::viewport::Viewport;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_linebreak::linebreaks;
+
+// Horizontal positions are accumulated in 26.6 fixed point so that sums come out the
+// same no matter where (or on which processor) they are computed
+pub const FIXED_SCALE: f32 = 1.0 / 64.0;
+
+pub fn to_fixed(px: f32) -> i32 {
+    (px * 64.0).round() as i32
+}
+
+pub fn from_fixed(value: i32) -> f32 {
+    value as f32 * FIXED_SCALE
+}
+
+// Runs the cache keeps before it first drops the ones no row uses
+const MIN_RUN_CACHE: usize = 1024;
+
+// A glyph bitmap of a line. Its x is summed from the line's advance stream when it is
+// drawn; y is relative to the top of the line.
+#[derive(Debug, Clone)]
+pub struct LayoutGlyph {
+    // Font and glyph chosen by the fallback chain and the shaper
+    pub key: GlyphKey,
+    // First char of the cluster the glyph belongs to
+    pub char_offset: usize,
+    // Index of the glyph's entry in the line's advance stream
+    pub stream: usize,
+    pub y: f32,
+    // Shaping offset from the pen position, in pixels with y down
+    pub offset_x: f32,
+    pub offset_y: f32,
+}
+
+// Laid-out contents of a single line, shared between identical lines
+#[derive(Debug)]
+pub struct LineRun {
+    // Glyphs with ink, in visual order
+    pub glyphs: Vec<LayoutGlyph>,
+    // Caret x for every char boundary in the line (len = chars + 1)
+    pub carets: Vec<f32>,
+    // Leading and trailing edge x of every char; the leading edge is on the right in
+    // right-to-left text. Chars of one grapheme cluster share the cluster's span.
+    pub spans: Vec<(f32, f32)>,
+    // Char offsets where grapheme clusters start, plus the line length. The caret only
+    // stops at these.
+    pub boundaries: Vec<usize>,
+    // Fixed-point advance of every shaped glyph in visual order, inkless ones included
+    pub advances: Vec<i32>,
+    pub width: f32,
+    pub ascent: f32,
+    pub height: f32,
+}
+
+impl LineRun {
+    pub fn is_boundary(&self, offset: usize) -> bool {
+        self.boundaries.binary_search(&offset).is_ok()
+    }
+}
+
+// One visual row. Without wrapping every document line is one row; a wrapped line is
+// split into several rows, each laid out on its own.
+#[derive(Debug, Clone)]
+pub struct LineLayout {
+    pub start_char: usize,
+    pub len_chars: usize,
+    pub top: f32,
+    pub run: Rc<LineRun>,
+    // The row ends in a soft wrap rather than at the end of its document line
+    pub wraps: bool,
+    // The row is out of date: either its line was never shaped and the row stands in for
+    // all of it with the run and height of an empty line, or the line was laid out for
+    // another wrap width
+    pub estimated: bool,
+}
+
+impl LineLayout {
+    pub fn height(&self) -> f32 {
+        self.run.height
+    }
+
+    pub fn bottom(&self) -> f32 {
+        self.top + self.run.height
+    }
+
+    pub fn end_char(&self) -> usize {
+        self.start_char + self.len_chars
+    }
+
+    // Caret stop of `char_idx` within the row; every char of an estimated row maps to its start
+    fn offset(&self, char_idx: usize) -> usize {
+        char_idx.saturating_sub(self.start_char).min(self.run.carets.len() - 1)
+    }
+}
+
+// Document-space location of the glyph boundary at a char index
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CaretPosition {
+    pub line: usize,
+    pub x: f32,
+    pub top: f32,
+    pub height: f32,
+    // Left edge and width of the glyph after the boundary (or a fallback cell at end of line)
+    pub cell_x: f32,
+    pub advance: f32,
+}
+
+pub struct TextLayout {
+    font_size: f32,
+    line_height: f32,
+    // Top-left of the first line in document space: canvas pixels with the text scrolled
+    // to its start. Rows never move when the text scrolls.
+    origin_x: f32,
+    origin_y: f32,
+    // Rows wrap to fit this width, if set
+    wrap_width: Option<f32>,
+    // Estimated rows are only shaped when they fall inside this y range; text above
+    // `anchor_y` is kept still on screen when they turn out taller than estimated
+    band: Range<f32>,
+    anchor_y: f32,
+    anchor_shift: f32,
+    lines: Vec<LineLayout>,
+    max_width: f32,
+    // Text the rows belong to; newer revisions are caught up through their edits
+    laid_out: Option<DocumentSnapshot>,
+    // Runs keyed by line (or row) text, and the empty-line run estimated rows borrow
+    run_cache: HashMap<String, Rc<LineRun>>,
+    estimate: Option<Rc<LineRun>>,
+    // Cache size past which runs no row uses any more are dropped
+    prune_at: usize,
+}
+
+impl TextLayout {
+    pub fn new(font_size: f32, line_height: f32) -> Self {
+        Self {
+            font_size,
+            line_height,
+            origin_x: 0.0,
+            origin_y: 0.0,
+            wrap_width: None,
+            band: f32::NEG_INFINITY..f32::INFINITY,
+            anchor_y: f32::NEG_INFINITY,
+            anchor_shift: 0.0,
+            lines: Vec::new(),
+            max_width: 0.0,
+            laid_out: None,
+            run_cache: HashMap::new(),
+            estimate: None,
+            prune_at: MIN_RUN_CACHE,
+        }
+    }
+
+    // Place the text inside the viewport's padding, and shape the rows around where it
+    // is scrolled to. Scrolling itself moves no rows: the offset is applied when drawing.
+    pub fn set_viewport(&mut self, viewport: &Viewport) {
+        let shift = viewport.padding_y - self.origin_y;
+        if shift != 0.0 {
+            for line in &mut self.lines {
+                line.top += shift;
+            }
+        }
+        self.origin_x = viewport.padding_x;
+        self.origin_y = viewport.padding_y;
+        self.band = viewport.layout_band();
+        self.anchor_y = viewport.scroll().1;
+    }
+
+    pub fn origin(&self) -> (f32, f32) {
+        (self.origin_x, self.origin_y)
+    }
+
+    // Change the size lines are laid out at; every cached run is dropped
+    pub fn set_metrics(&mut self, font_size: f32, line_height: f32) {
+        if font_size != self.font_size || line_height != self.line_height {
+            self.font_size = font_size;
+            self.line_height = line_height;
+            self.invalidate();
+        }
+    }
+
+    // Wrap rows at `width` pixels, or not at all. Lines are re-wrapped as they come near
+    // the viewport; cached runs stay valid since they are keyed by their text.
+    pub fn set_wrap_width(&mut self, width: Option<f32>) {
+        if width != self.wrap_width {
+            self.wrap_width = width;
+            for line in &mut self.lines {
+                line.estimated = true;
+            }
+        }
+    }
+
+    // Forget every laid-out line, e.g. after the font changed
+    pub fn invalidate(&mut self) {
+        self.run_cache.clear();
+        self.estimate = None;
+        self.laid_out = None;
+    }
+    
+    // Bring the rows up to date with `document`. Lines touched by the edits since the last
+    // call get estimated rows; estimated rows are then shaped inside the band and next to
+    // the caret. Rows elsewhere are only moved, never rebuilt.
+    pub fn update(&mut self, fonts: &FontChain, document: &Document, caret: usize) {
+        let (font_size, line_height) = (self.font_size, self.line_height);
+        let estimate = Rc::clone(self.estimate.get_or_insert_with(|| Rc::new(layout_line(fonts, "", font_size, line_height))));
+        let mut changed = true;
+        match self.laid_out.take() {
+            Some(old) if !self.lines.is_empty() => match document.changes_since(&old).into_iter().reduce(Change::merge) {
+                Some(change) => self.replace_lines(document, change, &estimate),
+                None => changed = false,
+            },
+            _ => self.estimate_lines(document, &estimate),
+        }
+        self.laid_out = Some(document.snapshot());
+
+        // Shaping can shrink rows and pull more of them into the band
+        loop {
+            let rows = self.rows_between(self.band.start, self.band.end);
+            if !self.lines[rows.clone()].iter().any(|line| line.estimated) {
+                break;
+            }
+            self.shape_lines(fonts, document, rows);
+            changed = true;
+        }
+        let caret_line = document.char_to_line(caret.min(document.len_chars()));
+        let first = self.char_to_line(document.line_to_char(caret_line.saturating_sub(1)));
+        let last = self.char_to_line(document.line_to_char((caret_line + 1).min(document.len_lines() - 1)));
+        if self.lines[first..=last].iter().any(|line| line.estimated) {
+            self.shape_lines(fonts, document, first..last + 1);
+            changed = true;
+        }
+
+        if changed {
+            self.max_width = self.lines.iter().map(|line| line.run.width).fold(0.0, f32::max);
+            if self.run_cache.len() > self.prune_at {
+                self.run_cache.retain(|_, run| Rc::strong_count(run) > 1);
+                self.prune_at = (self.run_cache.len() * 2).max(MIN_RUN_CACHE);
+            }
+        }
+    }
+
+    // One estimated row for every line of `document`
+    fn estimate_lines(&mut self, document: &Document, estimate: &Rc<LineRun>) {
+        let mut top = self.origin_y;
+        self.lines = (0..document.len_lines())
+            .map(|line_idx| {
+                top += estimate.height;
+                LineLayout {
+                    start_char: document.line_to_char(line_idx),
+                    len_chars: document.line_len_chars(line_idx),
+                    top: top - estimate.height,
+                    run: Rc::clone(estimate),
+                    wraps: false,
+                    estimated: true,
+                }
+            })
+            .collect();
+    }
+
+    // Put estimated rows in place of the lines `change` touched, and move the rows below
+    // by the chars and height that came or went. A change at the start of a line also
+    // takes the line before, whose line break it may extend ("\r" followed by "\n").
+    fn replace_lines(&mut self, document: &Document, change: Change, estimate: &Rc<LineRun>) {
+        let mut start = self.char_to_line(change.start);
+        if start > 0 && self.lines[start].start_char == change.start {
+            start -= 1;
+        }
+        while start > 0 && self.lines[start - 1].wraps {
+            start -= 1;
+        }
+        let mut end = self.char_to_line(change.old_end) + 1;
+        while end < self.lines.len() && self.lines[end - 1].wraps {
+            end += 1;
+        }
+
+        // Lines before and after the replaced rows start where they did, so the new
+        // lines lie between them
+        let moved = change.new_end as isize - change.old_end as isize;
+        let first_line = document.char_to_line(self.lines[start].start_char);
+        let end_line = match self.lines.get(end) {
+            Some(next) => document.char_to_line(next.start_char.saturating_add_signed(moved)),
+            None => document.len_lines(),
+        };
+        let old_bottom = self.lines[end - 1].bottom();
+        let mut top = self.lines[start].top;
+        let rows: Vec<LineLayout> = (first_line..end_line)
+            .map(|line_idx| {
+                top += estimate.height;
+                LineLayout {
+                    start_char: document.line_to_char(line_idx),
+                    len_chars: document.line_len_chars(line_idx),
+                    top: top - estimate.height,
+                    run: Rc::clone(estimate),
+                    wraps: false,
+                    estimated: true,
+                }
+            })
+            .collect();
+
+        let tail = start + rows.len();
+        self.lines.splice(start..end, rows);
+        for line in &mut self.lines[tail..] {
+            line.start_char = line.start_char.saturating_add_signed(moved);
+            line.top += top - old_bottom;
+        }
+    }
+
+    // Shape the estimated lines among `rows` (widened to whole lines) into as many rows as
+    // they wrap to, and move the rows below by what they grew
+    fn shape_lines(&mut self, fonts: &FontChain, document: &Document, rows: Range<usize>) {
+        let mut start = rows.start;
+        while start > 0 && self.lines[start - 1].wraps {
+            start -= 1;
+        }
+        let mut end = rows.end.max(start + 1);
+        while end < self.lines.len() && self.lines[end - 1].wraps {
+            end += 1;
+        }
+
+        let old_bottom = self.lines[end - 1].bottom();
+        let mut top = self.lines[start].top;
+        let mut shaped = Vec::with_capacity(end - start);
+        let mut row = start;
+        while row < end {
+            let mut line_end = row + 1;
+            while line_end < end && self.lines[line_end - 1].wraps {
+                line_end += 1;
+            }
+            let line_bottom = self.lines[line_end - 1].bottom();
+            if self.lines[row].estimated {
+                let estimated: f32 = self.lines[row..line_end].iter().map(LineLayout::height).sum();
+                let line_idx = document.char_to_line(self.lines[row].start_char);
+                let first = shaped.len();
+                self.layout_rows(fonts, document, line_idx, top, &mut shaped);
+                let height: f32 = shaped[first..].iter().map(LineLayout::height).sum();
+                // Lines above the anchor report what they grew, so the scroll offset can
+                // follow and the text below stays where it was on screen
+                if line_bottom <= self.anchor_y {
+                    self.anchor_shift += height - estimated;
+                }
+                top += height;
+            } else {
+                for line in &self.lines[row..line_end] {
+                    shaped.push(LineLayout { top, ..line.clone() });
+                    top += line.height();
+                }
+            }
+            row = line_end;
+        }
+
+        let tail = start + shaped.len();
+        self.lines.splice(start..end, shaped);
+        for line in &mut self.lines[tail..] {
+            line.top += top - old_bottom;
+        }
+    }
+
+    // Shape one document line, reusing cached runs, and wrap it into rows from `top` down
+    fn layout_rows(&mut self, fonts: &FontChain, document: &Document, line_idx: usize, top: f32, rows: &mut Vec<LineLayout>) {
+        let start_char = document.line_to_char(line_idx);
+        let len_chars = document.line_len_chars(line_idx);
+        let text: String = document.line(line_idx).chars().take(len_chars).collect();
+        let run = self.cached_run(fonts, &text);
+        let ranges = match self.wrap_width {
+            Some(width) if run.width > width => wrap_line(&text, &run, width),
+            _ => vec![Range { start: 0, end: len_chars }],
+        };
+
+        let row_count = ranges.len();
+        let mut top = top;
+        for (row_idx, range) in ranges.into_iter().enumerate() {
+            let row_run = if row_count == 1 {
+                Rc::clone(&run)
+            } else {
+                let row_text: String = text.chars().skip(range.start).take(range.len()).collect();
+                self.cached_run(fonts, &row_text)
+            };
+            top += row_run.height;
+            rows.push(LineLayout {
+                start_char: start_char + range.start,
+                len_chars: range.len(),
+                top: top - row_run.height,
+                run: row_run,
+                wraps: row_idx + 1 < row_count,
+                estimated: false,
+            });
+        }
+    }
+
+    fn cached_run(&mut self, fonts: &FontChain, text: &str) -> Rc<LineRun> {
+        if let Some(run) = self.run_cache.get(text) {
+            return Rc::clone(run);
+        }
+        let run = Rc::new(layout_line(fonts, text, self.font_size, self.line_height));
+        self.run_cache.insert(text.to_string(), Rc::clone(&run));
+        run
+    }
+
+    // How far lines shaped above the anchor pushed the text below it down since this was
+    // last asked; the scroll offset has to follow
+    pub fn take_anchor_shift(&mut self) -> f32 {
+        std::mem::take(&mut self.anchor_shift)
+    }
+
+    pub fn lines(&self) -> &[LineLayout] {
+        &self.lines
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.lines.len()
+    }
+
+    pub fn line(&self, line_idx: usize) -> Option<&LineLayout> {
+        self.lines.get(line_idx)
+    }
+
+    // Top of the given line in document space; lines past the end stack below the last one
+    pub fn line_to_y(&self, line_idx: usize) -> f32 {
+        match self.lines.get(line_idx) {
+            Some(line) => line.top,
+            None => self.lines.last().map(|line| line.bottom()).unwrap_or(self.origin_y),
+        }
+    }
+
+    // Line containing the given y coordinate, clamped to the first/last line
+    pub fn y_to_line(&self, y: f32) -> usize {
+        let idx = self.lines.partition_point(|line| line.bottom() <= y);
+        idx.min(self.lines.len().saturating_sub(1))
+    }
+
+    pub fn line_height(&self, line_idx: usize) -> f32 {
+        self.lines.get(line_idx).map(|line| line.height()).unwrap_or(0.0)
+    }
+
+    pub fn max_line_width(&self) -> f32 {
+        self.max_width
+    }
+
+    pub fn total_height(&self) -> f32 {
+        self.lines.last().map(|line| line.bottom() - self.origin_y).unwrap_or(0.0)
+    }
+
+    // Rows at least partly inside the y range `top..bottom`
+    pub fn rows_between(&self, top: f32, bottom: f32) -> Range<usize> {
+        let start = self.lines.partition_point(|line| line.bottom() <= top);
+        let end = self.lines.partition_point(|line| line.top < bottom);
+        start..end.max(start)
+    }
+
+    // Line containing the given document char index
+    pub fn char_to_line(&self, char_idx: usize) -> usize {
+        let idx = self.lines.partition_point(|line| line.start_char <= char_idx);
+        idx.saturating_sub(1)
+    }
+
+    // Highlight rectangles (x, y, width, height) covering a char range. A line that mixes
+    // directions can need several rectangles, since its chars are not contiguous on screen.
+    // Lines whose line break is selected extend by a small cell to show it.
+    pub fn selection_rects(&self, range: Range<usize>) -> Vec<(f32, f32, f32, f32)> {
+        let mut rects = Vec::new();
+        if range.is_empty() || self.lines.is_empty() {
+            return rects;
+        }
+
+        let first_line = self.char_to_line(range.start);
+        let last_line = self.char_to_line(range.end);
+        for line in self.lines[first_line..=last_line].iter().filter(|line| !line.estimated) {
+            let start = range.start.max(line.start_char) - line.start_char;
+            let end = range.end.min(line.end_char()).saturating_sub(line.start_char).max(start);
+            let mut spans: Vec<(f32, f32)> = line.run.spans[start..end]
+                .iter()
+                .map(|&(leading, trailing)| (leading.min(trailing), leading.max(trailing)))
+                .collect();
+            if range.end > line.end_char() && !line.wraps {
+                spans.push((line.run.width, line.run.width + line.run.height * 0.5));
+            }
+            spans.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+            // Merge spans that touch into one rectangle
+            let mut merged: Vec<(f32, f32)> = Vec::new();
+            for (left, right) in spans {
+                match merged.last_mut() {
+                    Some(last) if left <= last.1 + 0.01 => last.1 = last.1.max(right),
+                    _ => merged.push((left, right)),
+                }
+            }
+            for (left, right) in merged.into_iter().filter(|(left, right)| right > left) {
+                rects.push((self.origin_x + left, line.top, right - left, line.height()));
+            }
+        }
+        rects
+    }
+
+    pub fn caret_position(&self, char_idx: usize) -> Option<CaretPosition> {
+        let line_idx = self.char_to_line(char_idx);
+        let line = self.lines.get(line_idx)?;
+        let offset = line.offset(char_idx);
+        let x = line.run.carets[offset];
+        let (cell_x, advance) = match line.run.spans.get(offset) {
+            Some(&(leading, trailing)) => (leading.min(trailing), (trailing - leading).abs()),
+            None => (x, line.run.height * 0.5),
+        };
+
+        Some(CaretPosition {
+            line: line_idx,
+            x: self.origin_x + x,
+            top: line.top,
+            height: line.height(),
+            cell_x: self.origin_x + cell_x,
+            advance,
+        })
+    }
+
+    // Char index one caret stop to the left or right of `char_idx` on screen. Past the
+    // ends of a line the caret continues on the neighbouring line.
+    pub fn move_visually(&self, char_idx: usize, right: bool) -> usize {
+        let line_idx = self.char_to_line(char_idx);
+        let Some(line) = self.lines.get(line_idx) else {
+            return char_idx;
+        };
+        let offset = line.offset(char_idx);
+        let x = line.run.carets[offset];
+
+        // Closest stop beyond x; stops at the same x prefer the logically nearest char
+        let next = line.run.carets.iter()
+            .enumerate()
+            .filter(|&(idx, &stop)| line.run.is_boundary(idx) && if right { stop > x } else { stop < x })
+            .min_by(|a, b| (a.1 - x).abs().total_cmp(&(b.1 - x).abs()).then(a.0.abs_diff(offset).cmp(&b.0.abs_diff(offset))));
+        if let Some((next, _)) = next {
+            return line.start_char + next;
+        }
+
+        let neighbour = if right { self.lines.get(line_idx + 1) } else { line_idx.checked_sub(1).and_then(|idx| self.lines.get(idx)) };
+        match neighbour {
+            Some(neighbour) => {
+                let stops = neighbour.run.carets.iter()
+                    .enumerate()
+                    .filter(|&(idx, _)| neighbour.run.is_boundary(idx));
+                let edge = if right {
+                    stops.min_by(|a, b| a.1.total_cmp(b.1).then(a.0.cmp(&b.0)))
+                } else {
+                    stops.max_by(|a, b| a.1.total_cmp(b.1).then(b.0.cmp(&a.0)))
+                };
+                neighbour.start_char + edge.map(|(idx, _)| idx).unwrap_or(0)
+            }
+            None => char_idx,
+        }
+    }
+
+    // Char index of the caret stop nearest to a document-space point. Clicking the leading half
+    // of a grapheme cluster puts the caret before it, the trailing half after it.
+    pub fn hit_test(&self, x: f32, y: f32) -> usize {
+        let Some(line) = self.lines.get(self.y_to_line(y)) else {
+            return 0;
+        };
+        let x = x - self.origin_x;
+        let distance = |&(leading, trailing): &(f32, f32)| {
+            let (left, right) = (leading.min(trailing), leading.max(trailing));
+            (left - x).max(x - right).max(0.0)
+        };
+        let nearest = line.run.boundaries.windows(2)
+            .map(|cluster| (cluster[0], cluster[1], line.run.spans[cluster[0]]))
+            .min_by(|a, b| distance(&a.2).total_cmp(&distance(&b.2)));
+
+        // The end of a wrapped row is the start of the next one, so stop before the last
+        // cluster to keep the caret on the clicked row
+        let last = if line.wraps { line.run.boundaries.len().saturating_sub(2) } else { line.run.boundaries.len() - 1 };
+        match nearest {
+            Some((_, end, (leading, trailing))) if (x - leading).abs() > (x - trailing).abs() => {
+                line.start_char + end.min(line.run.boundaries[last])
+            }
+            Some((start, _, _)) => line.start_char + start,
+            None => line.start_char,
+        }
+    }
+}
+
+// Vertical extent of a font above and below the baseline, plus its line gap
+fn vertical_metrics(font: &fontdue::Font, font_size: f32) -> (f32, f32, f32) {
+    match font.horizontal_line_metrics(font_size) {
+        Some(metrics) => (metrics.ascent, metrics.descent, metrics.line_gap),
+        None => (font_size, -font_size * 0.2, 0.0),
+    }
+}
+
+// Shape a single line (without its line break) and position its glyphs starting at x = 0.
+// All fonts share one baseline, placed low enough for the tallest font on the line.
+// Extra line spacing is split evenly above and below the text.
+fn layout_line(fonts: &FontChain, text: &str, font_size: f32, line_height: f32) -> LineRun {
+    let primary = fonts.primary();
+    let (mut ascent, mut descent, line_gap) = vertical_metrics(&primary.font, font_size);
+    
+    let runs = shape_line(fonts, text, font_size);
+    let mut glyphs = Vec::new();
+    let mut advances = Vec::new();
+    let mut carets = vec![0; text.chars().count() + 1];
+    let mut spans = vec![(0, 0); carets.len() - 1];
+    let mut pen = 0;
+    
+    for run in &runs {
+        let (run_start, run_first) = (pen, advances.len());
+        for shaped in &run.glyphs {
+            let advance = to_fixed(shaped.advance);
+            let Some(font) = fonts.get(shaped.key.font) else {
+                advances.push(advance);
+                pen += advance;
+                continue;
+            };
+            if font.id != primary.id {
+                let (font_ascent, font_descent, _) = vertical_metrics(&font.font, font_size);
+                ascent = ascent.max(font_ascent);
+                descent = descent.min(font_descent);
+            }
+            
+            let metrics = font.font.metrics_indexed(shaped.key.glyph, font_size);
+            if metrics.width > 0 && metrics.height > 0 {
+                glyphs.push(LayoutGlyph {
+                    key: shaped.key,
+                    char_offset: shaped.cluster,
+                    stream: advances.len(),
+                    // Relative to the baseline until the line's ascent is known
+                    y: -(metrics.height as f32 + metrics.ymin as f32) - shaped.offset_y,
+                    offset_x: shaped.offset_x,
+                    offset_y: -shaped.offset_y,
+                });
+            }
+            advances.push(advance);
+            pen += advance;
+        }
+        place_carets(run, &advances[run_first..], run_start, &mut carets, &mut spans);
+        
+        // The end of the line is the trailing edge of the last run
+        if run.chars.end == carets.len() - 1 {
+            carets[run.chars.end] = if run.rtl { run_start } else { pen };
+        }
+    }
+    
+    // A grapheme cluster is a single caret cell, however its chars were shaped
+    let boundaries = grapheme_boundaries(text);
+    for cluster in boundaries.windows(2).filter(|cluster| cluster[1] - cluster[0] > 1) {
+        let chars = &spans[cluster[0]..cluster[1]];
+        let left = chars.iter().map(|&(leading, trailing)| leading.min(trailing)).min().unwrap_or(0);
+        let right = chars.iter().map(|&(leading, trailing)| leading.max(trailing)).max().unwrap_or(0);
+        let merged = if chars[0].0 > chars[0].1 { (right, left) } else { (left, right) };
+        spans[cluster[0]..cluster[1]].fill(merged);
+        carets[cluster[0]] = merged.0;
+    }
+    
+    let natural_height = ascent - descent + line_gap;
+    let height = natural_height * line_height;
+    let ascent = ascent + (height - natural_height) * 0.5;
+    for glyph in &mut glyphs {
+        glyph.y += ascent;
+    }
+    
+    LineRun {
+        glyphs,
+        carets: carets.iter().map(|&caret| from_fixed(caret)).collect(),
+        spans: spans.iter().map(|&(leading, trailing)| (from_fixed(leading), from_fixed(trailing))).collect(),
+        boundaries,
+        advances,
+        width: from_fixed(pen),
+        ascent,
+        height,
+    }
+}
+
+// Set the caret stop before each char of a run, and the span each char covers. A
+// cluster's width is shared evenly between its chars, so carets can stop inside
+// ligatures; in right-to-left runs each char starts at its right edge.
+fn place_carets(run: &ShapedRun, advances: &[i32], run_start: i32, carets: &mut [i32], spans: &mut [(i32, i32)]) {
+    // (first char, left x, right x) of every cluster, in visual order
+    let mut clusters: Vec<(usize, i32, i32)> = Vec::new();
+    let mut pen = run_start;
+    for (glyph, &advance) in run.glyphs.iter().zip(advances) {
+        match clusters.last_mut() {
+            Some(cluster) if cluster.0 == glyph.cluster => cluster.2 = pen + advance,
+            _ => clusters.push((glyph.cluster, pen, pen + advance)),
+        }
+        pen += advance;
+    }
+    clusters.sort_by_key(|cluster| cluster.0);
+    
+    for (idx, &(first, left, right)) in clusters.iter().enumerate() {
+        let end = clusters.get(idx + 1).map(|next| next.0).unwrap_or(run.chars.end);
+        let count = (end - first) as i32;
+        for k in 0..count {
+            let (share, next_share) = ((right - left) * k / count, (right - left) * (k + 1) / count);
+            spans[first + k as usize] = if run.rtl {
+                (right - share, right - next_share)
+            } else {
+                (left + share, left + next_share)
+            };
+            carets[first + k as usize] = spans[first + k as usize].0;
+        }
+    }
+}
+
+// Char offsets of the extended grapheme cluster boundaries in a line, 0 and the length included
+fn grapheme_boundaries(text: &str) -> Vec<usize> {
+    let mut boundaries = vec![0];
+    let mut offset = 0;
+    for grapheme in text.graphemes(true) {
+        offset += grapheme.chars().count();
+        boundaries.push(offset);
+    }
+    boundaries
+}
+
+// Split a laid-out line into rows no wider than `max_width`, breaking where the Unicode
+// line breaking algorithm allows. Spaces at the end of a row may hang past the width.
+// Words wider than a whole row are broken between grapheme clusters. Returns the char
+// range of each row.
+fn wrap_line(text: &str, run: &LineRun, max_width: f32) -> Vec<Range<usize>> {
+    let chars: Vec<char> = text.chars().collect();
+    let char_starts: Vec<usize> = text.char_indices().map(|(offset, _)| offset).collect();
+    // Chars a row may start at
+    let opportunities: Vec<usize> = linebreaks(text)
+        .map(|(byte, _)| char_starts.partition_point(|&start| start < byte))
+        .filter(|&offset| offset > 0 && offset < chars.len())
+        .collect();
+    let char_width = |offset: usize| {
+        let (leading, trailing) = run.spans[offset];
+        (trailing - leading).abs()
+    };
+
+    let mut rows = Vec::new();
+    let mut start = 0;
+    let mut width = 0.0;
+    let mut offset = 0;
+    while offset < chars.len() {
+        width += char_width(offset);
+        if width <= max_width || chars[offset].is_whitespace() || offset == start {
+            offset += 1;
+            continue;
+        }
+
+        let last_break = opportunities[..opportunities.partition_point(|&o| o <= offset)].last().copied();
+        let end = match last_break {
+            Some(opportunity) if opportunity > start => opportunity,
+            // Nowhere to break: cut before the overflowing grapheme cluster, or after it
+            // when it is the first on the row
+            _ => {
+                let boundaries = &run.boundaries;
+                let next = boundaries.partition_point(|&b| b <= offset);
+                match boundaries[next - 1] {
+                    cut if cut > start => cut,
+                    _ => boundaries[next],
+                }
+            }
+        };
+        rows.push(start..end);
+        start = end;
+        width = (start..=offset).map(char_width).sum();
+        offset = offset.max(start - 1) + 1;
+    }
+    rows.push(start..chars.len());
+    rows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FONT_SIZE: f32 = 16.0;
+    const LINE_HEIGHT: f32 = 1.2;
+
+    fn fonts() -> FontChain {
+        crate::fonts::active_chain().expect("the built-in font loads")
+    }
+
+    // Where every row is, and whether it was shaped. Tops are rounded to a hundredth of a
+    // pixel, since rows moved by an edit may differ from freshly stacked ones in the last bit.
+    fn rows(layout: &TextLayout) -> Vec<(usize, usize, i32, bool, bool)> {
+        layout.lines()
+            .iter()
+            .map(|line| (line.start_char, line.len_chars, (line.top * 100.0).round() as i32, line.wraps, line.estimated))
+            .collect()
+    }
+
+    fn fresh(fonts: &FontChain, document: &Document, wrap_width: Option<f32>) -> TextLayout {
+        let mut layout = TextLayout::new(FONT_SIZE, LINE_HEIGHT);
+        layout.set_wrap_width(wrap_width);
+        layout.update(fonts, document, 0);
+        layout
+    }
+
+    #[test]
+    fn edits_match_a_fresh_layout() {
+        let fonts = fonts();
+        let wrap_width = Some(120.0);
+        let mut document = Document::from("short\r\na line long enough to wrap a few times over\n\nlast\r");
+        let mut layout = fresh(&fonts, &document, wrap_width);
+
+        // Typing inside a wrapped line, splitting and joining lines, turning a lone "\r"
+        // into "\r\n", and deleting across several lines
+        let edits: &[(Range<usize>, &str)] = &[
+            (9..9, "quite "),
+            (3..3, "\n"),
+            (2..5, ""),
+            (61..61, "\n"),
+            (1..30, "x"),
+            (0..0, "first line\n"),
+        ];
+        for (range, text) in edits {
+            document.replace(range.clone(), text);
+            layout.update(&fonts, &document, range.start);
+            assert_eq!(rows(&layout), rows(&fresh(&fonts, &document, wrap_width)), "after {:?} -> {:?}", range, text);
+        }
+    }
+
+    #[test]
+    fn scrolling_moves_no_rows() {
+        let fonts = fonts();
+        let text = "line of text\n".repeat(400);
+        let document = Document::from(text.as_str());
+        let mut viewport = Viewport::new(400.0, 300.0);
+        viewport.set_padding(8.0, 8.0);
+        viewport.set_scroll_limits(0.0, 1.0e6);
+
+        let mut layout = TextLayout::new(FONT_SIZE, LINE_HEIGHT);
+        layout.set_viewport(&viewport);
+        layout.update(&fonts, &document, 0);
+        let before = rows(&layout);
+        let visible = viewport.visible_rows(&layout);
+        assert!(before[visible.clone()].iter().all(|row| !row.4));
+        assert!(before.last().is_some_and(|row| row.4), "rows far below the view are not shaped");
+
+        // Every line has the same height, so shaping more of them moves nothing
+        viewport.scroll_by(0.0, 2000.0);
+        layout.set_viewport(&viewport);
+        layout.update(&fonts, &document, 0);
+        let after = rows(&layout);
+        assert_eq!(after.len(), before.len());
+        for (before, after) in before.iter().zip(&after) {
+            assert_eq!((before.0, before.2), (after.0, after.2));
+        }
+        assert!(after[visible].iter().all(|row| !row.4));
+        assert!(after[viewport.visible_rows(&layout)].iter().all(|row| !row.4));
+        assert_eq!(layout.take_anchor_shift(), 0.0);
+    }
+
+    // Where `steps` presses of an arrow key take the caret, starting at `from`
+    fn walk(layout: &TextLayout, from: usize, right: bool, steps: usize) -> Vec<usize> {
+        let mut caret = from;
+        (0..steps)
+            .map(|_| {
+                caret = layout.move_visually(caret, right);
+                caret
+            })
+            .collect()
+    }
+
+    #[test]
+    fn arrows_move_visually_across_direction_runs() {
+        let fonts = fonts();
+        // A Hebrew word inside English reads right to left
+        let layout = fresh(&fonts, &Document::from("abc \u{5d0}\u{5d1}\u{5d2} def"), None);
+        assert_eq!(walk(&layout, 0, true, 11), [1, 2, 3, 6, 5, 4, 8, 9, 10, 11, 11]);
+        assert_eq!(walk(&layout, 11, false, 11), [10, 9, 8, 7, 5, 6, 3, 2, 1, 0, 0]);
+
+        // A number inside Hebrew reads left to right within a right-to-left line
+        let layout = fresh(&fonts, &Document::from("\u{5d0}\u{5d1} 12 \u{5d2}"), None);
+        assert_eq!(walk(&layout, 0, false, 7), [1, 2, 4, 3, 6, 7, 7]);
+        assert_eq!(walk(&layout, 7, true, 7), [6, 5, 4, 2, 1, 0, 0]);
+    }
+
+    #[test]
+    fn carets_at_direction_boundaries() {
+        let fonts = fonts();
+        let layout = fresh(&fonts, &Document::from("abc\u{5d0}\u{5d1}\u{5d2}"), None);
+        let run = &layout.lines()[0].run;
+        let carets = &run.carets;
+        // Left to right through "abc", then right to left through the Hebrew word
+        assert!(carets[0] < carets[1] && carets[1] < carets[2]);
+        assert!(carets[6] < carets[5] && carets[5] < carets[4] && carets[4] < carets[3]);
+        // Right-to-left chars lead with their right edge
+        assert!(run.spans[3].0 > run.spans[3].1);
+
+        // The caret before the first Hebrew char stands where that char begins: at the
+        // right end of the line
+        assert_eq!(carets[3], run.width);
+        assert_eq!(layout.caret_position(3).map(|caret| caret.x), Some(run.width));
+        // The line end follows the last Hebrew char, which is drawn right after "c"
+        assert_eq!(carets[6], run.spans[2].1);
+        assert_eq!(carets[6], run.spans[5].1);
+        // Both edges of the Hebrew word can be reached from "abc"
+        assert_eq!(walk(&layout, 2, true, 5), [6, 5, 4, 3, 3]);
+        assert_eq!(walk(&layout, 6, false, 3), [2, 1, 0]);
+    }
+
+    // Rows of one line wrapped at a width picked from its caret positions
+    fn wrap(fonts: &FontChain, text: &str, max_width: impl Fn(&[f32]) -> f32) -> Vec<Range<usize>> {
+        let run = layout_line(fonts, text, FONT_SIZE, LINE_HEIGHT);
+        wrap_line(text, &run, max_width(&run.carets))
+    }
+
+    #[test]
+    fn rows_break_after_spaces_which_hang() {
+        let fonts = fonts();
+        // "world" does not fit after "hello ", so the row breaks before it
+        assert_eq!(wrap(&fonts, "hello world again", |carets| carets[8]), [0..6, 6..12, 12..17]);
+        // Exactly as wide as "hello world": the space after it hangs and "again" moves on
+        assert_eq!(wrap(&fonts, "hello world again", |carets| carets[11]), [0..12, 12..17]);
+        // Any number of spaces stays on the row it follows, however wide they are
+        assert_eq!(wrap(&fonts, "world      hello", |carets| carets[5]), [0..11, 11..16]);
+        // A line that fits is one row
+        assert_eq!(wrap(&fonts, "hello world", |carets| carets[11]), vec![Range { start: 0, end: 11 }]);
+    }
+
+    #[test]
+    fn overlong_words_start_a_row_and_break_between_clusters() {
+        let fonts = fonts();
+        // The long word leaves the row of "a", then is cut wherever the row is full
+        let text = "a abcdefghijkl";
+        let run = layout_line(&fonts, text, FONT_SIZE, LINE_HEIGHT);
+        let max_width = run.carets[6] - run.carets[2];
+        let rows = wrap_line(text, &run, max_width);
+        assert_eq!(rows[0], 0..2);
+        assert_eq!(rows.last().map(|row| row.end), Some(14));
+        assert!(rows.len() > 3);
+        for row in &rows[1..] {
+            assert!(run.carets[row.end] - run.carets[row.start] <= max_width, "{:?} overflows", row);
+        }
+        assert!(rows.windows(2).all(|pair| pair[0].end == pair[1].start));
+
+        // Narrower than any char: one grapheme cluster per row, an accent kept with its letter
+        assert_eq!(wrap(&fonts, "xe\u{301}y", |_| 1.0), [0..1, 1..3, 3..4]);
+    }
+
+    #[test]
+    fn changing_the_wrap_width_rewraps() {
+        let fonts = fonts();
+        let document = Document::from("a line long enough to wrap a few times over\nshort\n");
+        let mut layout = fresh(&fonts, &document, Some(200.0));
+        let wide = rows(&layout);
+
+        layout.set_wrap_width(Some(80.0));
+        layout.update(&fonts, &document, 0);
+        let narrow = rows(&layout);
+        assert_eq!(narrow, rows(&fresh(&fonts, &document, Some(80.0))));
+        assert!(narrow.len() > wide.len());
+
+        layout.set_wrap_width(Some(200.0));
+        layout.update(&fonts, &document, 0);
+        assert_eq!(rows(&layout), wide);
+
+        // Without a width every document line is one row
+        layout.set_wrap_width(None);
+        layout.update(&fonts, &document, 0);
+        assert_eq!(rows(&layout), rows(&fresh(&fonts, &document, None)));
+        assert_eq!(layout.lines().len(), 3);
+    }
+}
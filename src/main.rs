@@ -3,6 +3,13 @@ use wasm_bindgen::prelude::*;
 use web_sys::{HtmlCanvasElement, HtmlElement};
 
 mod gpu;
+mod document;
+mod fonts;
+mod layout;
+mod shaping;
+mod selection;
+mod viewport;
+mod history;
 mod text_input;
 mod input_buffer;
 mod fast_text_input;
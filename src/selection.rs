@@ -0,0 +1,60 @@
+// Selection Model
+// Anchor/head selection over document char indices
+// The anchor stays put while extending; the head is where the caret is drawn.
+// An empty selection (anchor == head) is a plain cursor.
+
+use std::ops::Range;
+
+// Unit a mouse selection grows by: chars after a single click, words after a double
+// click, lines after a triple click
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Granularity {
+    Char,
+    Word,
+    Line,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Selection {
+    pub anchor: usize,
+    pub head: usize,
+}
+
+impl Selection {
+    pub fn new(anchor: usize, head: usize) -> Self {
+        Self { anchor, head }
+    }
+
+    pub fn caret(pos: usize) -> Self {
+        Self { anchor: pos, head: pos }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.anchor == self.head
+    }
+
+    pub fn start(&self) -> usize {
+        self.anchor.min(self.head)
+    }
+
+    pub fn end(&self) -> usize {
+        self.anchor.max(self.head)
+    }
+
+    pub fn range(&self) -> Range<usize> {
+        self.start()..self.end()
+    }
+
+    // Move the head, keeping the anchor
+    pub fn extend_to(self, head: usize) -> Self {
+        Self { anchor: self.anchor, head }
+    }
+
+    // Keep both ends inside a document of `len` chars
+    pub fn clamp(self, len: usize) -> Self {
+        Self {
+            anchor: self.anchor.min(len),
+            head: self.head.min(len),
+        }
+    }
+}
@@ -0,0 +1,212 @@
+// Text Shaping
+// Turns a line of text into positioned glyphs with OpenType shaping
+// Responsibilities:
+// - Order a line into directional runs with the Unicode Bidirectional Algorithm
+// - Split those into runs that share one font and one script
+// - Shape each run with rustybuzz: ligatures, kerning, mark positioning, joining scripts
+// - Report glyph ids with advances and offsets in pixels, and the chars each glyph came from
+
+use std::ops::Range;
+use unicode_bidi::BidiInfo;
+use unicode_script::{Script, UnicodeScript};
+use crate::fonts::{FontChain, GlyphKey, LoadedFont};
+
+// Tab stops are this many spaces apart
+const TAB_WIDTH: f32 = 4.0;
+
+#[derive(Debug, Clone, Copy)]
+pub struct ShapedGlyph {
+    pub key: GlyphKey,
+    // Line-relative index of the first char of the cluster this glyph belongs to
+    pub cluster: usize,
+    pub advance: f32,
+    // Shift from the pen position, x right and y up
+    pub offset_x: f32,
+    pub offset_y: f32,
+}
+
+// The glyphs of a run of chars, in visual (left to right) order
+#[derive(Debug, Clone)]
+pub struct ShapedRun {
+    pub chars: Range<usize>,
+    pub rtl: bool,
+    pub glyphs: Vec<ShapedGlyph>,
+}
+
+// Shape a single line (without its line break). Runs come back in visual order,
+// left to right, as the Unicode Bidirectional Algorithm places them.
+pub fn shape_line(fonts: &FontChain, text: &str, font_size: f32) -> Vec<ShapedRun> {
+    let chars: Vec<char> = text.chars().collect();
+    // Byte offset of each char, to map the bidi byte ranges back to char indices
+    let char_starts: Vec<usize> = text.char_indices().map(|(offset, _)| offset).collect();
+    let to_char = |byte: usize| char_starts.partition_point(|&start| start < byte);
+
+    let bidi = BidiInfo::new(text, None);
+    let mut runs = Vec::new();
+    for paragraph in &bidi.paragraphs {
+        let (levels, visual_runs) = bidi.visual_runs(paragraph, paragraph.range.clone());
+        for bytes in visual_runs {
+            let rtl = levels[bytes.start].is_rtl();
+            let mut pieces = split_runs(fonts, &chars, to_char(bytes.start)..to_char(bytes.end), rtl, font_size);
+            // Pieces of a right-to-left run read from the right
+            if rtl {
+                pieces.reverse();
+            }
+            runs.extend(pieces);
+        }
+    }
+    expand_tabs(fonts, &chars, &mut runs, font_size);
+    runs
+}
+
+// Widen each tab to reach the next tab stop, counted from the start of the line
+fn expand_tabs(fonts: &FontChain, chars: &[char], runs: &mut [ShapedRun], font_size: f32) {
+    let stop = tab_stop(fonts, font_size);
+    if stop <= 0.0 {
+        return;
+    }
+    let mut pen = 0.0;
+    for glyph in runs.iter_mut().flat_map(|run| run.glyphs.iter_mut()) {
+        if chars[glyph.cluster] == '\t' {
+            glyph.advance = stop - pen % stop;
+        }
+        pen += glyph.advance;
+    }
+}
+
+// Distance between tab stops: TAB_WIDTH spaces of the primary font
+fn tab_stop(fonts: &FontChain, font_size: f32) -> f32 {
+    let font = fonts.primary();
+    let glyph = font.font.lookup_glyph_index(' ');
+    font.font.metrics_indexed(glyph, font_size).advance_width * TAB_WIDTH
+}
+
+// Split one directional run into pieces that share a font and a script, and shape each.
+// Pieces come back in logical order.
+fn split_runs(fonts: &FontChain, chars: &[char], range: Range<usize>, rtl: bool, font_size: f32) -> Vec<ShapedRun> {
+    let mut runs = Vec::new();
+    let mut start = range.start;
+
+    while start < range.end {
+        if chars[start] == '\t' {
+            runs.push(tab_run(fonts, start, rtl, font_size));
+            start += 1;
+            continue;
+        }
+
+        let (font, _) = fonts.resolve(chars[start]);
+        let mut script = specific_script(chars[start]);
+        let mut end = start + 1;
+        while end < range.end && chars[end] != '\t' {
+            let ch = chars[end];
+            let ch_script = specific_script(ch);
+            if let (Some(run_script), Some(ch_script)) = (script, ch_script)
+                && run_script != ch_script
+            {
+                break;
+            }
+            // Spaces, punctuation and marks stay in the run as long as its font covers them
+            if font.font.lookup_glyph_index(ch) == 0 && fonts.resolve(ch).0.id != font.id {
+                break;
+            }
+            script = script.or(ch_script);
+            end += 1;
+        }
+
+        runs.push(shape_run(font, chars, start..end, rtl, font_size));
+        start = end;
+    }
+    runs
+}
+
+// Script of a char, or None for chars (spaces, digits, marks) that take on their neighbours'
+fn specific_script(ch: char) -> Option<Script> {
+    match ch.script() {
+        Script::Common | Script::Inherited | Script::Unknown => None,
+        script => Some(script),
+    }
+}
+
+fn shape_run(font: &LoadedFont, chars: &[char], range: Range<usize>, rtl: bool, font_size: f32) -> ShapedRun {
+    let face = font.face();
+
+    // Clusters are numbered by line-relative char index rather than by byte
+    let mut buffer = rustybuzz::UnicodeBuffer::new();
+    for idx in range.clone() {
+        buffer.add(chars[idx], idx as u32);
+    }
+    // The bidi level decides the direction; the shaper mirrors brackets in right-to-left runs
+    buffer.set_direction(if rtl { rustybuzz::Direction::RightToLeft } else { rustybuzz::Direction::LeftToRight });
+    buffer.guess_segment_properties();
+
+    let shaped = rustybuzz::shape(&face, &[], buffer);
+    let scale = font_size / face.units_per_em() as f32;
+    let glyphs = shaped
+        .glyph_infos()
+        .iter()
+        .zip(shaped.glyph_positions())
+        .map(|(info, position)| ShapedGlyph {
+            key: GlyphKey { font: font.id, glyph: info.glyph_id as u16 },
+            cluster: info.cluster as usize,
+            advance: position.x_advance as f32 * scale,
+            offset_x: position.x_offset as f32 * scale,
+            offset_y: position.y_offset as f32 * scale,
+        })
+        .collect();
+
+    ShapedRun { chars: range, rtl, glyphs }
+}
+
+// Tabs are drawn as a wide space of the primary font, a full tab stop wide until
+// `expand_tabs` knows where on the line they start
+fn tab_run(fonts: &FontChain, idx: usize, rtl: bool, font_size: f32) -> ShapedRun {
+    let font = fonts.primary();
+    let glyph = font.font.lookup_glyph_index(' ');
+    ShapedRun {
+        chars: idx..idx + 1,
+        rtl,
+        glyphs: vec![ShapedGlyph {
+            key: GlyphKey { font: font.id, glyph },
+            cluster: idx,
+            advance: tab_stop(fonts, font_size),
+            offset_x: 0.0,
+            offset_y: 0.0,
+        }],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FONT_SIZE: f32 = 16.0;
+
+    // Where each char's glyphs start on the line
+    fn pen_positions(text: &str) -> Vec<f32> {
+        let fonts = crate::fonts::active_chain().unwrap();
+        let mut starts = vec![f32::NAN; text.chars().count()];
+        let mut pen = 0.0;
+        for glyph in shape_line(&fonts, text, FONT_SIZE).iter().flat_map(|run| &run.glyphs) {
+            if starts[glyph.cluster].is_nan() {
+                starts[glyph.cluster] = pen;
+            }
+            pen += glyph.advance;
+        }
+        starts
+    }
+
+    #[test]
+    fn tabs_advance_to_the_next_stop() {
+        let stop = tab_stop(&crate::fonts::active_chain().unwrap(), FONT_SIZE);
+        let close = |a: f32, b: f32| (a - b).abs() < 1e-3;
+        // Text after a tab starts on a stop however much preceded the tab
+        for text in ["\tx", "a\tx", "abc\tx", "\t\tx", "a\tb\tx"] {
+            let x = *pen_positions(text).last().unwrap();
+            assert!(close(x % stop, 0.0) || close(x % stop, stop), "{:?} puts x at {}", text, x);
+        }
+        assert!(close(pen_positions("a\tx")[2], stop));
+        assert!(close(pen_positions("a\tb\tx")[4], stop * 2.0));
+        // A tab starting on a stop is a full stop wide
+        assert!(close(pen_positions("\t\tx")[2], stop * 2.0));
+    }
+}
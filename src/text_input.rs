@@ -1,7 +1,7 @@
 use leptos::prelude::*;
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::closure::Closure;
-use web_sys::{HtmlCanvasElement, HtmlElement, KeyboardEvent};
+use web_sys::{HtmlCanvasElement, KeyboardEvent};
 use std::cell::RefCell;
 use std::rc::Rc;
 
@@ -119,6 +119,11 @@ thread_local! {
     static WEBGPU_RESOURCES: RefCell<Option<Rc<RefCell<WebGPUResources>>>> = RefCell::new(None);
 }
 
+// The resources if they have been initialized, for callers that cannot wait
+pub fn webgpu_resources() -> Option<Rc<RefCell<WebGPUResources>>> {
+    WEBGPU_RESOURCES.with(|res| res.borrow().clone())
+}
+
 pub async fn get_or_init_webgpu_resources() -> Result<Rc<RefCell<WebGPUResources>>, JsValue> {
     // Check if already initialized
     if let Some(existing) = WEBGPU_RESOURCES.with(|res| res.borrow().clone()) {
@@ -143,15 +148,10 @@ pub async fn get_or_init_webgpu_resources() -> Result<Rc<RefCell<WebGPUResources
             .dyn_into::<HtmlCanvasElement>()
             .map_err(|_| JsValue::from_str("Element is not a canvas"))?;
 
-        let css_width = 800.0;
-        let css_height = 600.0;
-        let canvas_elem = canvas.unchecked_ref::<HtmlElement>();
-        canvas_elem.style().set_property("width", &format!("{}px", css_width))?;
-        canvas_elem.style().set_property("height", &format!("{}px", css_height))?;
-
-        let dpr = window.device_pixel_ratio();
-        canvas.set_width((css_width * dpr) as u32);
-        canvas.set_height((css_height * dpr) as u32);
+        // The backing store follows the canvas's CSS box, scaled by devicePixelRatio
+        let (width, height) = crate::fast_text_input::canvas_pixel_size(&canvas);
+        canvas.set_width(width);
+        canvas.set_height(height);
 
         let context = crate::gpu::context::GpuContext::new(&canvas).await?;
         
@@ -161,6 +161,7 @@ pub async fn get_or_init_webgpu_resources() -> Result<Rc<RefCell<WebGPUResources
             10000, // Support up to 10k characters
         )?;
         fast_text_renderer.initialize()?;
+        crate::fast_text_input::fit_viewport(&mut fast_text_renderer, &canvas)?;
         
         // Keep old renderer as fallback
         let mut text_renderer = crate::gpu::text::TextRenderer::new()?;
@@ -190,7 +191,7 @@ fn render_text_with_resources(text: &str, resources: &Rc<RefCell<WebGPUResources
     {
         let mut res = resources.borrow_mut();
         if let Some(fast_renderer) = res.fast_text_renderer.as_mut() {
-            fast_renderer.update_text(text)?;
+            fast_renderer.update_text(&crate::document::Document::from(text).snapshot())?;
         }
     }
 
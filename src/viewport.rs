@@ -0,0 +1,262 @@
+// Viewport
+// The window of laid-out text shown on the canvas
+// Responsibilities:
+// - Describe the canvas area the text is drawn into and where text starts inside it
+// - Convert between canvas pixels and the document space the text is laid out in
+// - Track the scroll offset and keep it within the text
+// - Give the band of rows worth laying out: the visible ones plus an overscan margin
+// - Scroll the least distance that brings a rectangle (the caret) into view
+// - Smooth wheel and trackpad scrolling with momentum
+
+use std::ops::Range;
+use crate::layout::TextLayout;
+
+// Rows this far above and below the canvas are laid out ahead of time, so short scrolls
+// find them ready
+const DEFAULT_OVERSCAN: f32 = 200.0;
+
+// Wheel scrolling glides instead of jumping: each wheel delta becomes a velocity that
+// decays exponentially with this time constant, covering exactly the wheel distance
+const MOMENTUM_TIME_MS: f32 = 120.0;
+
+// Below this speed (pixels per millisecond) the glide ends
+const MIN_VELOCITY: f32 = 0.01;
+
+#[derive(Debug, Clone)]
+pub struct Viewport {
+    // Size of the area the text is drawn into, in canvas pixels
+    pub width: f32,
+    pub height: f32,
+    // Distance from the top-left of the area to the text when scrolled to the start
+    pub padding_x: f32,
+    pub padding_y: f32,
+    pub overscan: f32,
+    scroll_x: f32,
+    scroll_y: f32,
+    // Largest scroll offsets the text allows
+    max_scroll_x: f32,
+    max_scroll_y: f32,
+    // Momentum scrolling speed in pixels per millisecond, and when it was last applied
+    velocity: (f32, f32),
+    last_step_ms: Option<f64>,
+}
+
+impl Viewport {
+    pub fn new(width: f32, height: f32) -> Self {
+        Self {
+            width,
+            height,
+            padding_x: 0.0,
+            padding_y: 0.0,
+            overscan: DEFAULT_OVERSCAN,
+            scroll_x: 0.0,
+            scroll_y: 0.0,
+            max_scroll_x: 0.0,
+            max_scroll_y: 0.0,
+            velocity: (0.0, 0.0),
+            last_step_ms: None,
+        }
+    }
+
+    pub fn resize(&mut self, width: f32, height: f32) {
+        self.width = width;
+        self.height = height;
+    }
+
+    pub fn set_padding(&mut self, x: f32, y: f32) {
+        self.padding_x = x;
+        self.padding_y = y;
+    }
+
+    pub fn scroll(&self) -> (f32, f32) {
+        (self.scroll_x, self.scroll_y)
+    }
+
+    // Canvas position of a point in document space, where the text lies unscrolled
+    pub fn to_canvas(&self, x: f32, y: f32) -> (f32, f32) {
+        (x - self.scroll_x, y - self.scroll_y)
+    }
+
+    // Document-space position of a point on the canvas
+    pub fn to_document(&self, x: f32, y: f32) -> (f32, f32) {
+        (x + self.scroll_x, y + self.scroll_y)
+    }
+
+    // Room between the text's left edge and the right edge of the area
+    pub fn text_width(&self) -> f32 {
+        self.width - self.padding_x
+    }
+
+    // Document-space y range whose rows are laid out: the area plus the overscan on both sides
+    pub fn layout_band(&self) -> Range<f32> {
+        self.scroll_y - self.overscan..self.scroll_y + self.height + self.overscan
+    }
+
+    // Rows of `layout` that are at least partly inside the area
+    pub fn visible_rows(&self, layout: &TextLayout) -> Range<usize> {
+        layout.rows_between(self.scroll_y, self.scroll_y + self.height)
+    }
+
+    // Largest offsets the text can be scrolled by; the current offset is pulled back inside
+    pub fn set_scroll_limits(&mut self, max_x: f32, max_y: f32) {
+        self.max_scroll_x = max_x.max(0.0);
+        self.max_scroll_y = max_y.max(0.0);
+        self.scroll_to(self.scroll_x, self.scroll_y);
+    }
+
+    pub fn scroll_to(&mut self, x: f32, y: f32) {
+        self.scroll_x = x.clamp(0.0, self.max_scroll_x);
+        self.scroll_y = y.clamp(0.0, self.max_scroll_y);
+    }
+
+    pub fn scroll_by(&mut self, dx: f32, dy: f32) {
+        self.scroll_to(self.scroll_x + dx, self.scroll_y + dy);
+    }
+
+    // Scroll just far enough for a rectangle in canvas pixels to be inside the area.
+    // A rectangle larger than the area keeps its top-left edge in view.
+    pub fn scroll_into_view(&mut self, left: f32, top: f32, right: f32, bottom: f32) {
+        let dx = if right > self.width { right - self.width } else { 0.0 };
+        let dx = if left - dx < 0.0 { left } else { dx };
+        let dy = if bottom > self.height { bottom - self.height } else { 0.0 };
+        let dy = if top - dy < 0.0 { top } else { dy };
+        if dx != 0.0 || dy != 0.0 {
+            self.velocity = (0.0, 0.0);
+            self.scroll_by(dx, dy);
+        }
+    }
+
+    // Add a wheel or trackpad delta to the glide. Deltas arriving while the text still
+    // moves add up, so a fast flick travels further.
+    pub fn fling(&mut self, dx: f32, dy: f32, now_ms: f64) {
+        if !self.is_gliding() {
+            self.last_step_ms = Some(now_ms);
+        }
+        self.velocity.0 += dx / MOMENTUM_TIME_MS;
+        self.velocity.1 += dy / MOMENTUM_TIME_MS;
+    }
+
+    pub fn is_gliding(&self) -> bool {
+        self.velocity != (0.0, 0.0)
+    }
+
+    // Advance the glide to `now_ms`. Returns whether the text is still moving. Running
+    // into the end of the text stops the glide along that axis.
+    pub fn step(&mut self, now_ms: f64) -> bool {
+        if !self.is_gliding() {
+            return false;
+        }
+        let elapsed = self.last_step_ms.map(|last| (now_ms - last).max(0.0) as f32).unwrap_or(0.0);
+        self.last_step_ms = Some(now_ms);
+
+        // Distance covered while the velocity decays; once the glide is slow enough the
+        // rest of it is travelled at once
+        let decay = (-elapsed / MOMENTUM_TIME_MS).exp();
+        let (mut vx, mut vy) = (self.velocity.0 * decay, self.velocity.1 * decay);
+        let mut dx = (self.velocity.0 - vx) * MOMENTUM_TIME_MS;
+        let mut dy = (self.velocity.1 - vy) * MOMENTUM_TIME_MS;
+        if vx.abs() < MIN_VELOCITY && vy.abs() < MIN_VELOCITY {
+            dx += vx * MOMENTUM_TIME_MS;
+            dy += vy * MOMENTUM_TIME_MS;
+            (vx, vy) = (0.0, 0.0);
+        }
+
+        let (before_x, before_y) = self.scroll();
+        self.scroll_by(dx, dy);
+        if self.scroll_x == before_x && dx != 0.0 {
+            vx = 0.0;
+        }
+        if self.scroll_y == before_y && dy != 0.0 {
+            vy = 0.0;
+        }
+        self.velocity = (vx, vy);
+        self.is_gliding()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::Document;
+
+    #[test]
+    fn scrolling_stops_at_the_ends_of_the_text() {
+        let mut viewport = Viewport::new(400.0, 300.0);
+        viewport.set_scroll_limits(50.0, 1000.0);
+        viewport.scroll_by(-10.0, -10.0);
+        assert_eq!(viewport.scroll(), (0.0, 0.0));
+        viewport.scroll_to(100.0, 5000.0);
+        assert_eq!(viewport.scroll(), (50.0, 1000.0));
+
+        // Text getting shorter pulls the offset back inside it
+        viewport.set_scroll_limits(20.0, 400.0);
+        assert_eq!(viewport.scroll(), (20.0, 400.0));
+        viewport.set_scroll_limits(-5.0, -5.0);
+        assert_eq!(viewport.scroll(), (0.0, 0.0));
+    }
+
+    // Step a glide at 60 frames a second until it ends, returning the number of frames
+    fn glide(viewport: &mut Viewport, start_ms: f64) -> usize {
+        let mut frames = 0;
+        while viewport.step(start_ms + 16.0 * (frames + 1) as f64) {
+            frames += 1;
+            assert!(frames < 1000, "the glide never stops");
+        }
+        frames
+    }
+
+    #[test]
+    fn glides_decay_to_rest_after_the_wheel_distance() {
+        let mut viewport = Viewport::new(400.0, 300.0);
+        viewport.set_scroll_limits(0.0, 10_000.0);
+        viewport.fling(0.0, 300.0, 0.0);
+        assert!(viewport.is_gliding());
+        let frames = glide(&mut viewport, 0.0);
+        assert!(frames > 5, "the text glides rather than jumps");
+        assert!(!viewport.is_gliding());
+        assert!((viewport.scroll().1 - 300.0).abs() < 0.01, "glided to {}", viewport.scroll().1);
+        assert!(!viewport.step(10_000.0));
+
+        // Deltas arriving mid-glide add up
+        viewport.fling(0.0, 100.0, 20_000.0);
+        viewport.step(20_016.0);
+        viewport.fling(0.0, 100.0, 20_016.0);
+        glide(&mut viewport, 20_016.0);
+        assert!((viewport.scroll().1 - 500.0).abs() < 0.01);
+
+        // Running into the end of the text stops the glide on the next frame, well before
+        // the momentum would have run out
+        viewport.scroll_to(0.0, 9_950.0);
+        viewport.fling(0.0, 300.0, 30_000.0);
+        assert!(glide(&mut viewport, 30_000.0) < frames / 2);
+        assert_eq!(viewport.scroll().1, 10_000.0);
+        assert!(!viewport.is_gliding());
+    }
+
+    #[test]
+    fn the_layout_band_covers_the_area_after_a_resize() {
+        let fonts = crate::fonts::active_chain().expect("the built-in font loads");
+        let text = "line of text\n".repeat(400);
+        let document = Document::from(text.as_str());
+        let mut viewport = Viewport::new(400.0, 300.0);
+        viewport.set_scroll_limits(0.0, 1.0e6);
+        viewport.scroll_to(0.0, 1000.0);
+
+        let mut layout = TextLayout::new(16.0, 1.2);
+        layout.set_viewport(&viewport);
+        layout.update(&fonts, &document, 0);
+
+        // A taller area reaches rows the old band left unshaped
+        viewport.resize(400.0, 2000.0);
+        let band = viewport.layout_band();
+        assert_eq!(band, 1000.0 - DEFAULT_OVERSCAN..3000.0 + DEFAULT_OVERSCAN);
+        let visible = viewport.visible_rows(&layout);
+        assert!(layout.lines()[visible].iter().any(|row| row.estimated));
+
+        layout.set_viewport(&viewport);
+        layout.update(&fonts, &document, 0);
+        let visible = viewport.visible_rows(&layout);
+        assert!(layout.lines()[visible.clone()].iter().all(|row| !row.estimated));
+        assert!(layout.lines()[visible.end - 1].bottom() >= 3000.0);
+    }
+}